@@ -0,0 +1,172 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+use suppaftp::types::{FtpError, FtpResult};
+use suppaftp_client::{
+    FtpClient, FtpClientSettings,
+    format::{FtpFormatOptions, format_list},
+    mirror::FtpMirrorOptions,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const USAGE: &str = "\
+usage: suppaftp-cli [-u USER] [-p PASSWORD] [-d DIR] HOST[:PORT] COMMAND [ARGS...]
+
+commands:
+    ls [-h] [PATH]              list a remote directory
+    get REMOTE [LOCAL]          download a file
+    put LOCAL [REMOTE]          upload a file
+    mirror get REMOTE LOCAL     download a directory tree
+    mirror put LOCAL REMOTE     upload a directory tree
+    rm PATH                     delete a remote file
+
+The password is read from SUPPAFTP_PASSWORD or prompted for unless given with -p.";
+
+#[derive(Debug)]
+struct CliSettings {
+    addr:       String,
+    login:      String,
+    password:   String,
+    remote_dir: Option<String>,
+}
+
+impl FtpClientSettings for CliSettings {
+    fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    fn login(&self) -> &str {
+        &self.login
+    }
+
+    fn password(&self) -> &str {
+        &self.password
+    }
+
+    fn remote_dir(&self) -> Option<&str> {
+        self.remote_dir.as_deref()
+    }
+
+    // upgrade where the server offers AUTH TLS, as the CLI has no TLS switch
+    #[cfg(feature = "sync-secure")]
+    fn tls_policy(&self) -> suppaftp_client::TlsPolicy {
+        suppaftp_client::TlsPolicy::Preferred
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("suppaftp-cli: {}", message);
+    process::exit(2);
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn file_name(path: &str) -> &str {
+    path.trim_end_matches('/').rsplit('/').next().unwrap_or(path)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Returns whether every file was transferred.
+fn run(client: &mut FtpClient, command: &str, args: &[String]) -> FtpResult<bool> {
+    match (command, args) {
+        ("ls", args) => {
+            let human_readable = args.iter().any(|arg| arg == "-h");
+            let path = args.iter().find(|arg| *arg != "-h");
+            let list = match path {
+                Some(path) => client.list_path(path)?,
+                None => client.list()?,
+            };
+            println!("{}", format_list(&list, &FtpFormatOptions { human_readable }));
+        },
+        ("get", [remote]) => {
+            client.download_file(remote, file_name(remote))?;
+        },
+        ("get", [remote, local]) => {
+            client.download_file(remote, local)?;
+        },
+        ("put", [local]) => {
+            let name = Path::new(local).file_name().and_then(|name| name.to_str()).unwrap_or_else(|| usage());
+            client.upload_file(local, name)?;
+        },
+        ("put", [local, remote]) => {
+            client.upload_file(local, remote)?;
+        },
+        ("mirror", [direction, from, to]) => {
+            let report = match direction.as_str() {
+                "get" => client.download_dir(from, PathBuf::from(to), &FtpMirrorOptions::default())?,
+                "put" => client.upload_dir(PathBuf::from(from), to, &FtpMirrorOptions::default())?,
+                _ => usage(),
+            };
+            for error in &report.errors {
+                eprintln!("{}: {}", error.path, error.message);
+            }
+            println!(
+                "{} downloaded, {} uploaded, {} skipped, {} failed, {} bytes in {:.1}s",
+                report.downloaded, report.uploaded, report.skipped, report.errors.len(), report.bytes, report.duration.as_secs_f64(),
+            );
+            return Ok(report.is_success());
+        },
+        ("rm", [path]) => {
+            client.rm(path)?;
+        },
+        _ => usage(),
+    }
+
+    Ok(true)
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut login = None;
+    let mut password = None;
+    let mut remote_dir = None;
+
+    let host = loop {
+        match args.next() {
+            Some(arg) if arg == "-u" => login = Some(args.next().unwrap_or_else(|| usage())),
+            Some(arg) if arg == "-p" => password = Some(args.next().unwrap_or_else(|| usage())),
+            Some(arg) if arg == "-d" => remote_dir = Some(args.next().unwrap_or_else(|| usage())),
+            Some(arg) if arg == "--help" => usage(),
+            Some(arg) => break arg,
+            None => usage(),
+        }
+    };
+    let command = args.next().unwrap_or_else(|| usage());
+    let args: Vec<String> = args.collect();
+
+    let addr = if host.contains(':') { host } else { format!("{}:21", host) };
+    let login = login.unwrap_or_else(|| "anonymous".to_string());
+    let password = match password.or_else(|| env::var("SUPPAFTP_PASSWORD").ok()) {
+        Some(password) => password,
+        None if login == "anonymous" => String::new(),
+        None => rpassword::read_password_from_tty(Some("Password: ")).unwrap_or_else(|e| fail(&e.to_string())),
+    };
+
+    let mut client = FtpClient::new(Box::new(CliSettings {
+        addr,
+        login,
+        password,
+        remote_dir,
+    }));
+
+    let result = client.connect().and_then(|_| run(&mut client, &command, &args));
+    let _ = client.quit();
+
+    match result {
+        Ok(true) => {},
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            let message = match &e {
+                FtpError::UnexpectedResponse(response) => format!("{} {}", response.code, response.body.trim()),
+                e => e.to_string(),
+            };
+            eprintln!("suppaftp-cli: {}", message);
+            process::exit(1);
+        },
+    }
+}
@@ -0,0 +1,38 @@
+use chrono::{DateTime, Utc};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum FtpAuditOperation {
+    Upload,
+    Delete,
+    Rename,
+    Mkdir,
+    Rmdir,
+    Chmod,
+    Chown,
+    Chgrp,
+    Symlink,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FtpAuditRecord {
+    pub timestamp:  DateTime<Utc>,
+    pub operation:  FtpAuditOperation,
+    pub path:       String,
+    // new path of a rename, link target of a symlink, new owner or group
+    pub target:     Option<String>,
+    // bytes sent by an upload
+    pub size:       Option<u64>,
+    // `None` when the operation succeeded
+    pub error:      Option<String>,
+}
+
+// Receives a record of every operation that changes the server, whether it
+// succeeded or not. `record` takes `&self` so one sink can be shared between
+// the clients of a pool.
+pub trait FtpAuditSink: std::fmt::Debug + Send + Sync {
+    fn record(&self, record: &FtpAuditRecord);
+}
@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use crate::types::FtpList;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub(crate) struct FtpListCache {
+    ttl: Duration,
+    entries: HashMap<String, (Instant, FtpList)>,
+}
+
+impl FtpListCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, path: &str) -> Option<FtpList> {
+        let expired = match self.entries.get(path) {
+            Some((stored, list)) if stored.elapsed() < self.ttl => return Some(list.clone()),
+            Some(_) => true,
+            None => false,
+        };
+
+        if expired {
+            self.entries.remove(path);
+        }
+
+        None
+    }
+
+    pub fn insert(&mut self, path: String, list: FtpList) {
+        self.entries.insert(path, (Instant::now(), list));
+    }
+
+    pub fn invalidate(&mut self, path: &str) {
+        self.entries.remove(path);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FtpChecksumEntry {
+    pub size:       u64,
+    pub modified:   Option<u64>,
+    pub checksum:   u64,
+    // `unique` fact of the remote file, which survives renames on the server
+    pub unique:     Option<String>,
+}
+
+// On-disk `path -> (size, mtime, checksum, unique)` map kept by the mirror subsystem
+// between runs. After the header, one entry per line:
+// `size<TAB>mtime<TAB>checksum<TAB>unique<TAB>path`, mtime and unique are `-` when
+// unknown. Files without the header are from before `unique` was stored and have
+// no such column.
+#[derive(Debug, Clone, Default)]
+pub struct FtpChecksumCache {
+    path: PathBuf,
+    entries: BTreeMap<String, FtpChecksumEntry>,
+}
+
+const CACHE_HEADER: &str = "#suppaftp-client checksum cache v2";
+
+fn invalid_line(line: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("malformed checksum cache line {:?}", line))
+}
+
+impl FtpChecksumCache {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut entries = BTreeMap::new();
+
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self { path, entries }),
+            Err(e) => return Err(e),
+        };
+
+        let mut has_unique = false;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            if line == CACHE_HEADER {
+                has_unique = true;
+                continue;
+            }
+
+            let mut fields = line.splitn(if has_unique { 5 } else { 4 }, '\t');
+            let mut next = || fields.next().ok_or_else(|| invalid_line(&line));
+
+            let size = next()?.parse().map_err(|_| invalid_line(&line))?;
+            let modified = match next()? {
+                "-" => None,
+                modified => Some(modified.parse().map_err(|_| invalid_line(&line))?),
+            };
+            let checksum = u64::from_str_radix(next()?, 16).map_err(|_| invalid_line(&line))?;
+            let unique = if has_unique {
+                Some(next()?).filter(|unique| *unique != "-").map(str::to_string)
+            } else {
+                None
+            };
+            let key = next()?.to_string();
+
+            entries.insert(key, FtpChecksumEntry { size, modified, checksum, unique });
+        }
+
+        Ok(Self { path, entries })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&FtpChecksumEntry> {
+        self.entries.get(key)
+    }
+
+    pub fn insert(&mut self, key: String, entry: FtpChecksumEntry) {
+        self.entries.insert(key, entry);
+    }
+
+    // Keys of the entries recorded with a `unique` fact, by that fact.
+    pub fn keys_by_unique(&self) -> HashMap<String, String> {
+        self.entries.iter()
+            .filter_map(|(key, entry)| Some((entry.unique.clone()?, key.clone())))
+            .collect()
+    }
+
+    pub fn rename(&mut self, from: &str, to: String) {
+        if let Some(entry) = self.entries.remove(from) {
+            self.entries.insert(to, entry);
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut temp_name = self.path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+        temp_name.push(".tmp");
+        let temp_path = self.path.with_file_name(temp_name);
+
+        let mut file = io::BufWriter::new(File::create(&temp_path)?);
+        writeln!(file, "{}", CACHE_HEADER)?;
+        for (key, entry) in &self.entries {
+            let modified = entry.modified.map(|modified| modified.to_string()).unwrap_or_else(|| "-".to_string());
+            let unique = entry.unique.as_deref().unwrap_or("-");
+            writeln!(file, "{}\t{}\t{:016x}\t{}\t{}", entry.size, modified, entry.checksum, unique, key)?;
+        }
+        file.flush()?;
+        file.get_ref().sync_all()?;
+
+        fs::rename(&temp_path, &self.path)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// FNV-1a: stable across builds and platforms, which a cache on disk needs.
+pub fn file_checksum<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+    fnv_checksum(&mut File::open(path)?)
+}
+
+// Of the first `limit` bytes only.
+pub(crate) fn head_checksum(path: &Path, limit: u64) -> io::Result<u64> {
+    fnv_checksum(&mut File::open(path)?.take(limit))
+}
+
+fn fnv_checksum(reader: &mut dyn Read) -> io::Result<u64> {
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut hash = FNV_OFFSET;
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(hash),
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        for byte in &buffer[..read] {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+fn local_mtime(metadata: &fs::Metadata) -> Option<u64> {
+    metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok().map(|mtime| mtime.as_secs())
+}
+
+// Describes a local file, reusing the cached checksum when size and mtime are unchanged.
+// The flag tells whether the content matches `known`.
+pub(crate) fn local_entry(path: &Path, known: Option<&FtpChecksumEntry>) -> io::Result<(FtpChecksumEntry, bool)> {
+    let metadata = fs::metadata(path)?;
+    let size = metadata.len();
+    let modified = local_mtime(&metadata);
+
+    if let Some(known) = known {
+        if known.size == size && known.modified.is_some() && known.modified == modified {
+            return Ok((known.clone(), true));
+        }
+    }
+
+    let checksum = file_checksum(path)?;
+    let unchanged = known.is_some_and(|known| known.size == size && known.checksum == checksum);
+
+    Ok((FtpChecksumEntry { size, modified, checksum, unique: None }, unchanged))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::mock::support::temp_path;
+
+    #[test]
+    fn test_checksum_cache() {
+        let dir = temp_path("checksum");
+        fs::create_dir_all(&dir).unwrap();
+        let data = dir.join("a.txt");
+        fs::write(&data, "hello").unwrap();
+
+        let (entry, unchanged) = local_entry(&data, None).unwrap();
+        assert!(!unchanged);
+
+        let mut cache = FtpChecksumCache::open(dir.join("cache")).unwrap();
+        cache.insert("a.txt".to_string(), entry.clone());
+        let moved = FtpChecksumEntry { unique: Some("801g4".to_string()), ..entry.clone() };
+        cache.insert("b.txt".to_string(), moved.clone());
+        cache.save().unwrap();
+
+        let mut cache = FtpChecksumCache::open(dir.join("cache")).unwrap();
+        assert_eq!(cache.get("a.txt"), Some(&entry));
+        assert_eq!(cache.keys_by_unique().get("801g4").map(String::as_str), Some("b.txt"));
+        cache.rename("b.txt", "c.txt".to_string());
+        assert_eq!(cache.get("c.txt"), Some(&moved));
+
+        // written before the unique column existed
+        fs::write(dir.join("old"), "5\t-\t00000000000000ff\tdir/a.txt\n").unwrap();
+        let old = FtpChecksumCache::open(dir.join("old")).unwrap();
+        assert_eq!(old.get("dir/a.txt").map(|entry| entry.checksum), Some(0xff));
+
+        // same size, different content
+        fs::write(&data, "world").unwrap();
+        let stale = FtpChecksumEntry { modified: None, ..entry };
+        assert!(!local_entry(&data, Some(&stale)).unwrap().1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
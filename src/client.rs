@@ -1,10 +1,25 @@
-use suppaftp::{sync_ftp::FtpStream, types::{FtpResult, FtpError}, list};
+use suppaftp::{types::{FileType, FtpResult, FtpError, Mode, Response}, list};
 use crate::{
-    mlst::{MlstFact, parse_mlst_feat, parse_mlst_line, list_to_ftp},
-    types::{FtpItem, FtpItemType, FtpList}
+    audit::{FtpAuditOperation, FtpAuditRecord, FtpAuditSink},
+    cache::FtpListCache,
+    middleware::FtpMiddleware,
+    listing::{FtpListFormat, ListLineParser, is_total_line},
+    mlst::{MlstFact, parse_mlst_date, parse_mlst_feat, parse_mlst_line_interned, list_to_ftp},
+    path::{join_path, normalize_path, parent_path, resolve_dot_segments},
+    pattern::wildcard_match,
+    rate::{FtpRateLimiter, RateLimit},
+    transport::{FtpConnector, FtpStreamConnector, FtpTransport},
+    types::{FtpItem, FtpItemType, FtpList, FtpServerStatus, FtpStringInterner}
 };
+#[cfg(feature = "sync-secure")]
 use native_tls::{TlsConnector};
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{self, BufRead, Read, Write};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -17,9 +32,12 @@ pub struct FtpClientFeatures {
     size: bool,
     rest_stream: bool,
     tvfs: bool,
+    comb: bool,
     mlst: Option<Vec<(MlstFact, bool)>>,
     auth_tls: bool,
-    others: Vec<String>,
+    // the remaining extensions by upper-cased name, with their parameters; SITE
+    // lines are keyed `SITE <verb>`
+    others: HashMap<String, Option<String>>,
 }
 
 impl Default for FtpClientFeatures {
@@ -32,13 +50,88 @@ impl Default for FtpClientFeatures {
             size: false,
             rest_stream: false,
             tvfs: false,
+            comb: false,
             mlst: None,
             auth_tls: false,
-            others: vec![],
+            others: HashMap::new(),
         }
     }
 }
 
+impl FtpClientFeatures {
+    fn has_command(&self, verb: &str) -> bool {
+        match verb {
+            "CLNT" => self.clnt,
+            "PASV" => self.pasv,
+            "MDTM" => self.mdtm,
+            "SIZE" => self.size,
+            "REST" => self.rest_stream,
+            "COMB" => self.comb,
+            "MLST" | "MLSD" => self.mlst.is_some(),
+            "AUTH" => self.auth_tls,
+            _ => self.has_extension(verb),
+        }
+    }
+
+    pub fn has_extension(&self, name: &str) -> bool {
+        self.others.contains_key(&name.to_ascii_uppercase())
+    }
+
+    pub fn extension_params(&self, name: &str) -> Option<&str> {
+        self.others.get(&name.to_ascii_uppercase()).and_then(Option::as_deref)
+    }
+
+    fn site_commands(&self) -> impl Iterator<Item = String> + '_ {
+        self.others.keys().filter_map(|name| name.strip_prefix("SITE ").map(str::to_string))
+    }
+
+    // `HASH SHA-256*;SHA-1;MD5`, the selected algorithm marked with '*'
+    fn hash_algorithms(&self) -> Vec<(String, bool)> {
+        self.extension_params("HASH")
+            .map(|params| {
+                params.split(';')
+                    .map(str::trim)
+                    .filter(|algorithm| !algorithm.is_empty())
+                    .map(|algorithm| match algorithm.strip_suffix('*') {
+                        Some(algorithm) => (algorithm.to_string(), true),
+                        None => (algorithm.to_string(), false),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+// What the server offers, assembled from FEAT and, when probed, HELP and SITE HELP.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct FtpCapabilities {
+    pub auth_tls:       bool,
+    pub utf8:           bool,
+    pub tvfs:           bool,
+    pub mdtm:           bool,
+    pub mfmt:           bool,
+    pub size:           bool,
+    pub rest_stream:    bool,
+    pub mode_z:         bool,
+    // MLST/MLSD facts, `true` for the ones currently enabled; `None` without MLSD
+    pub mlst:           Option<Vec<(MlstFact, bool)>>,
+    pub hash:           bool,
+    // from FEAT, the selected one `true`; empty when HASH is only known from HELP
+    pub hash_algorithms:    Vec<(String, bool)>,
+    // which of XCRC, XMD5, XSHA1, XSHA256 and XSHA512 the server has
+    pub x_hashes:       BTreeSet<String>,
+    // SITE subcommands
+    pub site:           BTreeSet<String>,
+}
+
+impl FtpCapabilities {
+    pub fn mlsd(&self) -> bool {
+        self.mlst.is_some()
+    }
+}
+
+const X_HASH_COMMANDS: &[&str] = &["XCRC", "XMD5", "XSHA1", "XSHA256", "XSHA512"];
+
 impl From<Vec<String>> for FtpClientFeatures {
 
     fn from(lines: Vec<String>) -> Self {
@@ -78,6 +171,9 @@ impl From<Vec<String>> for FtpClientFeatures {
                 "TVFS" => { 
                     result.tvfs = true; 
                 },
+                "COMB" => {
+                    result.comb = true;
+                },
                 "MLST" => { 
                     result.mlst = Some(parse_mlst_feat(tail)); 
                 },
@@ -85,7 +181,12 @@ impl From<Vec<String>> for FtpClientFeatures {
                     result.auth_tls = true;
                 },
                 _ => {
-                    result.others.push( line );
+                    let (name, params) = match tail.split_once(' ') {
+                        Some((verb, params)) if first_word.eq_ignore_ascii_case("SITE") => (format!("SITE {}", verb), params.trim()),
+                        None if first_word.eq_ignore_ascii_case("SITE") && !tail.is_empty() => (format!("SITE {}", tail), ""),
+                        _ => (first_word.to_string(), tail),
+                    };
+                    result.others.insert(name.to_ascii_uppercase(), Some(params.to_string()).filter(|params| !params.is_empty()));
                 }
             }
 
@@ -98,6 +199,7 @@ impl From<Vec<String>> for FtpClientFeatures {
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "lowercase"))]
 pub enum FtpClientListMode {
     List,
     Nlst,
@@ -105,37 +207,386 @@ pub enum FtpClientListMode {
     Stat,
 }
 
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum FtpAddressPreference {
+    Any,
+    PreferIpv4,
+    PreferIpv6,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum FtpTlsVersion {
+    Tls10,
+    Tls11,
+    Tls12,
+}
+
+// `Preferred` upgrades only when FEAT advertises AUTH TLS, so it stays in plaintext
+// when FEAT is disabled or unsupported.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum TlsPolicy {
+    Required,
+    Preferred,
+    Disabled,
+}
+
+// Timezone of the times in LIST output, which carries none. They are converted to
+// UTC like the MLSD ones.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum FtpListTimezone {
+    #[default]
+    Utc,
+    // seconds east of UTC
+    Offset(i32),
+    // the timezone of this machine
+    Local,
+}
+
+impl FtpListTimezone {
+    pub fn to_utc(&self, time: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            Self::Utc => time,
+            Self::Offset(seconds) => time - chrono::Duration::seconds(*seconds as i64),
+            // a time skipped by a DST change is left as it is
+            Self::Local => chrono::Local.from_local_datetime(&time)
+                .earliest()
+                .map(|local| local.naive_utc())
+                .unwrap_or(time),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FtpLatency {
+    pub min:        Duration,
+    pub median:     Duration,
+    pub max:        Duration,
+    pub samples:    usize,
+}
+
+// What the reconnect handling had to do since the client was created or the
+// counters were last reset.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FtpRetryStats {
+    // every connection opened, the first one included
+    pub connections:    u64,
+    // operations sent again on a new connection after a recoverable error
+    pub retries:        u64,
+    // retries that failed as well, reconnecting included
+    pub failed_retries: u64,
+    // recoverable errors of operations that can't be repeated safely
+    pub not_retried:    u64,
+    // the errors behind `retries` and `not_retried`, by `FtpRetryStats::error_class`
+    pub causes:         BTreeMap<String, u64>,
+    // slept before retries, as `FtpClientSettings::retry_backoff` asked
+    pub backoff:        Duration,
+}
+
+impl FtpRetryStats {
+    // The `io::ErrorKind` of connection errors, e.g. `ConnectionReset`, and the
+    // reply code of unexpected replies.
+    pub fn error_class(error: &FtpError) -> String {
+        match error {
+            FtpError::ConnectionError(e) => format!("{:?}", e.kind()),
+            FtpError::UnexpectedResponse(reply) => format!("reply {}", reply.code),
+            FtpError::BadResponse => "bad response".to_string(),
+            _ => "other".to_string(),
+        }
+    }
+
+    // Adds the counters of `other`, e.g. of another client of a pool.
+    pub fn add(&mut self, other: &FtpRetryStats) {
+        self.connections += other.connections;
+        self.retries += other.retries;
+        self.failed_retries += other.failed_retries;
+        self.not_retried += other.not_retried;
+        for (cause, count) in &other.causes {
+            *self.causes.entry(cause.clone()).or_insert(0) += count;
+        }
+        self.backoff += other.backoff;
+    }
+
+    fn count_cause(&mut self, error: &FtpError) {
+        *self.causes.entry(Self::error_class(error)).or_insert(0) += 1;
+    }
+}
+
+// Protection of the control connection as negotiated at the last connect.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FtpTlsMode {
+    Plain,
+    // AUTH TLS after the greeting
+    Explicit,
+}
+
+#[cfg(feature = "sync-secure")]
+impl From<FtpTlsVersion> for native_tls::Protocol {
+    fn from(version: FtpTlsVersion) -> Self {
+        match version {
+            FtpTlsVersion::Tls10 => native_tls::Protocol::Tlsv10,
+            FtpTlsVersion::Tls11 => native_tls::Protocol::Tlsv11,
+            FtpTlsVersion::Tls12 => native_tls::Protocol::Tlsv12,
+        }
+    }
+}
+
+#[cfg(feature = "sync-secure")]
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+#[cfg(feature = "sync-secure")]
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+// `Certificate::from_pem` reads a single certificate, so a bundle is split first.
+#[cfg(feature = "sync-secure")]
+fn parse_pem_bundle(bundle: &[u8]) -> FtpResult<Vec<native_tls::Certificate>> {
+    let text = std::str::from_utf8(bundle).map_err(|e| FtpError::SecureError(e.to_string()))?;
+    let mut certificates = vec![];
+    let mut rest = text;
+
+    while let Some(start) = rest.find(PEM_BEGIN) {
+        let end = rest[start..].find(PEM_END)
+            .map(|end| start + end + PEM_END.len())
+            .ok_or_else(|| FtpError::SecureError("unterminated PEM certificate".to_string()))?;
+        let certificate = native_tls::Certificate::from_pem(rest[start..end].as_bytes())
+            .map_err(|e| FtpError::SecureError(e.to_string()))?;
+        certificates.push(certificate);
+        rest = &rest[end..];
+    }
+
+    if certificates.is_empty() {
+        return Err(FtpError::SecureError("no certificates in PEM bundle".to_string()));
+    }
+    Ok(certificates)
+}
+
+// The host part of `host:port`, without the brackets of an IPv6 literal.
+#[cfg(feature = "sync-secure")]
+fn addr_host(addr: &str) -> &str {
+    if let Some(rest) = addr.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    match addr.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') && port.parse::<u16>().is_ok() => host,
+        _ => addr,
+    }
+}
+
+// What a download or upload does when its destination already exists.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum OverwritePolicy {
+    #[default]
+    Overwrite,
+    Skip,
+    Error,
+    // the existing file is renamed by appending the suffix
+    RenameExisting(String),
+    // same as `RenameExisting(".bak")`
+    Backup,
+}
+
+impl OverwritePolicy {
+    pub(crate) fn backup_suffix(&self) -> Option<&str> {
+        match self {
+            Self::RenameExisting(suffix) => Some(suffix),
+            Self::Backup => Some(".bak"),
+            _ => None,
+        }
+    }
+}
+
+// Owner or group for SITE CHOWN/CHGRP, as in the `unix.owner`/`unix.ownername`
+// and `unix.group`/`unix.groupname` facts.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum FtpUnixId {
+    Id(u32),
+    Name(String),
+}
+
+impl From<u32> for FtpUnixId {
+    fn from(id: u32) -> Self {
+        Self::Id(id)
+    }
+}
+
+impl From<&str> for FtpUnixId {
+    fn from(name: &str) -> Self {
+        Self::Name(name.to_string())
+    }
+}
+
+impl std::fmt::Display for FtpUnixId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Id(id) => write!(f, "{}", id),
+            Self::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+// Applied to the control socket right after it is connected, and to every data
+// connection of a plain passive session. Over TLS, and in active mode, suppaftp
+// opens the data connections itself and they keep the system defaults.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct FtpSocketOptions {
+    pub nodelay:            Option<bool>,
+    pub keepalive_time:     Option<Duration>,
+    pub keepalive_interval: Option<Duration>,
+    pub recv_buffer_size:   Option<usize>,
+    pub send_buffer_size:   Option<usize>,
+}
+
 pub enum FtpClientPathMode {
     Linux,
     Windows,
     StepByStep,
 }
 
-pub trait FtpClientSettings: std::fmt::Debug {
+pub(crate) const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(5);
+pub(crate) const DEFAULT_TRANSFER_BUFFER_SIZE: usize = 64 * 1024;
+const PIPELINE_WINDOW: usize = 64;
+
+pub trait FtpClientSettings: std::fmt::Debug + Send {
     fn addr(&self) -> &str;
     fn login(&self) -> &str;
     fn password(&self) -> &str;
     fn remote_dir(&self) -> Option<&str>;
+
+    fn account(&self) -> Option<&str> {
+        None
+    }
     
-    #[cfg(feature = "secure")]
+    #[cfg(feature = "sync-secure")]
     fn use_secure(&self) -> bool {
         true
     }
 
-    #[cfg(feature = "secure")]
-    fn sni(&self) -> Option<&str>;
+    // Name sent in SNI and checked against the server certificate; the host of
+    // `addr()` by default.
+    #[cfg(feature = "sync-secure")]
+    fn sni(&self) -> Option<&str> {
+        None
+    }
+
+    // Some servers behind TLS terminators reject any SNI; the certificate is
+    // still checked against the name.
+    #[cfg(feature = "sync-secure")]
+    fn use_sni(&self) -> bool {
+        true
+    }
+
+    #[cfg(feature = "sync-secure")]
+    fn tls_policy(&self) -> TlsPolicy {
+        if self.use_secure() { TlsPolicy::Required } else { TlsPolicy::Disabled }
+    }
+
+    // `None` keeps the TLS backend's default; old appliances may need `Tls10`
+    #[cfg(feature = "sync-secure")]
+    fn tls_min_version(&self) -> Option<FtpTlsVersion> {
+        None
+    }
+
+    #[cfg(feature = "sync-secure")]
+    fn tls_max_version(&self) -> Option<FtpTlsVersion> {
+        None
+    }
+
+    // PEM bundle of extra trust roots, accepted in addition to the system store
+    #[cfg(feature = "sync-secure")]
+    fn root_certificates(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn address_preference(&self) -> FtpAddressPreference {
+        FtpAddressPreference::Any
+    }
+
+    // per resolved address
+    fn connect_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    fn socket_options(&self) -> FtpSocketOptions {
+        FtpSocketOptions::default()
+    }
 
     fn use_feat(&self) -> bool {
         true
     }
 
+    // `false` has the server connect back for data, with PORT
     fn use_passive_mode(&self) -> bool {
         true
     }
 
+    // Sends `EPSV ALL` after login, for firewalls that only let extended passive
+    // through; the server then refuses PASV, PORT and EPRT for the session, so
+    // connecting fails without passive mode.
+    fn use_epsv_all(&self) -> bool {
+        false
+    }
+
     fn list_mode(&self) -> Option<FtpClientListMode> {
         None
     }
+
+    fn list_cache_ttl(&self) -> Option<Duration> {
+        None
+    }
+
+    // send `LIST -a` so servers include hidden entries (MLSD always does)
+    fn list_hidden(&self) -> bool {
+        false
+    }
+
+    fn list_timezone(&self) -> FtpListTimezone {
+        FtpListTimezone::Utc
+    }
+
+    fn ping_timeout(&self) -> Duration {
+        DEFAULT_PING_TIMEOUT
+    }
+
+    // default for downloads and uploads; individual calls may override it
+    fn rate_limit(&self) -> RateLimit {
+        RateLimit::Unlimited
+    }
+
+    fn overwrite_policy(&self) -> OverwritePolicy {
+        OverwritePolicy::Overwrite
+    }
+
+    // copy buffer for file transfers, rounded up to whole 4 KiB pages
+    fn transfer_buffer_size(&self) -> usize {
+        DEFAULT_TRANSFER_BUFFER_SIZE
+    }
+
+    // the control connection is closed after being idle this long and reopened on demand
+    fn max_idle(&self) -> Option<Duration> {
+        None
+    }
+
+    // write independent commands of a batch back to back instead of waiting for
+    // each reply; the server has to queue commands it hasn't answered yet. Only
+    // plain control connections are pipelined.
+    fn use_pipelining(&self) -> bool {
+        false
+    }
+
+    // How often an operation that is safe to repeat is tried again on a new
+    // connection after a recoverable error; `operation` is the name middleware sees.
+    fn max_retries(&self, _operation: &str) -> u32 {
+        1
+    }
+
+    // Wait before retry number `attempt` of an operation, counted from 1.
+    fn retry_backoff(&self, _attempt: u32) -> Duration {
+        Duration::ZERO
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -150,206 +601,2437 @@ pub struct FtpClient {
     settings: Box<dyn FtpClientSettings>,
     settings_list_mode: Option<FtpClientListMode>,
     effective_list_mode: Option<FtpClientListMode>,
+    list_format: Option<FtpListFormat>,
+    list_parsers: Vec<Box<dyn ListLineParser>>,
+    fallback_list_parsers: Vec<Box<dyn ListLineParser>>,
+    hide_dotfiles: bool,
+    pipelining: bool,
 
-    ftp: Option<FtpStream>,
+    connector: Box<dyn FtpConnector>,
+    ftp: Option<Box<dyn FtpTransport>>,
 
     has_feat: bool,
     features: FtpClientFeatures,
+    help_commands: Option<BTreeSet<String>>,
+    site_commands: Option<BTreeSet<String>>,
 
     current_path: Option<FtpPath>,
-}
+    credentials: Option<(String, String)>,
 
-macro_rules! ftp {
-    ($self:expr, $func:ident($($params:tt)*)) => {{
-        let mut already_reconnected = false;
-        
-        let mut ftp = match $self.ftp.as_mut() {
-            Some(ftp) => ftp,
-            None => {
-                already_reconnected = true;
-                $self.reconnect()?
-            }
-        };
+    welcome_message: Option<String>,
+    tls_mode: Option<FtpTlsMode>,
+    last_latency: Option<FtpLatency>,
+    retry_stats: FtpRetryStats,
+    last_reply: Option<Response>,
+    last_activity: Instant,
 
-        let mut result = ftp.$func($($params)*);
+    transfer_buffer: Vec<u8>,
+    shared_rate_limiter: Option<FtpRateLimiter>,
+    clock_offset: chrono::Duration,
 
-        if let Err(e) = &result {
-            if e.is_recoverable() && !already_reconnected {
-                ftp = $self.reconnect()?;
-                result = ftp.$func($($params)*);
-            };
-        };
+    list_cache: Option<FtpListCache>,
 
-        result
-    }};
+    middleware: Vec<Box<dyn FtpMiddleware>>,
+    audit_sink: Option<Arc<dyn FtpAuditSink>>,
 }
 
-macro_rules! list_fn {
-    ($self: expr, $func: ident, $map: expr) => {
-        ftp!($self, $func(None))?
-            .into_iter()
-            .map($map)
-            .try_fold( FtpList::default(), |mut list, ritem| {
-                let item = ritem?;
-                match item.ty {
-                    FtpItemType::CurrentDir => {
-                        list.current = Some(item);
-                    },
-                    FtpItemType::ParentDir => {
-                        list.parent = Some(item);
-                    },
-                    _ => {
-                        list.items.push(item);
-                    },
-                };
-                Ok(list)
-            })
-    };
-}    
+// Whether an operation may be repeated on a fresh connection when the first
+// attempt failed with a recoverable error.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum FtpRetry {
+    // reads, and commands that have the same effect when repeated
+    Reconnect,
+    // the server may have acted on part of it already
+    Never,
+}
 
 impl FtpClient {
     
     pub fn new(settings: Box<dyn FtpClientSettings>) -> Self {
+        let connector = FtpStreamConnector {
+            preference: settings.address_preference(),
+            timeout: settings.connect_timeout(),
+            socket_options: settings.socket_options(),
+        };
+        Self::with_connector(settings, Box::new(connector))
+    }
+
+    pub(crate) fn with_connector(settings: Box<dyn FtpClientSettings>, connector: Box<dyn FtpConnector>) -> Self {
+        let list_cache = settings.list_cache_ttl().map(FtpListCache::new);
+        let pipelining = settings.use_pipelining();
+
         Self {
             settings,
             settings_list_mode: None,
             effective_list_mode: None,
+            list_format: None,
+            list_parsers: vec![],
+            fallback_list_parsers: vec![],
+            hide_dotfiles: false,
+            pipelining,
 
+            connector,
             ftp: None,
 
             has_feat: false,
+            help_commands: None,
+            site_commands: None,
             features: Default::default(),
 
             current_path: None,
-        }
-    }
+            credentials: None,
 
-    fn reconnect(&mut self) -> FtpResult<&mut FtpStream> {
-        // drop existing ftp connection
-        self.ftp = None;
+            welcome_message: None,
+            tls_mode: None,
+            last_latency: None,
+            retry_stats: FtpRetryStats::default(),
+            last_reply: None,
+            last_activity: Instant::now(),
 
-        let mut ftp = FtpStream::connect(self.settings.addr())?;
-        
-        if !self.has_feat && self.settings.use_feat() {
-            self.features = ftp.feat()?.into();
-            self.has_feat = true;
-        }
+            transfer_buffer: vec![],
+            shared_rate_limiter: None,
+            clock_offset: chrono::Duration::zero(),
 
-        #[cfg(feature = "secure")]
-        if self.settings.use_secure() {
-            let sni = self.settings.sni();
+            list_cache,
 
-            let tls_connector = TlsConnector::builder()
-                .use_sni(sni.is_some())
-                .build()
-                .map_err(|e| FtpError::SecureError(e.to_string()))?;
+            middleware: vec![],
+            audit_sink: None,
+        }
+    }
 
-            ftp = ftp.into_secure(tls_connector, sni.unwrap_or_default())?;
-        };
+    pub fn add_middleware(&mut self, middleware: Box<dyn FtpMiddleware>) {
+        self.middleware.push(middleware);
+    }
 
-        ftp.login( self.settings.login(), self.settings.password() )?;
+    pub fn set_audit_sink(&mut self, sink: Option<Arc<dyn FtpAuditSink>>) {
+        self.audit_sink = sink;
+    }
 
-        if let Some(path) = self.settings.remote_dir() {
-            ftp.cwd(path)?;
+    pub(crate) fn audit(&self, operation: FtpAuditOperation, path: &str, target: Option<&str>, size: Option<u64>, result: Result<(), &FtpError>) {
+        if let Some(sink) = self.audit_sink.as_ref() {
+            sink.record(&FtpAuditRecord {
+                timestamp: Utc::now(),
+                operation,
+                path: path.to_string(),
+                target: target.map(str::to_string),
+                size,
+                error: result.err().map(|e| e.to_string()),
+            });
         }
+    }
 
-        if !self.current_path.is_none() {
-//            ftp.cwd(self.current_path.as_str())?;
-        }
+    fn audited<T, F>(&mut self, operation: FtpAuditOperation, path: &str, target: Option<&str>, f: F) -> FtpResult<T>
+    where
+        F: FnOnce(&mut Self) -> FtpResult<T>
+    {
+        let result = f(self);
+        self.audit(operation, path, target, None, result.as_ref().map(|_| ()));
+        result
+    }
 
-        self.ftp = Some(ftp);
+    pub fn set_hide_dotfiles(&mut self, hide: bool) {
+        self.hide_dotfiles = hide;
+    }
 
-        Ok(self.ftp.as_mut().unwrap())
+    pub fn set_pipelining(&mut self, pipelining: bool) {
+        self.pipelining = pipelining;
     }
 
-    pub fn cdup(&mut self) -> FtpResult<()> {
-        ftp!(self, cdup())
+    // applied to transfers on top of their own rate limit
+    pub fn set_shared_rate_limiter(&mut self, limiter: Option<FtpRateLimiter>) {
+        self.shared_rate_limiter = limiter;
     }
 
-    pub fn chdir(&mut self, path: &str) -> FtpResult<()> {
-        ftp!(self, cwd(path))
+    // consulted before the built-in LIST parsers
+    pub fn add_list_parser(&mut self, parser: Box<dyn ListLineParser>) {
+        self.list_parsers.push(parser);
     }
 
-    fn list_mlsd(&mut self) -> FtpResult<FtpList> {
-        list_fn!(self, mlsd, |s| parse_mlst_line(s.as_str()).map_err(|_| FtpError::BadResponse))
+    // consulted for lines the built-in LIST parsers reject
+    pub fn add_fallback_list_parser(&mut self, parser: Box<dyn ListLineParser>) {
+        self.fallback_list_parsers.push(parser);
     }
 
-    fn list_nlst(&mut self) -> FtpResult<FtpList> {
-        unimplemented!()
+    // Every transport call goes through here: idle connections are closed first,
+    // middleware sees the operation, and a recoverable error is retried on a new
+    // connection, up to `max_retries` times, when `retry` allows it.
+    fn execute<T, F>(&mut self, operation: &'static str, retry: FtpRetry, mut call: F) -> FtpResult<T>
+    where
+        F: FnMut(&mut dyn FtpTransport) -> FtpResult<T>,
+    {
+        self.close_if_idle();
+        self.notify_before(operation);
+        let started = Instant::now();
+
+        let max_retries = match retry {
+            FtpRetry::Reconnect => self.settings.max_retries(operation),
+            FtpRetry::Never => 0,
+        };
+
+        // a connection opened just for this call uses up the first retry
+        let mut attempts = 0;
+        if self.ftp.is_none() {
+            self.reconnect()?;
+            attempts += 1;
+        }
+        let mut result = call(&mut **self.connected_stream()?);
+
+        while let Err(e) = &result {
+            if !e.is_recoverable() || attempts >= max_retries.max(1) {
+                break;
+            }
+            attempts += 1;
+
+            let retried = attempts <= max_retries;
+            self.retry_stats.count_cause(e);
+            for middleware in self.middleware.iter_mut() {
+                middleware.on_retry(operation, e, retried);
+            }
+            if !retried {
+                self.retry_stats.not_retried += 1;
+                break;
+            }
+
+            self.retry_stats.retries += 1;
+            let backoff = self.settings.retry_backoff(attempts);
+            if backoff > Duration::ZERO {
+                let waited = Instant::now();
+                thread::sleep(backoff);
+                self.retry_stats.backoff += waited.elapsed();
+            }
+
+            // a reconnect that fails is a failed retry too, and may be retried itself
+            result = match self.reconnect() {
+                Ok(ftp) => call(&mut **ftp),
+                Err(e) => Err(e),
+            };
+            if result.is_err() {
+                self.retry_stats.failed_retries += 1;
+            }
+        }
+
+        self.notify_after(operation, result.as_ref().map(|_| ()), started.elapsed());
+        result
     }
 
-    fn list_stat(&mut self) -> FtpResult<FtpList> {
-        unimplemented!()
+    fn notify_before(&mut self, operation: &str) {
+        for middleware in self.middleware.iter_mut() {
+            middleware.before_operation(operation);
+        }
     }
 
-    fn list_list(&mut self) -> FtpResult<FtpList> {
-        list_fn!(self, list, |s| list::File::from_str(s.as_str()).map(|f| list_to_ftp(&f)).map_err(|_| FtpError::BadResponse))
+    fn notify_after(&mut self, operation: &str, result: Result<(), &FtpError>, elapsed: Duration) {
+        self.last_activity = Instant::now();
+
+        if let Err(FtpError::UnexpectedResponse(response)) = result {
+            self.last_reply = Some(response.clone());
+        }
+
+        for middleware in self.middleware.iter_mut() {
+            middleware.after_operation(operation, result, elapsed);
+        }
     }
 
-    fn get_list_mode(&mut self) -> FtpClientListMode {
-        match self.effective_list_mode {
-            Some(lm) => return lm,
-            _ => {},
-        };
+    fn reconnect(&mut self) -> FtpResult<&mut Box<dyn FtpTransport>> {
+        // drop existing ftp connection
+        self.ftp = None;
 
-        if self.settings_list_mode.is_none() {
-            self.settings_list_mode = self.settings.list_mode();
+        // the server refuses PORT after EPSV ALL, so no transfer could ever start
+        if self.settings.use_epsv_all() && !self.settings.use_passive_mode() {
+            return Err(FtpError::ConnectionError(io::Error::new(io::ErrorKind::InvalidInput, "EPSV ALL needs passive mode")));
+        }
+
+        self.last_activity = Instant::now();
+        let mut ftp = self.connector.connect(self.settings.addr())?;
+        self.retry_stats.connections += 1;
+        self.welcome_message = ftp.welcome_message();
+        
+        if !self.has_feat && self.settings.use_feat() {
+            self.features = ftp.feat()?.into();
+            self.has_feat = true;
+        }
+
+        #[cfg(feature = "sync-secure")]
+        let secure = match self.settings.tls_policy() {
+            TlsPolicy::Required => true,
+            TlsPolicy::Preferred => self.features.auth_tls,
+            TlsPolicy::Disabled => false,
         };
 
-        match self.settings_list_mode {
-            Some(lm) => {
-                self.effective_list_mode = self.settings_list_mode;
-                return lm;
-            },
-            _ => {},
+        #[cfg(not(feature = "sync-secure"))]
+        let secure = false;
+
+        #[cfg(feature = "sync-secure")]
+        if secure {
+            let domain = self.settings.sni().unwrap_or_else(|| addr_host(self.settings.addr())).to_string();
+            ftp = ftp.into_secure(self.tls_connector()?, &domain)?;
         };
+        self.tls_mode = Some(if secure { FtpTlsMode::Explicit } else { FtpTlsMode::Plain });
 
-        FtpClientListMode::List
+        self.start_session(&mut ftp)?;
+        self.ftp = Some(ftp);
+
+        Ok(self.ftp.as_mut().unwrap())
     }
 
-    pub fn list(&mut self) -> FtpResult<FtpList> {
-        match self.get_list_mode() {
-            FtpClientListMode::List => self.list_list(),
-            FtpClientListMode::Nlst => self.list_nlst(),
-            FtpClientListMode::Mlsd => self.list_mlsd(),
-            FtpClientListMode::Stat => self.list_stat(),
+    #[cfg(feature = "sync-secure")]
+    fn tls_connector(&self) -> FtpResult<TlsConnector> {
+        let mut builder = TlsConnector::builder();
+        builder
+            .use_sni(self.settings.use_sni())
+            .min_protocol_version(self.settings.tls_min_version().map(Into::into))
+            .max_protocol_version(self.settings.tls_max_version().map(Into::into));
+
+        if let Some(bundle) = self.settings.root_certificates() {
+            for certificate in parse_pem_bundle(bundle)? {
+                builder.add_root_certificate(certificate);
+            }
         }
+
+        builder.build().map_err(|e| FtpError::SecureError(e.to_string()))
     }
-}
 
-#[cfg(test)]
-mod test {
+    fn start_session(&mut self, ftp: &mut Box<dyn FtpTransport>) -> FtpResult<()> {
+        let (login, password) = match &self.credentials {
+            Some((login, password)) => (login.clone(), password.clone()),
+            None => (self.settings.login().to_string(), self.settings.password().to_string()),
+        };
 
-    use super::*;
-    use suppaftp::test::*;
+        let mut reply = self.session_command(ftp, format!("USER {}", login))?;
+        if reply.code == 331 {
+            reply = self.session_command(ftp, format!("PASS {}", password))?;
+        }
+        if reply.code == 332 {
+            if let Some(account) = self.settings.account().map(str::to_string) {
+                reply = self.session_command(ftp, format!("ACCT {}", account))?;
+            }
+        }
+        if reply.code != 230 && reply.code != 202 {
+            return Err(FtpError::UnexpectedResponse(reply));
+        }
 
-    #[derive(Debug)]
-    struct TestSettings {}
+        if self.settings.use_epsv_all() {
+            let reply = ftp.command("EPSV ALL")?;
+            if reply.code != 200 {
+                return Err(FtpError::UnexpectedResponse(reply));
+            }
+            ftp.set_mode(Mode::ExtendedPassive);
+        } else if !self.settings.use_passive_mode() {
+            ftp.set_mode(Mode::Active);
+        }
 
-    impl FtpClientSettings for TestSettings {
-        fn addr(&self) -> &str { TEST_SERVER_ADDR }
-        fn login(&self) -> &str { TEST_SERVER_LOGIN }
-        fn password(&self) -> &str { TEST_SERVER_PASSWORD }
-        fn remote_dir(&self) -> Option<&str> { None }
-        
-        #[cfg(feature = "secure")]
-        fn use_secure(&self) -> bool { false }
-    
-        #[cfg(feature = "secure")]
-        fn sni(&self) -> Option<&str> { None }
-    
-        fn list_mode(&self) -> Option<FtpClientListMode> {
-            Some(FtpClientListMode::Mlsd)
+        for middleware in self.middleware.iter_mut() {
+            for command in middleware.after_login() {
+                ftp.command(&command)?;
+            }
         }
-    }
 
-    fn settings() -> Box<dyn FtpClientSettings> {
-        Box::new(TestSettings {})
-    }
+        if let Some(path) = self.settings.remote_dir().map(str::to_string) {
+            expect_reply(&self.session_command(ftp, format!("CWD {}", path))?, 250)?;
+        }
 
-    #[test]
+        if let Some(FtpPath::Linux(path)) = self.current_path.clone() {
+            expect_reply(&self.session_command(ftp, format!("CWD {}", path))?, 250)?;
+        }
+
+        Ok(())
+    }
+
+    // During login the connection isn't the client's yet, so commands are sent on
+    // `ftp` directly rather than through `execute`, but still pass the middleware.
+    fn session_command(&mut self, ftp: &mut Box<dyn FtpTransport>, command: String) -> FtpResult<Response> {
+        let command = self.rewrite_command(command);
+        let mut reply = ftp.command(&command)?;
+        self.notify_reply(&command, &mut reply);
+        Ok(reply)
+    }
+
+    // Control-connection commands are sent as text through here, so middleware
+    // can rewrite them and inspect their replies.
+    fn execute_command(&mut self, operation: &'static str, retry: FtpRetry, command: String, codes: &[u32]) -> FtpResult<Response> {
+        self.execute_command_with(operation, retry, command, |reply, _| expect_codes(reply, codes))
+    }
+
+    // `check` runs on the reply of each attempt, and is told whether the command
+    // was sent before; an `UnexpectedResponse` it returns reaches `on_reply` too.
+    fn execute_command_with<F>(&mut self, operation: &'static str, retry: FtpRetry, command: String, mut check: F) -> FtpResult<Response>
+    where
+        F: FnMut(Response, bool) -> FtpResult<Response>,
+    {
+        let command = self.rewrite_command(command);
+        let mut repeated = false;
+        let mut result = self.execute(operation, retry, |ftp| {
+            let reply = ftp.command(&command);
+            check(reply?, std::mem::replace(&mut repeated, true))
+        });
+        if let Ok(reply) | Err(FtpError::UnexpectedResponse(reply)) = &mut result {
+            self.notify_reply(&command, reply);
+            self.last_reply = Some(reply.clone());
+        }
+        result
+    }
+
+    fn rewrite_command(&mut self, command: String) -> String {
+        self.middleware.iter_mut().fold(command, |command, middleware| middleware.rewrite_command(command))
+    }
+
+    fn notify_reply(&mut self, command: &str, reply: &mut Response) {
+        for middleware in self.middleware.iter_mut() {
+            middleware.on_reply(command, reply);
+        }
+    }
+
+    // Switches the session to another account. REIN keeps the control connection
+    // (and its TLS session) open; servers without REIN get a fresh connection.
+    pub fn reinitialize(&mut self, login: &str, password: &str) -> FtpResult<()> {
+        self.credentials = Some((login.to_string(), password.to_string()));
+        self.current_path = None;
+        self.clear_cache();
+
+        if let Some(mut ftp) = self.ftp.take() {
+            if let Ok(Response { code: 220, .. }) = ftp.command("REIN") {
+                if self.start_session(&mut ftp).is_ok() {
+                    self.ftp = Some(ftp);
+                    return Ok(());
+                }
+            }
+        }
+
+        self.reconnect().map(|_| ())
+    }
+
+    pub fn connect(&mut self) -> FtpResult<()> {
+        if self.ftp.is_none() {
+            self.reconnect()?;
+        }
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self) {
+        self.ftp = None;
+    }
+
+    pub fn quit(&mut self) -> FtpResult<()> {
+        match self.ftp.take() {
+            Some(mut ftp) => {
+                ftp.set_timeout(Some(self.settings.ping_timeout()))?;
+                ftp.quit()
+            },
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.ftp.is_some()
+    }
+
+    // Sends NOOP on the current connection without reconnecting; a connection that
+    // fails to answer in time is dropped, so the next operation reconnects.
+    pub fn ping(&mut self) -> FtpResult<Duration> {
+        let timeout = self.settings.ping_timeout();
+        let started = Instant::now();
+
+        let ftp = self.connected_stream()?;
+        let result = ftp.set_timeout(Some(timeout))
+            .and_then(|_| ftp.noop())
+            .and_then(|_| ftp.set_timeout(None));
+
+        match result {
+            Ok(()) => Ok(started.elapsed()),
+            Err(e) => {
+                self.ftp = None;
+                Err(e)
+            }
+        }
+    }
+
+    // Times `samples` NOOPs (at least one) over a connection made beforehand, so the
+    // handshake isn't counted.
+    pub fn latency(&mut self, samples: usize) -> FtpResult<FtpLatency> {
+        self.connect()?;
+        let mut times = (0..samples.max(1)).map(|_| self.ping()).collect::<FtpResult<Vec<_>>>()?;
+        times.sort();
+
+        let latency = FtpLatency {
+            min: times[0],
+            median: times[times.len() / 2],
+            max: times[times.len() - 1],
+            samples: times.len(),
+        };
+        self.last_latency = Some(latency);
+        Ok(latency)
+    }
+
+    pub fn last_latency(&self) -> Option<FtpLatency> {
+        self.last_latency
+    }
+
+    pub fn retry_stats(&self) -> &FtpRetryStats {
+        &self.retry_stats
+    }
+
+    pub fn reset_retry_stats(&mut self) {
+        self.retry_stats = FtpRetryStats::default();
+    }
+
+    // The retry stats of just `f`, which count in the client's own stats as well.
+    pub(crate) fn counting_retries<T, F>(&mut self, f: F) -> (T, FtpRetryStats)
+    where
+        F: FnOnce(&mut Self) -> T
+    {
+        let earlier = std::mem::take(&mut self.retry_stats);
+        let value = f(self);
+        let stats = std::mem::replace(&mut self.retry_stats, earlier);
+        self.retry_stats.add(&stats);
+        (value, stats)
+    }
+
+    pub fn is_alive(&mut self) -> bool {
+        self.ping().is_ok()
+    }
+
+    // Called before every operation; long-lived callers may also call it from a timer
+    // to free server slots. Returns true when the connection was closed.
+    pub fn close_if_idle(&mut self) -> bool {
+        let idle = match self.settings.max_idle() {
+            Some(max_idle) => self.ftp.is_some() && self.last_activity.elapsed() >= max_idle,
+            None => false,
+        };
+
+        if idle {
+            let _ = self.quit();
+        }
+        idle
+    }
+
+    // Sent once: the server may have acted on a command whose reply was lost, so
+    // it isn't repeated on a new connection. See `quote_idempotent`.
+    pub fn quote(&mut self, command: &str) -> FtpResult<Response> {
+        self.quote_with(command, FtpRetry::Never)
+    }
+
+    // For commands that have the same effect when sent twice (HELP, STAT, SITE
+    // CHMOD with an absolute mode, ...), which are retried like other reads.
+    pub fn quote_idempotent(&mut self, command: &str) -> FtpResult<Response> {
+        self.quote_with(command, FtpRetry::Reconnect)
+    }
+
+    fn quote_with(&mut self, command: &str, retry: FtpRetry) -> FtpResult<Response> {
+        self.execute_command_with("command", retry, command.to_string(), |reply, _| Ok(reply))
+    }
+
+    // Like `quote` for each command, but pipelined when pipelining is enabled and
+    // the control connection is plain. The commands must not open data connections.
+    // A command may only depend on the one right before it, as RNTO does on RNFR,
+    // when the server safely rejects it after that one failed (503 for RNTO).
+    pub fn quote_many(&mut self, commands: &[String]) -> FtpResult<Vec<Response>> {
+        if !self.can_pipeline() {
+            return commands.iter().map(|command| self.quote(command)).collect();
+        }
+
+        let mut replies = Vec::with_capacity(commands.len());
+
+        // bounded, so neither side stalls on a full socket buffer
+        for chunk in commands.chunks(PIPELINE_WINDOW) {
+            let chunk: Vec<String> = chunk.iter().map(|command| self.rewrite_command(command.clone())).collect();
+
+            let mut chunk_replies = self.execute("pipeline", FtpRetry::Never, |ftp| ftp.pipeline(&chunk))?;
+            for (command, reply) in chunk.iter().zip(chunk_replies.iter_mut()) {
+                self.notify_reply(command, reply);
+            }
+            replies.extend(chunk_replies);
+        }
+
+        self.last_reply = replies.last().cloned();
+        Ok(replies)
+    }
+
+    // A pipelined batch is written straight to the socket, past the TLS layer, so
+    // an encrypted control connection falls back to one command at a time.
+    fn can_pipeline(&mut self) -> bool {
+        self.pipelining && self.connect().is_ok() && self.tls_mode == Some(FtpTlsMode::Plain)
+    }
+
+    pub fn welcome_message(&self) -> Option<&str> {
+        self.welcome_message.as_deref()
+    }
+
+    // `None` until the first connection is made
+    pub fn tls_mode(&self) -> Option<FtpTlsMode> {
+        self.tls_mode
+    }
+
+    pub fn last_reply(&self) -> Option<&Response> {
+        self.last_reply.as_ref()
+    }
+
+    pub fn cdup(&mut self) -> FtpResult<()> {
+        self.execute_command("cdup", FtpRetry::Reconnect, "CDUP".to_string(), &[200, 250])?;
+        self.update_current_path()
+    }
+
+    pub fn chdir(&mut self, path: &str) -> FtpResult<()> {
+        let path = self.remote_path(path)?;
+        self.execute_command("cwd", FtpRetry::Reconnect, format!("CWD {}", path), &[250])?;
+        self.update_current_path()
+    }
+
+    fn update_current_path(&mut self) -> FtpResult<()> {
+        self.current_path = None;
+        let reply = self.execute_command("pwd", FtpRetry::Reconnect, "PWD".to_string(), &[257])?;
+        let path = parse_pwd_reply(&reply.body).ok_or(FtpError::BadResponse)?;
+        self.current_path = Some(FtpPath::Linux(path));
+        Ok(())
+    }
+
+    pub fn pwd(&mut self) -> FtpResult<String> {
+        if let Some(FtpPath::Linux(path)) = &self.current_path {
+            return Ok(path.clone());
+        }
+
+        self.update_current_path()?;
+        self.pwd()
+    }
+
+    // Paths are normalized before they are sent; on servers advertising TVFS `..`
+    // is also resolved against the tracked working directory, elsewhere it is left
+    // to the server.
+    fn remote_path(&mut self, path: &str) -> FtpResult<String> {
+        let path = normalize_path(path);
+        if !path.split('/').any(|segment| segment == "..") {
+            return Ok(path);
+        }
+
+        // the features are only known once connected
+        self.connect()?;
+        if !self.features.tvfs {
+            return Ok(path);
+        }
+
+        let path = self.absolute_path(Some(&path))?;
+        Ok(resolve_dot_segments(&path))
+    }
+
+    pub(crate) fn absolute_path(&mut self, path: Option<&str>) -> FtpResult<String> {
+        match path {
+            Some(path) if path.starts_with('/') => Ok(path.to_string()),
+            Some(path) if !path.is_empty() && path != "." => Ok(join_path(&self.pwd()?, path)),
+            _ => self.pwd(),
+        }
+    }
+
+    pub fn invalidate_cache(&mut self, path: &str) -> FtpResult<()> {
+        if self.list_cache.is_some() {
+            let key = self.absolute_path(Some(path))?;
+            if let Some(cache) = self.list_cache.as_mut() {
+                cache.invalidate(&key);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn clear_cache(&mut self) {
+        if let Some(cache) = self.list_cache.as_mut() {
+            cache.clear();
+        }
+    }
+
+    pub fn mkdir(&mut self, path: &str) -> FtpResult<()> {
+        let path = self.remote_path(path)?;
+        self.audited(FtpAuditOperation::Mkdir, &path, None, |client| {
+            client.execute_command_with("mkdir", FtpRetry::Reconnect, format!("MKD {}", path), |reply, repeated| done_if_repeated(expect_codes(reply, &[257]), repeated))
+        })?;
+        self.invalidate_cache(parent_path(&path))
+    }
+
+    pub fn rmdir(&mut self, path: &str) -> FtpResult<()> {
+        let path = self.remote_path(path)?;
+        self.audited(FtpAuditOperation::Rmdir, &path, None, |client| {
+            client.execute_command_with("rmdir", FtpRetry::Reconnect, format!("RMD {}", path), |reply, repeated| done_if_repeated(expect_codes(reply, &[250]), repeated))
+        })?;
+        self.invalidate_cache(&path)?;
+        self.invalidate_cache(parent_path(&path))
+    }
+
+    pub fn rm(&mut self, path: &str) -> FtpResult<()> {
+        let path = self.remote_path(path)?;
+        self.audited(FtpAuditOperation::Delete, &path, None, |client| {
+            client.execute_command_with("rm", FtpRetry::Reconnect, format!("DELE {}", path), |reply, repeated| done_if_repeated(expect_codes(reply, &[250]), repeated))
+        })?;
+        self.invalidate_cache(parent_path(&path))
+    }
+
+    // `mode` is sent in octal with SITE CHMOD, which not every server implements.
+    pub fn chmod(&mut self, path: &str, mode: u32) -> FtpResult<()> {
+        let path = self.remote_path(path)?;
+        self.audited(FtpAuditOperation::Chmod, &path, None, |client| {
+            let reply = client.quote_idempotent(&format!("SITE CHMOD {:o} {}", mode, path))?;
+            expect_reply(&reply, 200)
+        })?;
+        self.invalidate_cache(parent_path(&path))
+    }
+
+    // SITE CHOWN, which servers usually only allow to privileged accounts.
+    pub fn chown<O: Into<FtpUnixId>>(&mut self, path: &str, owner: O) -> FtpResult<()> {
+        self.site_change_id(FtpAuditOperation::Chown, "CHOWN", path, owner.into())
+    }
+
+    pub fn chgrp<G: Into<FtpUnixId>>(&mut self, path: &str, group: G) -> FtpResult<()> {
+        self.site_change_id(FtpAuditOperation::Chgrp, "CHGRP", path, group.into())
+    }
+
+    fn site_change_id(&mut self, operation: FtpAuditOperation, verb: &str, path: &str, id: FtpUnixId) -> FtpResult<()> {
+        let path = self.remote_path(path)?;
+        let id = id.to_string();
+        self.audited(operation, &path, Some(&id), |client| {
+            let reply = client.quote(&format!("SITE {} {} {}", verb, id, path))?;
+            expect_reply(&reply, 200)
+        })?;
+        self.invalidate_cache(parent_path(&path))
+    }
+
+    // `target` is stored as given, so a relative one is resolved from the link's
+    // directory. An existing symlink at `link` is replaced; any other entry there
+    // is an `AlreadyExists` error.
+    pub fn symlink(&mut self, target: &str, link: &str) -> FtpResult<()> {
+        let link = self.remote_path(link)?;
+        if !self.supports_site_command("SYMLINK")? {
+            return Err(FtpError::ConnectionError(io::Error::new(io::ErrorKind::Unsupported, "the server doesn't support SITE SYMLINK")));
+        }
+
+        match self.lookup(&link)? {
+            Some(item) if item.ty == FtpItemType::Symlink => self.rm(&link)?,
+            Some(_) => return Err(FtpError::ConnectionError(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", link)))),
+            None => {},
+        }
+        self.audited(FtpAuditOperation::Symlink, &link, Some(target), |client| {
+            let reply = client.quote(&format!("SITE SYMLINK {} {}", target, link))?;
+            expect_reply(&reply, 200)
+        })?;
+        self.invalidate_cache(parent_path(&link))
+    }
+
+    // MFMT where the server has it, ProFTPD's SITE UTIME otherwise. `modified` is
+    // UTC, as the server stores it.
+    pub fn set_modified(&mut self, path: &str, modified: NaiveDateTime) -> FtpResult<()> {
+        let path = self.remote_path(path)?;
+        let time = modified.format("%Y%m%d%H%M%S");
+
+        if self.supports_command("MFMT")? {
+            let reply = self.quote(&format!("MFMT {} {}", time, path))?;
+            expect_reply(&reply, 213)?;
+        } else if self.supports_site_command("UTIME")? {
+            let reply = self.quote(&format!("SITE UTIME {} {}", time, path))?;
+            expect_reply(&reply, 200)?;
+        } else {
+            return Err(FtpError::ConnectionError(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the server supports neither MFMT nor SITE UTIME",
+            )));
+        }
+
+        self.invalidate_cache(parent_path(&path))
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) -> FtpResult<()> {
+        let from = self.remote_path(from)?;
+        let to = self.remote_path(to)?;
+        self.audited(FtpAuditOperation::Rename, &from, Some(&to), |client| {
+            client.execute_command("rename", FtpRetry::Never, format!("RNFR {}", from), &[350])?;
+            client.execute_command("rename", FtpRetry::Never, format!("RNTO {}", to), &[250])
+        })?;
+        self.invalidate_cache(&from)?;
+        self.invalidate_cache(parent_path(&from))?;
+        self.invalidate_cache(parent_path(&to))
+    }
+
+    // Batches run on one connection and a failed item doesn't stop the rest;
+    // results are in input order. With pipelining enabled the commands of the
+    // whole batch are pipelined.
+    pub fn rename_many(&mut self, renames: &[(&str, &str)]) -> Vec<FtpResult<()>> {
+        if !self.can_pipeline() {
+            return renames.iter().map(|(from, to)| self.rename(from, to)).collect();
+        }
+
+        let renames: Vec<FtpResult<(String, String)>> = renames.iter()
+            .map(|(from, to)| Ok((self.remote_path(from)?, self.remote_path(to)?)))
+            .collect();
+        let commands: Vec<String> = renames.iter()
+            .flatten()
+            .flat_map(|(from, to)| vec![format!("RNFR {}", from), format!("RNTO {}", to)])
+            .collect();
+
+        let mut replies = self.quote_many(&commands).map(Vec::into_iter);
+        let results: Vec<FtpResult<()>> = renames.iter()
+            .map(|rename| {
+                rename.as_ref().map_err(batch_error)?;
+                let replies = replies.as_mut().map_err(|e| batch_error(e))?;
+                let (rnfr, rnto) = replies.next().zip(replies.next()).ok_or(FtpError::BadResponse)?;
+                expect_reply(&rnfr, 350)?;
+                expect_reply(&rnto, 250)
+            })
+            .collect();
+
+        for (rename, result) in renames.iter().zip(&results) {
+            if let Ok((from, to)) = rename {
+                self.audit(FtpAuditOperation::Rename, from, Some(to), None, result.as_ref().map(|_| ()));
+                for path in [from.as_str(), parent_path(from), parent_path(to)] {
+                    let _ = self.invalidate_cache(path);
+                }
+            }
+        }
+
+        results
+    }
+
+    pub fn delete_many(&mut self, paths: &[&str]) -> Vec<FtpResult<()>> {
+        if !self.can_pipeline() {
+            return paths.iter().map(|path| self.rm(path)).collect();
+        }
+
+        let paths: Vec<FtpResult<String>> = paths.iter().map(|path| self.remote_path(path)).collect();
+        let commands: Vec<String> = paths.iter().flatten().map(|path| format!("DELE {}", path)).collect();
+
+        let mut replies = self.quote_many(&commands).map(Vec::into_iter);
+        let results: Vec<FtpResult<()>> = paths.iter()
+            .map(|path| {
+                path.as_ref().map_err(batch_error)?;
+                let replies = replies.as_mut().map_err(|e| batch_error(e))?;
+                expect_reply(&replies.next().ok_or(FtpError::BadResponse)?, 250)
+            })
+            .collect();
+
+        for (path, result) in paths.iter().zip(&results) {
+            if let Ok(path) = path {
+                self.audit(FtpAuditOperation::Delete, path, None, None, result.as_ref().map(|_| ()));
+                let _ = self.invalidate_cache(parent_path(path));
+            }
+        }
+
+        results
+    }
+
+    pub(crate) fn transfer_type(&mut self, file_type: FileType) -> FtpResult<()> {
+        let file_type = file_type.to_string();
+        self.execute_command("transfer_type", FtpRetry::Reconnect, format!("TYPE {}", file_type), &[200])?;
+        Ok(())
+    }
+
+    // `retry` may only be `Reconnect` when `reader` starts over from scratch each
+    // time it is called, since a retry delivers the file again from the beginning.
+    pub(crate) fn retr<T, F>(&mut self, path: &str, retry: FtpRetry, reader: &mut F) -> FtpResult<T>
+    where
+        F: FnMut(&mut dyn Read) -> FtpResult<T>
+    {
+        let path = self.remote_path(path)?;
+        let mut value = None;
+        self.execute("retr", retry, |ftp| ftp.retr(&path, &mut |stream| {
+            value = Some(reader(stream)?);
+            Ok(())
+        }))?;
+        value.ok_or(FtpError::BadResponse)
+    }
+
+    // Like `retr`, with the data starting at `offset` on every attempt.
+    pub(crate) fn retr_from<T, F>(&mut self, path: &str, offset: u64, retry: FtpRetry, reader: &mut F) -> FtpResult<T>
+    where
+        F: FnMut(&mut dyn Read) -> FtpResult<T>
+    {
+        let path = self.remote_path(path)?;
+        let offset = offset as usize;
+        let mut value = None;
+        self.execute("retr_from", retry, |ftp| ftp.retr_from(&path, offset, &mut |stream| {
+            value = Some(reader(stream)?);
+            Ok(())
+        }))?;
+        value.ok_or(FtpError::BadResponse)
+    }
+
+    pub(crate) fn put_file<R, F>(&mut self, path: &str, open: &mut F) -> FtpResult<u64>
+    where
+        R: Read,
+        F: FnMut() -> FtpResult<R>
+    {
+        let path = self.remote_path(path)?;
+        // not repeated: the file may be stored already when the reply is lost
+        self.execute("put_file", FtpRetry::Never, |ftp| ftp.put_file(&path, &mut open()?))
+    }
+
+    // Not retried after a reconnect: a partial append can't be undone.
+    pub(crate) fn append_file(&mut self, path: &str, reader: &mut dyn Read) -> FtpResult<u64> {
+        let path = self.remote_path(path)?;
+        self.close_if_idle();
+        self.connect()?;
+        self.connected_stream()?.append_file(&path, reader)
+    }
+
+    // FEAT only lists extensions, and some servers leave even those out, so the
+    // verbs from HELP (asked once per client) are consulted too.
+    pub fn supports_command(&mut self, verb: &str) -> FtpResult<bool> {
+        self.connect()?;
+        let verb = verb.to_ascii_uppercase();
+        if self.features.has_command(&verb) {
+            return Ok(true);
+        }
+        Ok(self.help_commands()?.contains(&verb))
+    }
+
+    fn help_commands(&mut self) -> FtpResult<&BTreeSet<String>> {
+        if self.help_commands.is_none() {
+            let reply = self.quote_idempotent("HELP")?;
+            self.help_commands = Some(match reply.code / 100 {
+                2 => parse_help_reply(&reply.body, 3..=4),
+                _ => BTreeSet::new(),
+            });
+        }
+        Ok(self.help_commands.get_or_insert_with(BTreeSet::new))
+    }
+
+    fn site_help_commands(&mut self) -> FtpResult<&BTreeSet<String>> {
+        if self.site_commands.is_none() {
+            let reply = self.quote_idempotent("SITE HELP")?;
+            self.site_commands = Some(match reply.code / 100 {
+                2 => parse_help_reply(&reply.body, 2..=16),
+                _ => BTreeSet::new(),
+            });
+        }
+        Ok(self.site_commands.get_or_insert_with(BTreeSet::new))
+    }
+
+    // Like `supports_command` for SITE subcommands: ProFTPD lists them in FEAT as
+    // `SITE <verb>`, other servers only answer SITE HELP.
+    pub fn supports_site_command(&mut self, verb: &str) -> FtpResult<bool> {
+        self.connect()?;
+        let verb = verb.to_ascii_uppercase();
+        if self.features.has_extension(&format!("SITE {}", verb)) {
+            return Ok(true);
+        }
+        Ok(self.site_help_commands()?.contains(&verb))
+    }
+
+    // The FEAT reply of the current connection, for extensions `capabilities`
+    // doesn't cover (see `FtpClientFeatures::extension_params`).
+    pub fn features(&mut self) -> FtpResult<&FtpClientFeatures> {
+        self.connect()?;
+        Ok(&self.features)
+    }
+
+    // FEAT alone costs nothing, it was read on connect; `probe` also asks HELP and
+    // SITE HELP once per client, for servers that leave commands out of FEAT.
+    pub fn capabilities(&mut self, probe: bool) -> FtpResult<FtpCapabilities> {
+        self.connect()?;
+        let features = self.features.clone();
+        let help = if probe { self.help_commands()?.clone() } else { BTreeSet::new() };
+        let mut site: BTreeSet<String> = features.site_commands().collect();
+        if probe {
+            site.extend(self.site_help_commands()?.iter().cloned());
+        }
+
+        let has = |verb: &str| features.has_command(verb) || help.contains(verb);
+        let hash_algorithms = features.hash_algorithms();
+
+        Ok(FtpCapabilities {
+            auth_tls: features.auth_tls,
+            utf8: features.utf8,
+            tvfs: features.tvfs,
+            mdtm: has("MDTM"),
+            mfmt: has("MFMT"),
+            size: has("SIZE"),
+            rest_stream: features.rest_stream,
+            mode_z: features.extension_params("MODE").is_some_and(|modes| modes.split_whitespace().any(|mode| mode.eq_ignore_ascii_case("Z"))),
+            mlst: features.mlst.clone(),
+            hash: !hash_algorithms.is_empty() || has("HASH"),
+            hash_algorithms,
+            x_hashes: X_HASH_COMMANDS.iter().filter(|verb| has(verb)).map(|verb| verb.to_string()).collect(),
+            site,
+        })
+    }
+
+    pub(crate) fn supports_comb(&mut self) -> FtpResult<bool> {
+        self.connect()?;
+        Ok(self.features.comb)
+    }
+
+    // Joins `parts` in order into `path` on the server, which removes the parts.
+    pub(crate) fn combine(&mut self, path: &str, parts: &[String]) -> FtpResult<()> {
+        let command = std::iter::once(path)
+            .chain(parts.iter().map(String::as_str))
+            .map(|name| self.remote_path(name).map(|name| format!("\"{}\"", name)))
+            .collect::<FtpResult<Vec<_>>>()?
+            .join(" ");
+
+        let reply = self.quote(&format!("COMB {}", command))?;
+        if reply.code / 100 != 2 {
+            return Err(FtpError::UnexpectedResponse(reply));
+        }
+        self.invalidate_cache(parent_path(path))
+    }
+
+    pub(crate) fn retr_stream(&mut self, path: &str) -> FtpResult<Box<dyn Read + Send>> {
+        let path = self.remote_path(path)?;
+        self.execute("retr_as_stream", FtpRetry::Reconnect, |ftp| ftp.retr_as_stream(&path))
+    }
+
+    pub(crate) fn finalize_retr_stream(&mut self, stream: Box<dyn Read + Send>) -> FtpResult<()> {
+        self.connected_stream()?.finalize_retr_stream(stream)
+    }
+
+    pub(crate) fn put_stream(&mut self, path: &str) -> FtpResult<Box<dyn Write + Send>> {
+        let path = self.remote_path(path)?;
+        self.execute("put_with_stream", FtpRetry::Never, |ftp| ftp.put_with_stream(&path))
+    }
+
+    pub(crate) fn finalize_put_stream(&mut self, stream: Box<dyn Write + Send>) -> FtpResult<()> {
+        self.connected_stream()?.finalize_put_stream(stream)
+    }
+
+    pub(crate) fn rate_limit(&self) -> RateLimit {
+        self.settings.rate_limit()
+    }
+
+    pub(crate) fn shared_rate_limiter(&self) -> Option<FtpRateLimiter> {
+        self.shared_rate_limiter.clone()
+    }
+
+    // Holds back the next transfer while the shared limiter's schedule pauses them.
+    pub(crate) fn wait_while_paused(&self) {
+        if let Some(limiter) = self.shared_rate_limiter.as_ref() {
+            limiter.wait_while_paused();
+        }
+    }
+
+    pub(crate) fn overwrite_policy(&self) -> OverwritePolicy {
+        self.settings.overwrite_policy()
+    }
+
+    pub(crate) fn transfer_buffer_size(&self) -> usize {
+        let size = self.settings.transfer_buffer_size().max(1);
+        size.div_ceil(4096) * 4096
+    }
+
+    // The buffer is kept between transfers; callers hand it back with `restore_transfer_buffer`.
+    pub(crate) fn take_transfer_buffer(&mut self) -> Vec<u8> {
+        let size = self.transfer_buffer_size();
+        let mut buffer = std::mem::take(&mut self.transfer_buffer);
+        buffer.resize(size, 0);
+        buffer
+    }
+
+    pub(crate) fn restore_transfer_buffer(&mut self, buffer: Vec<u8>) {
+        self.transfer_buffer = buffer;
+    }
+
+    fn connected_stream(&mut self) -> FtpResult<&mut Box<dyn FtpTransport>> {
+        self.ftp.as_mut().ok_or_else(|| FtpError::ConnectionError(io::Error::from(io::ErrorKind::NotConnected)))
+    }
+
+    fn list_mlsd(&mut self, path: Option<&str>) -> FtpResult<FtpList> {
+        let mut strings = FtpStringInterner::default();
+        self.execute("mlsd", FtpRetry::Reconnect, |ftp| ftp.mlsd(path))?
+            .iter()
+            .try_fold(FtpList::default(), |mut list, line| {
+                list.push(parse_mlst_line_interned(line, &mut strings).map_err(|_| FtpError::BadResponse)?);
+                Ok(list)
+            })
+    }
+
+    // NLST only names the entries, so each one is reported as a file with no
+    // other facts.
+    fn list_nlst(&mut self, path: Option<&str>) -> FtpResult<FtpList> {
+        Ok(self.names(path.unwrap_or_default())?
+            .into_iter()
+            .fold(FtpList::default(), |mut list, name| {
+                list.push(FtpItem::new(name, FtpItemType::File));
+                list
+            }))
+    }
+
+    fn list_stat(&mut self, path: Option<&str>) -> FtpResult<FtpList> {
+        let path = self.absolute_path(path)?;
+        Ok(self.stat(&path)?
+            .into_iter()
+            .fold(FtpList::default(), |mut list, item| {
+                list.push(item);
+                list
+            }))
+    }
+
+    // STAT over the control connection: works when data connections are blocked.
+    pub fn stat(&mut self, path: &str) -> FtpResult<Vec<FtpItem>> {
+        let command = format!("STAT {}", self.remote_path(path)?);
+        let reply = self.execute_command("command", FtpRetry::Reconnect, command, &[211, 212, 213])?;
+        Ok(parse_stat_reply(&reply.body, self.settings.list_timezone()))
+    }
+
+    pub fn server_status(&mut self) -> FtpResult<FtpServerStatus> {
+        let reply = self.execute_command("command", FtpRetry::Reconnect, "STAT".to_string(), &[211, 212, 213])?;
+        Ok(FtpServerStatus::parse(&reply.body))
+    }
+
+    fn list_list(&mut self, path: Option<&str>) -> FtpResult<FtpList> {
+        let argument = match path {
+            Some(path) if self.settings.list_hidden() => Some(format!("-a {}", path)),
+            None if self.settings.list_hidden() => Some("-a".to_string()),
+            _ => path.map(str::to_string),
+        };
+        let lines = self.execute("list", FtpRetry::Reconnect, |ftp| ftp.list(argument.as_deref()))?;
+
+        // the LIST format is sniffed once per client from the first listing that has entries
+        if self.list_format.is_none() {
+            self.list_format = FtpListFormat::detect(&lines);
+        }
+        let format = self.list_format.unwrap_or(FtpListFormat::Unix);
+
+        lines.iter()
+            .filter(|line| !line.trim().is_empty() && !is_total_line(line))
+            .try_fold(FtpList::default(), |mut list, line| {
+                list.push(self.list_time_to_utc(self.parse_list_line(format, line)?));
+                Ok(list)
+            })
+    }
+
+    // LIST and STAT lines carry the wall-clock time of the server
+    fn list_time_to_utc(&self, mut item: FtpItem) -> FtpItem {
+        let timezone = self.settings.list_timezone();
+        item.modified = item.modified.map(|modified| timezone.to_utc(modified));
+        item
+    }
+
+    fn parse_list_line(&self, format: FtpListFormat, line: &str) -> FtpResult<FtpItem> {
+        if let Some(item) = self.list_parsers.iter().find_map(|parser| parser.parse_line(line)) {
+            return Ok(item);
+        }
+
+        format.parse_line(line)
+            .ok()
+            .or_else(|| self.fallback_list_parsers.iter().find_map(|parser| parser.parse_line(line)))
+            .ok_or(FtpError::BadResponse)
+    }
+
+    fn get_list_mode(&mut self) -> FtpClientListMode {
+        match self.effective_list_mode {
+            Some(lm) => return lm,
+            _ => {},
+        };
+
+        if self.settings_list_mode.is_none() {
+            self.settings_list_mode = self.settings.list_mode();
+        };
+
+        match self.settings_list_mode {
+            Some(lm) => {
+                self.effective_list_mode = self.settings_list_mode;
+                return lm;
+            },
+            _ => {},
+        };
+
+        FtpClientListMode::List
+    }
+
+    fn list_impl(&mut self, path: Option<&str>) -> FtpResult<FtpList> {
+        let path = match path {
+            Some(path) => Some(self.remote_path(path)?),
+            None => None,
+        };
+        let path = path.as_deref();
+
+        let dir = self.absolute_path(path)?;
+        let cache_key = self.list_cache.as_ref().map(|_| dir.clone());
+
+        if let (Some(cache), Some(key)) = (self.list_cache.as_mut(), cache_key.as_ref()) {
+            if let Some(list) = cache.get(key) {
+                return Ok(self.filter_list(list));
+            }
+        }
+
+        let list = match self.get_list_mode() {
+            FtpClientListMode::List => self.list_list(path),
+            FtpClientListMode::Nlst => self.list_nlst(path),
+            FtpClientListMode::Mlsd => self.list_mlsd(path),
+            FtpClientListMode::Stat => self.list_stat(path),
+        }?;
+        let list = with_paths(list, &dir);
+
+        if let (Some(cache), Some(key)) = (self.list_cache.as_mut(), cache_key) {
+            cache.insert(key, list.clone());
+        }
+
+        Ok(self.filter_list(list))
+    }
+
+    fn filter_list(&self, mut list: FtpList) -> FtpList {
+        if self.hide_dotfiles {
+            list.items.retain(|item| !item.name.starts_with('.'));
+        }
+        list
+    }
+
+    pub fn list(&mut self) -> FtpResult<FtpList> {
+        self.list_impl(None)
+    }
+
+    // MLSD with only `facts` requested, which shrinks the listings of huge
+    // directories when little more than type, size and modify is needed. Facts
+    // the server doesn't offer are left out, and the previous fact selection is
+    // restored afterwards. The listing bypasses the cache, which must not serve
+    // it to callers expecting every fact. Without MLSD this is a plain `list_path`.
+    pub fn list_path_with_facts(&mut self, path: &str, facts: &[MlstFact]) -> FtpResult<FtpList> {
+        self.connect()?;
+        let (previous, facts): (Vec<MlstFact>, Vec<MlstFact>) = match self.features.mlst.as_ref() {
+            Some(mlst) => (
+                mlst.iter().filter(|(_, enabled)| *enabled).map(|(fact, _)| fact.clone()).collect(),
+                facts.iter().filter(|fact| mlst.iter().any(|(offered, _)| offered == *fact)).cloned().collect(),
+            ),
+            None => return self.list_path(path),
+        };
+
+        let path = self.remote_path(path)?;
+        let dir = self.absolute_path(Some(&path))?;
+        let unchanged = previous.len() == facts.len() && facts.iter().all(|fact| previous.contains(fact));
+        if unchanged {
+            return self.list_mlsd(Some(&path)).map(|list| self.filter_list(with_paths(list, &dir)));
+        }
+
+        self.select_mlst_facts(&facts)?;
+        let list = self.list_mlsd(Some(&path));
+        let restored = self.select_mlst_facts(&previous);
+
+        let list = list?;
+        restored?;
+        Ok(self.filter_list(with_paths(list, &dir)))
+    }
+
+    // Hands the entries of `path` to `callback` in chunks of at most `chunk_size`,
+    // each parsed only when its turn comes; returning `false` stops, and the rest
+    // of the listing is never read. Over TLS the transport still reads the MLSD
+    // reply whole, so there this bounds the parsed items, not the raw lines.
+    // Bypasses the cache; without MLSD the chunks come from a plain `list_path`.
+    pub fn list_path_chunked<F>(&mut self, path: &str, chunk_size: usize, mut callback: F) -> FtpResult<()>
+    where
+        F: FnMut(Vec<FtpItem>) -> FtpResult<bool>,
+    {
+        self.connect()?;
+        let chunk_size = chunk_size.max(1);
+
+        if self.features.mlst.is_none() {
+            let list = self.list_path(path)?;
+            for chunk in list.items.chunks(chunk_size) {
+                if !callback(chunk.to_vec())? {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        let path = self.remote_path(path)?;
+        let dir = self.absolute_path(Some(&path))?;
+
+        if self.tls_mode != Some(FtpTlsMode::Plain) {
+            let lines = self.execute("mlsd", FtpRetry::Reconnect, |ftp| ftp.mlsd(Some(&path)))?;
+            return self.mlsd_chunks(&mut lines.into_iter().map(Ok), &dir, chunk_size, &mut callback).map(|_| ());
+        }
+
+        let mut stream = self.execute("mlsd", FtpRetry::Reconnect, |ftp| ftp.mlsd_as_stream(Some(&path)))?;
+        let finished = self.mlsd_chunks(&mut stream.by_ref().lines(), &dir, chunk_size, &mut callback);
+        match finished {
+            Ok(true) => self.connected_stream()?.finalize_mlsd_stream(stream),
+            // the listing was abandoned halfway, so the control connection can't be trusted
+            result => {
+                drop(stream);
+                self.disconnect();
+                result.map(|_| ())
+            },
+        }
+    }
+
+    // Returns whether `lines` ran out, rather than `callback` asking to stop.
+    fn mlsd_chunks<I, F>(&self, lines: &mut I, dir: &str, chunk_size: usize, callback: &mut F) -> FtpResult<bool>
+    where
+        I: Iterator<Item = io::Result<String>>,
+        F: FnMut(Vec<FtpItem>) -> FtpResult<bool>,
+    {
+        let mut strings = FtpStringInterner::default();
+
+        loop {
+            let mut items = Vec::with_capacity(chunk_size);
+            for line in lines.by_ref() {
+                let line = line.map_err(FtpError::ConnectionError)?;
+                if line.is_empty() {
+                    continue;
+                }
+
+                let mut item = parse_mlst_line_interned(&line, &mut strings).map_err(|_| FtpError::BadResponse)?;
+                let hidden = self.hide_dotfiles && item.name.starts_with('.');
+                if hidden || matches!(item.ty, FtpItemType::CurrentDir | FtpItemType::ParentDir) {
+                    continue;
+                }
+                item.path = Some(join_path(dir, &item.name));
+                items.push(item);
+                if items.len() == chunk_size {
+                    break;
+                }
+            }
+
+            if items.is_empty() {
+                return Ok(true);
+            }
+            if !callback(items)? {
+                return Ok(false);
+            }
+        }
+    }
+
+    fn select_mlst_facts(&mut self, facts: &[MlstFact]) -> FtpResult<()> {
+        let names: String = facts.iter().map(|fact| format!("{};", fact.name())).collect();
+        let reply = self.quote(&format!("OPTS MLST {}", names))?;
+        expect_reply(&reply, 200)?;
+
+        if let Some(mlst) = self.features.mlst.as_mut() {
+            for (fact, enabled) in mlst.iter_mut() {
+                *enabled = facts.contains(fact);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn list_path(&mut self, path: &str) -> FtpResult<FtpList> {
+        self.list_impl(Some(path))
+    }
+
+    // `LIST <pattern>` for servers that glob server-side; when the server rejects
+    // the pattern or matches nothing, the directory listing is filtered locally.
+    // Only the last path segment may contain wildcards.
+    pub fn list_matching(&mut self, pattern: &str) -> FtpResult<FtpList> {
+        let dir = parent_path(pattern);
+        let name_pattern = pattern.rsplit('/').next().unwrap_or(pattern);
+        let base = self.absolute_path(Some(dir))?;
+
+        let globbed = match self.list_list(Some(pattern)) {
+            Ok(list) => Some(list).filter(|list| !list.items.is_empty()),
+            Err(FtpError::UnexpectedResponse(_)) | Err(FtpError::BadResponse) => None,
+            Err(e) => return Err(e),
+        };
+
+        let list = match globbed {
+            Some(list) => list,
+            None if dir.is_empty() => self.list()?,
+            None => self.list_path(dir)?,
+        };
+
+        // some servers ignore the pattern, others report matches with their directory
+        let matching = list.items.into_iter()
+            .map(|mut item| {
+                if let Some(pos) = item.name.rfind('/') {
+                    item.name = item.name[pos + 1..].to_string();
+                }
+                item.path = Some(join_path(&base, &item.name));
+                item
+            })
+            .filter(|item| wildcard_match(name_pattern, &item.name))
+            .fold(FtpList::default(), |mut list, item| {
+                list.push(item);
+                list
+            });
+
+        Ok(self.filter_list(matching))
+    }
+
+    // One `LIST -R` instead of a listing per directory; keys are absolute paths.
+    pub fn list_recursive(&mut self, path: &str) -> FtpResult<BTreeMap<String, FtpList>> {
+        let root = self.remote_path(path)?;
+        let root = self.absolute_path(Some(&root))?;
+        let argument = format!("-R {}", root);
+        let lines = self.execute("list", FtpRetry::Reconnect, |ftp| ftp.list(Some(&argument)))?;
+        parse_recursive_list(&root, &lines, self.settings.list_timezone())
+    }
+
+    // MDTM when the server advertises it, the listing's modify fact otherwise
+    pub fn modified(&mut self, path: &str) -> FtpResult<Option<NaiveDateTime>> {
+        // the features are only known once connected
+        self.connect()?;
+        if self.features.mdtm {
+            let path = self.remote_path(path)?;
+            return self.mdtm(&path).map(Some);
+        }
+
+        Ok(self.lookup(path)?.and_then(|item| item.modified))
+    }
+
+    fn mdtm(&mut self, path: &str) -> FtpResult<NaiveDateTime> {
+        let reply = self.execute_command("mdtm", FtpRetry::Reconnect, format!("MDTM {}", path), &[213])?;
+        parse_mlst_date(reply_argument(&reply)).ok_or(FtpError::BadResponse)
+    }
+
+    // SIZE when the server advertises it, the listing's size otherwise
+    pub fn size(&mut self, path: &str) -> FtpResult<Option<u64>> {
+        self.connect()?;
+        if self.features.size {
+            let path = self.remote_path(path)?;
+            let reply = self.execute_command("size", FtpRetry::Reconnect, format!("SIZE {}", path), &[213])?;
+            return reply_argument(&reply).parse().map(Some).map_err(|_| FtpError::BadResponse);
+        }
+
+        Ok(self.lookup(path)?.and_then(|item| item.size))
+    }
+
+    // How far the server clock runs ahead of the local one.
+    pub fn clock_offset(&self) -> chrono::Duration {
+        self.clock_offset
+    }
+
+    pub fn set_clock_offset(&mut self, offset: chrono::Duration) {
+        self.clock_offset = offset;
+    }
+
+    // Measures the offset by creating an empty `probe_path`, reading its MDTM and
+    // removing it again.
+    pub fn calibrate_clock(&mut self, probe_path: &str) -> FtpResult<chrono::Duration> {
+        let probe_path = &self.remote_path(probe_path)?;
+        self.transfer_type(FileType::Binary)?;
+        let before = Utc::now().naive_utc();
+        self.put_file(probe_path, &mut || Ok(io::empty()))?;
+        let after = Utc::now().naive_utc();
+
+        let server = self.mdtm(probe_path);
+        self.rm(probe_path)?;
+
+        let local = before + (after - before) / 2;
+        self.clock_offset = server? - local;
+        Ok(self.clock_offset)
+    }
+
+    pub fn names(&mut self, path: &str) -> FtpResult<Vec<String>> {
+        let path = if path.is_empty() { None } else { Some(path) };
+        Ok(self.execute("nlst", FtpRetry::Reconnect, |ftp| ftp.nlst(path))?
+            .into_iter()
+            .filter_map(|name| {
+                // some servers answer NLST with paths rather than bare names
+                let name = name.trim_end_matches('/').rsplit('/').next().unwrap_or_default().to_string();
+                if name.is_empty() || name == "." || name == ".." { None } else { Some(name) }
+            })
+            .collect())
+    }
+
+    pub fn lookup(&mut self, path: &str) -> FtpResult<Option<FtpItem>> {
+        let name = path.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+        if name.is_empty() {
+            return Ok(None);
+        }
+
+        let parent = parent_path(path);
+        let list = if parent.is_empty() {
+            self.list()?
+        } else {
+            self.list_path(parent)?
+        };
+
+        Ok(list.items.into_iter().find(|item| item.name == name))
+    }
+}
+
+fn with_paths(mut list: FtpList, dir: &str) -> FtpList {
+    for item in list.items.iter_mut() {
+        item.path = Some(join_path(dir, &item.name));
+    }
+    if let Some(current) = list.current.as_mut() {
+        current.path = Some(dir.to_string());
+    }
+    for alias in list.current_aliases.iter_mut() {
+        alias.path = Some(dir.to_string());
+    }
+    if list.parent.is_none() && dir != "/" {
+        list.parent = Some(FtpItem::new("..", FtpItemType::ParentDir));
+    }
+    if let Some(parent) = list.parent.as_mut() {
+        let parent_dir = parent_path(dir);
+        parent.path = Some(if parent_dir.is_empty() { "/".to_string() } else { parent_dir.to_string() });
+    }
+    list
+}
+
+// MKD, RMD and DELE are repeated when the connection drops before the reply. A
+// 550 to the repeat ("exists", "not found") then means the first one went through.
+fn done_if_repeated(result: FtpResult<Response>, repeated: bool) -> FtpResult<Response> {
+    match result {
+        Err(FtpError::UnexpectedResponse(response)) if repeated && response.code == 550 => Ok(response),
+        result => result,
+    }
+}
+
+fn expect_codes(reply: Response, codes: &[u32]) -> FtpResult<Response> {
+    if codes.contains(&reply.code) {
+        Ok(reply)
+    } else {
+        Err(FtpError::UnexpectedResponse(reply))
+    }
+}
+
+// The text after the code of a single-line reply, e.g. the time in "213 20210101000000".
+fn reply_argument(reply: &Response) -> &str {
+    reply.body.get(4..).unwrap_or_default().trim()
+}
+
+// `257 "/home/user" is the current directory`, with quotes in the path doubled
+fn parse_pwd_reply(body: &str) -> Option<String> {
+    let start = body.find('"')? + 1;
+    let end = body.rfind('"').filter(|end| *end >= start)?;
+    Some(body[start..end].replace("\"\"", "\""))
+}
+
+fn expect_reply(reply: &Response, code: u32) -> FtpResult<()> {
+    if reply.code == code {
+        Ok(())
+    } else {
+        Err(FtpError::UnexpectedResponse(reply.clone()))
+    }
+}
+
+// FtpError isn't Clone; every item of a failed pipelined batch gets a copy of it.
+fn batch_error(e: &FtpError) -> FtpError {
+    match e {
+        FtpError::UnexpectedResponse(response) => FtpError::UnexpectedResponse(response.clone()),
+        e => FtpError::ConnectionError(io::Error::other(e.to_string())),
+    }
+}
+
+fn parse_stat_reply(body: &str, timezone: FtpListTimezone) -> Vec<FtpItem> {
+    body.lines()
+        .filter(|line| {
+            // skip the "213-" / "213 " status lines framing the listing
+            let bytes = line.as_bytes();
+            !(bytes.len() >= 4 && bytes[..3].iter().all(u8::is_ascii_digit) && (bytes[3] == b'-' || bytes[3] == b' '))
+        })
+        .filter_map(|line| list::File::from_str(line.trim()).ok())
+        .map(|file| {
+            let mut item = list_to_ftp(&file);
+            item.modified = item.modified.map(|modified| timezone.to_utc(modified));
+            item
+        })
+        .collect()
+}
+
+// Verbs are runs of letters of the given lengths, 3-4 for commands; ProFTPD marks
+// unimplemented ones with a trailing '*'.
+fn parse_help_reply(body: &str, lengths: std::ops::RangeInclusive<usize>) -> BTreeSet<String> {
+    // the lines with the status code are prose ("214-The following commands are
+    // recognized", "214 Help OK"), whatever their count; the verbs are between them
+    let is_status = |line: &str| {
+        let bytes = line.as_bytes();
+        bytes.len() >= 4 && bytes[..3].iter().all(u8::is_ascii_digit) && matches!(bytes[3], b'-' | b' ')
+    };
+
+    body.lines()
+        .filter(|line| !is_status(line))
+        .flat_map(|line| line.split_whitespace())
+        .filter(|word| lengths.contains(&word.len()) && word.bytes().all(|b| b.is_ascii_alphabetic()))
+        .map(str::to_ascii_uppercase)
+        .collect()
+}
+
+fn parse_recursive_list(root: &str, lines: &[String], timezone: FtpListTimezone) -> FtpResult<BTreeMap<String, FtpList>> {
+    let mut result = BTreeMap::new();
+    let mut current = root.to_string();
+
+    for line in lines {
+        let line = line.trim_end();
+        if line.is_empty() || is_total_line(line) {
+            continue;
+        }
+
+        if let Some(header) = line.strip_suffix(':') {
+            let header = header.trim_start_matches("./");
+            current = match header {
+                "." | "" => root.to_string(),
+                _ if header.starts_with('/') => header.to_string(),
+                _ => join_path(root, header),
+            };
+            result.entry(current.clone()).or_default();
+            continue;
+        }
+
+        let mut item = list::File::from_str(line).map(|f| list_to_ftp(&f)).map_err(|_| FtpError::BadResponse)?;
+        item.modified = item.modified.map(|modified| timezone.to_utc(modified));
+        result.entry(current.clone()).or_default().push(item);
+    }
+
+    Ok(result)
+}
+
+impl Drop for FtpClient {
+    fn drop(&mut self) {
+        let _ = self.quit();
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::audit::{FtpAuditOperation, FtpAuditRecord, FtpAuditSink};
+    use crate::mock::support::*;
+    use crate::transport::fake::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
     fn test() {
-        let mut client = FtpClient::new(settings());
-        dbg!(client.list());
+        let server = server();
+        let mut client = FtpClient::new(settings(&server));
+
+        let list = client.list().unwrap();
+        let names: Vec<_> = list.items.iter().map(|item| item.name.as_str()).collect();
+        assert_eq!(names, vec!["data", "readme"]);
+
+        assert_eq!(client.names("/data").unwrap(), vec!["a.txt"]);
+        assert_eq!(client.list_matching("/data/*.txt").unwrap().items.len(), 1);
+
+        client.chdir("data").unwrap();
+        assert_eq!(client.pwd().unwrap(), "/data");
+        assert_eq!(client.download_string("a.txt", None).unwrap(), "hello");
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let server = server();
+        let mut client = FtpClient::new(settings(&server));
+
+        client.chdir("/data").unwrap();
+        assert_eq!(server.connections(), 1);
+
+        server.drop_connections();
+
+        let list = client.list().unwrap();
+        assert_eq!(list.items.len(), 1);
+        assert_eq!(list.items[0].name, "a.txt");
+        assert_eq!(server.connections(), 2);
+    }
+
+    #[test]
+    fn test_fake_batch() {
+        let (mut client, state) = fake_client(None);
+
+        let results = client.delete_many(&["a.txt", "b.txt"]);
+        assert!(results.iter().all(Result::is_ok));
+
+        let results = client.rename_many(&[("c.txt", "d.txt")]);
+        assert_eq!(results.len(), 1);
+
+        let commands = state.lock().unwrap().commands.clone();
+        assert!(commands.ends_with(&["DELE a.txt".to_string(), "DELE b.txt".to_string(), "RNFR c.txt".to_string(), "RNTO d.txt".to_string()]));
+
+        client.set_pipelining(true);
+        state.lock().unwrap().replies = vec![
+            Response { code: 250, body: "250 Deleted".to_string() },
+            Response { code: 550, body: "550 No such file".to_string() },
+        ];
+        let results = client.delete_many(&["a.txt", "b.txt"]);
+        assert!(results[0].is_ok());
+        assert!(matches!(&results[1], Err(FtpError::UnexpectedResponse(Response { code: 550, .. }))));
+
+        client.rename_many(&[("./c.txt", "d//e.txt")]);
+        let commands = state.lock().unwrap().commands.clone();
+        assert!(commands.ends_with(&["RNFR c.txt".to_string(), "RNTO d/e.txt".to_string()]));
+    }
+
+    #[test]
+    fn test_fake_normalize_path() {
+        let (mut client, state) = fake_client(None);
+        state.lock().unwrap().cwd = "/home/user".to_string();
+
+        client.rm("a//b/./c.txt").unwrap();
+        client.chdir("../tmp/").unwrap();
+        assert!(state.lock().unwrap().commands.ends_with(&["DELE a/b/c.txt".to_string(), "CWD ../tmp".to_string(), "PWD".to_string()]));
+
+        state.lock().unwrap().features = vec!["TVFS".to_string()];
+        client.disconnect();
+        client.has_feat = false;
+        client.chdir("/home/user/../tmp/").unwrap();
+        assert_eq!(state.lock().unwrap().cwd, "/home/tmp");
+    }
+
+    #[test]
+    fn test_fake_audit() {
+        #[derive(Debug, Default)]
+        struct Records(Mutex<Vec<FtpAuditRecord>>);
+
+        impl FtpAuditSink for Records {
+            fn record(&self, record: &FtpAuditRecord) {
+                self.0.lock().unwrap().push(record.clone());
+            }
+        }
+
+        let (mut client, state) = fake_client(None);
+        let records = Arc::new(Records::default());
+        client.set_audit_sink(Some(records.clone()));
+
+        client.rename("/a.txt", "/b.txt").unwrap();
+        state.lock().unwrap().fail_next = 2;
+        assert!(client.rm("/b.txt").is_err());
+        client.pwd().unwrap();
+
+        let records = records.0.lock().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].operation, records[0].target.as_deref()), (FtpAuditOperation::Rename, Some("/b.txt")));
+        assert_eq!((records[1].operation, records[1].path.as_str()), (FtpAuditOperation::Delete, "/b.txt"));
+        assert!(records[1].error.is_some());
+    }
+
+    #[test]
+    fn test_fake_reconnect() {
+        let (mut client, state) = fake_client(Some(FtpClientListMode::Mlsd));
+        state.lock().unwrap().mlsd = vec!["type=file;size=1; a.txt".to_string()];
+
+        client.connect().unwrap();
+        state.lock().unwrap().fail_next = 1;
+
+        let list = client.list().unwrap();
+        assert_eq!(list.items.len(), 1);
+        assert_eq!(state.lock().unwrap().connections, 2);
+
+        // RNFR may have gone through, so the pair isn't sent again
+        state.lock().unwrap().fail_next = 1;
+        assert!(client.rename("/a.txt", "/b.txt").is_err());
+        assert_eq!(state.lock().unwrap().connections, 2);
+
+        let stats = client.retry_stats();
+        assert_eq!((stats.connections, stats.retries, stats.failed_retries, stats.not_retried), (2, 1, 0, 1));
+        assert_eq!(stats.causes.get("ConnectionReset"), Some(&2));
+    }
+
+    #[test]
+    fn test_fake_failed_reconnect() {
+        let (mut client, state) = fake_client(None);
+        client.connect().unwrap();
+
+        state.lock().unwrap().fail_next = 1;
+        state.lock().unwrap().refuse_connections = 1;
+        assert!(client.mkdir("/a").is_err());
+
+        let stats = client.retry_stats();
+        assert_eq!((stats.connections, stats.retries, stats.failed_retries), (1, 1, 1));
+        assert_eq!(stats.backoff, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_fake_quote_retry() {
+        let (mut client, state) = fake_client(None);
+        client.connect().unwrap();
+
+        state.lock().unwrap().fail_next = 1;
+        assert!(client.quote("SITE SYMLINK a b").is_err());
+        assert_eq!(state.lock().unwrap().connections, 1);
+
+        state.lock().unwrap().fail_next = 1;
+        assert_eq!(client.quote_idempotent("HELP").unwrap().code, 200);
+        assert_eq!(state.lock().unwrap().connections, 2);
+        assert_eq!(state.lock().unwrap().commands.iter().filter(|command| *command == "HELP").count(), 2);
+    }
+
+    #[test]
+    fn test_fake_repeated_commands() {
+        type Operation = fn(&mut FtpClient, &str) -> FtpResult<()>;
+        let operations: [(Operation, &str); 3] = [(FtpClient::mkdir, "MKD"), (FtpClient::rmdir, "RMD"), (FtpClient::rm, "DELE")];
+
+        for (operation, verb) in operations {
+            let (mut client, state) = fake_client(None);
+            let command = format!("{} /a", verb);
+            state.lock().unwrap().rejected = vec![command.clone()];
+            client.connect().unwrap();
+
+            // the reply to the first attempt is lost, so the 550 to the repeat is expected
+            state.lock().unwrap().fail_next = 1;
+            operation(&mut client, "/a").unwrap();
+            assert_eq!(state.lock().unwrap().connections, 2);
+            assert_eq!(state.lock().unwrap().commands.iter().filter(|sent| **sent == command).count(), 2);
+
+            assert!(operation(&mut client, "/a").is_err());
+        }
+    }
+
+    #[test]
+    fn test_fake_stor_not_repeated() {
+        let (mut client, state) = fake_client(None);
+        client.connect().unwrap();
+
+        state.lock().unwrap().fail_next = 1;
+        assert!(client.put_file("/a.bin", &mut || Ok(io::empty())).is_err());
+        state.lock().unwrap().fail_next = 1;
+        assert!(client.put_stream("/b.bin").is_err());
+
+        assert_eq!(state.lock().unwrap().connections, 1);
+        let stored = state.lock().unwrap().commands.iter().filter(|sent| sent.starts_with("STOR")).count();
+        assert_eq!(stored, 2);
+    }
+
+    #[test]
+    fn test_fake_list_mode() {
+        let (mut client, state) = fake_client(None);
+        state.lock().unwrap().list = vec!["-rw-r--r-- 1 user group 5 Jan 01  2021 a.txt".to_string()];
+
+        let list = client.list().unwrap();
+        assert_eq!(list.items[0].name, "a.txt");
+        // as listed, whatever the timezone of the test machine
+        assert_eq!(list.items[0].modified, NaiveDateTime::parse_from_str("2021-01-01 00:00:00", "%Y-%m-%d %H:%M:%S").ok());
+
+        let commands = state.lock().unwrap().commands.clone();
+        assert!(commands.iter().any(|command| command == "LIST"));
+        assert!(!commands.iter().any(|command| command == "MLSD"));
+    }
+
+    #[test]
+    fn test_list_timezone() {
+        let listed = NaiveDateTime::parse_from_str("2021-01-01 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(FtpListTimezone::Utc.to_utc(listed), listed);
+        assert_eq!(FtpListTimezone::Offset(3600).to_utc(listed), listed - chrono::Duration::hours(1));
+        assert_eq!(FtpListTimezone::Offset(-1800).to_utc(listed), listed + chrono::Duration::minutes(30));
+    }
+
+    #[test]
+    fn test_fake_list_dos() {
+        let (mut client, state) = fake_client(None);
+        state.lock().unwrap().list = vec![
+            "01-23-24  03:15PM       <DIR>          data".to_string(),
+            "01-23-24  03:16PM                 1024 a.txt".to_string(),
+        ];
+
+        let list = client.list().unwrap();
+        assert_eq!(list.items.len(), 2);
+        assert!(list.items[0].ty.is_dir());
+        assert_eq!(list.items[1].size, Some(1024));
+    }
+
+    #[test]
+    fn test_fake_list_matching() {
+        let (mut client, state) = fake_client(None);
+        state.lock().unwrap().list = vec![
+            "-rw-r--r-- 1 user group 10 Jan 01  2021 export.csv".to_string(),
+            "-rw-r--r-- 1 user group 10 Jan 01  2021 readme.txt".to_string(),
+        ];
+
+        // the fake ignores the pattern, like servers without globbing
+        let list = client.list_matching("*.csv").unwrap();
+        assert_eq!(list.items.len(), 1);
+        assert_eq!(list.items[0].name, "export.csv");
+    }
+
+    #[test]
+    fn test_fake_hide_dotfiles() {
+        let (mut client, state) = fake_client(Some(FtpClientListMode::Mlsd));
+        state.lock().unwrap().mlsd = vec![
+            "type=file;size=1; .profile".to_string(),
+            "type=file;size=1; a.txt".to_string(),
+        ];
+
+        assert_eq!(client.list().unwrap().items.len(), 2);
+
+        client.set_hide_dotfiles(true);
+        let list = client.list().unwrap();
+        assert_eq!(list.items.len(), 1);
+        assert_eq!(list.items[0].name, "a.txt");
+    }
+
+    #[derive(Debug)]
+    struct TestListParser;
+
+    impl ListLineParser for TestListParser {
+        fn parse_line(&self, line: &str) -> Option<FtpItem> {
+            let name = line.strip_prefix("VMS ")?;
+            Some(FtpItem::new(name, FtpItemType::File))
+        }
+    }
+
+    #[test]
+    fn test_fake_list_parser() {
+        let (mut client, state) = fake_client(None);
+        state.lock().unwrap().list = vec![
+            "-rw-r--r-- 1 user group 5 Jan 01  2021 a.txt".to_string(),
+            "VMS B.TXT;1".to_string(),
+        ];
+
+        assert!(client.list().is_err());
+
+        client.add_fallback_list_parser(Box::new(TestListParser));
+        let list = client.list().unwrap();
+        let names: Vec<_> = list.items.iter().map(|item| item.name.as_str()).collect();
+        assert_eq!(names, vec!["a.txt", "B.TXT;1"]);
+    }
+
+    #[derive(Debug, Default)]
+    struct TestMiddleware {
+        operations: Arc<Mutex<Vec<String>>>,
+        replies: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl FtpMiddleware for TestMiddleware {
+        fn after_login(&mut self) -> Vec<String> {
+            vec!["SITE UMASK 022".to_string()]
+        }
+
+        fn before_operation(&mut self, operation: &str) {
+            self.operations.lock().unwrap().push(operation.to_string());
+        }
+
+        fn rewrite_command(&mut self, command: String) -> String {
+            command.to_uppercase()
+        }
+
+        fn on_reply(&mut self, command: &str, reply: &mut Response) {
+            self.replies.lock().unwrap().push(format!("{} {}", reply.code, command));
+        }
+    }
+
+    #[test]
+    fn test_fake_middleware() {
+        let (mut client, state) = fake_client(Some(FtpClientListMode::Mlsd));
+        let middleware = TestMiddleware::default();
+        let operations = middleware.operations.clone();
+        let replies = middleware.replies.clone();
+        client.add_middleware(Box::new(middleware));
+
+        client.list().unwrap();
+        let reply = client.quote("site help").unwrap();
+        assert_eq!(reply.code, 200);
+
+        let commands = state.lock().unwrap().commands.clone();
+        assert!(commands.iter().any(|command| command == "SITE UMASK 022"));
+        assert_eq!(commands.last().map(String::as_str), Some("SITE HELP"));
+        assert_eq!(*operations.lock().unwrap(), vec!["pwd", "mlsd", "command"]);
+
+        // typed operations send their commands through the middleware as well
+        client.mkdir("/new").unwrap();
+        client.chdir("/new").unwrap();
+        assert!(state.lock().unwrap().commands.ends_with(&["MKD /NEW".to_string(), "CWD /NEW".to_string(), "PWD".to_string()]));
+        assert_eq!(client.pwd().unwrap(), "/NEW");
+
+        let replies = replies.lock().unwrap().clone();
+        assert!(replies.starts_with(&["331 USER TEST".to_string(), "230 PASS TEST".to_string()]));
+        assert!(replies.ends_with(&["257 MKD /NEW".to_string(), "250 CWD /NEW".to_string(), "257 PWD".to_string()]));
+    }
+
+    #[test]
+    fn test_fake_ping() {
+        let (mut client, state) = fake_client(None);
+        assert!(!client.is_alive());
+
+        client.connect().unwrap();
+        assert!(client.is_alive());
+
+        state.lock().unwrap().fail_next = 1;
+        assert!(client.ping().is_err());
+        assert!(!client.is_connected());
+        assert_eq!(state.lock().unwrap().connections, 1);
+
+        let latency = client.latency(3).unwrap();
+        assert_eq!(latency.samples, 3);
+        assert!(latency.min <= latency.median && latency.median <= latency.max);
+        assert_eq!(client.last_latency(), Some(latency));
+    }
+
+    #[test]
+    fn test_fake_quit() {
+        let (mut client, state) = fake_client(None);
+        client.connect().unwrap();
+        client.quit().unwrap();
+        assert!(!client.is_connected());
+
+        client.connect().unwrap();
+        drop(client);
+
+        let commands = state.lock().unwrap().commands.clone();
+        assert_eq!(commands.iter().filter(|command| *command == "QUIT").count(), 2);
+    }
+
+    #[test]
+    fn test_fake_reinitialize() {
+        let (mut client, state) = fake_client(None);
+        client.connect().unwrap();
+
+        state.lock().unwrap().replies.push(Response { code: 220, body: "220 Ready".to_string() });
+        client.reinitialize("other", "secret").unwrap();
+        assert_eq!(state.lock().unwrap().connections, 1);
+
+        client.reinitialize("third", "secret").unwrap();
+        assert_eq!(state.lock().unwrap().connections, 2);
+
+        let commands = state.lock().unwrap().commands.clone();
+        assert!(commands.iter().any(|command| command == "USER other"));
+        assert!(commands.ends_with(&["USER third".to_string(), "PASS secret".to_string()]));
+    }
+
+    #[cfg(feature = "sync-secure")]
+    #[test]
+    fn test_addr_host() {
+        assert_eq!(addr_host("ftp.example.com:21"), "ftp.example.com");
+        assert_eq!(addr_host("[2001:db8::1]:990"), "2001:db8::1");
+        assert_eq!(addr_host("ftp.example.com"), "ftp.example.com");
+    }
+
+    #[cfg(feature = "sync-secure")]
+    #[derive(Debug)]
+    struct TlsSettings {
+        addr: String,
+        policy: TlsPolicy,
+        min_version: Option<FtpTlsVersion>,
+        max_version: Option<FtpTlsVersion>,
+        root_certificates: Option<String>,
+    }
+
+    #[cfg(feature = "sync-secure")]
+    impl TlsSettings {
+        fn new(addr: &str, policy: TlsPolicy) -> Self {
+            Self { addr: addr.to_string(), policy, min_version: None, max_version: None, root_certificates: None }
+        }
+    }
+
+    #[cfg(feature = "sync-secure")]
+    impl FtpClientSettings for TlsSettings {
+        fn addr(&self) -> &str { &self.addr }
+        fn login(&self) -> &str { crate::mock::MOCK_SERVER_LOGIN }
+        fn password(&self) -> &str { crate::mock::MOCK_SERVER_PASSWORD }
+        fn remote_dir(&self) -> Option<&str> { None }
+        fn tls_policy(&self) -> TlsPolicy { self.policy }
+        fn tls_min_version(&self) -> Option<FtpTlsVersion> { self.min_version }
+        fn tls_max_version(&self) -> Option<FtpTlsVersion> { self.max_version }
+        fn root_certificates(&self) -> Option<&[u8]> { self.root_certificates.as_deref().map(str::as_bytes) }
+    }
+
+    #[cfg(feature = "sync-secure")]
+    #[test]
+    fn test_tls_versions() {
+        assert!(matches!(native_tls::Protocol::from(FtpTlsVersion::Tls10), native_tls::Protocol::Tlsv10));
+        assert!(matches!(native_tls::Protocol::from(FtpTlsVersion::Tls12), native_tls::Protocol::Tlsv12));
+
+        let mut settings = TlsSettings::new("ftp.example.com:21", TlsPolicy::Required);
+        settings.min_version = Some(FtpTlsVersion::Tls12);
+        settings.max_version = Some(FtpTlsVersion::Tls12);
+        let connector = FtpFakeConnector::default();
+        let state = connector.state.clone();
+        let mut client = FtpClient::with_connector(Box::new(settings), Box::new(connector));
+
+        client.tls_connector().unwrap();
+        client.connect().unwrap();
+        assert_eq!(client.tls_mode(), Some(FtpTlsMode::Explicit));
+        assert_eq!(state.lock().unwrap().connections, 1);
+    }
+
+    #[cfg(feature = "sync-secure")]
+    const TEST_CERTIFICATE_A: &str = concat!(
+        "-----BEGIN CERTIFICATE-----\n",
+        "MIIBiDCCAS+gAwIBAgIUDkG+C/QqNB/+cNCHn+W5VjvEjTEwCgYIKoZIzj0EAwIw\n",
+        "GTEXMBUGA1UEAwwOdGVzdC1hLmV4YW1wbGUwIBcNMjYxMDE0MTU0MDA2WhgPMjEy\n",
+        "NjA5MjAxNTQwMDZaMBkxFzAVBgNVBAMMDnRlc3QtYS5leGFtcGxlMFkwEwYHKoZI\n",
+        "zj0CAQYIKoZIzj0DAQcDQgAEF9MJpp8iBasYBQBrP+MneauhO8ZnIB3D4L5ej9iY\n",
+        "1DNyHSktocyuHl8SbWsJ4frVtB0zxEDqztolY7J+1GbV+6NTMFEwHQYDVR0OBBYE\n",
+        "FM0VW9xl25jUCb8HGyw+DWzYJVVLMB8GA1UdIwQYMBaAFM0VW9xl25jUCb8HGyw+\n",
+        "DWzYJVVLMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDRwAwRAIgC8o7Ad6m\n",
+        "VylycC/Isb91HHTRc3OghEU29HNn49sDqzUCICEOTbQkIcpQNryQpknP2XkwQ9+P\n",
+        "JmLmVhrOYkNOoms1\n",
+        "-----END CERTIFICATE-----\n",
+    );
+
+    #[cfg(feature = "sync-secure")]
+    const TEST_CERTIFICATE_B: &str = concat!(
+        "-----BEGIN CERTIFICATE-----\n",
+        "MIIBiTCCAS+gAwIBAgIUXAU1Q/K8EXI8Km1ajhy0jAJOxIQwCgYIKoZIzj0EAwIw\n",
+        "GTEXMBUGA1UEAwwOdGVzdC1iLmV4YW1wbGUwIBcNMjYxMDE0MTU0MDA2WhgPMjEy\n",
+        "NjA5MjAxNTQwMDZaMBkxFzAVBgNVBAMMDnRlc3QtYi5leGFtcGxlMFkwEwYHKoZI\n",
+        "zj0CAQYIKoZIzj0DAQcDQgAEY7sv1f6Xx96MAo7arnDffhxfGGL+f/zeu2E9/dK7\n",
+        "5Yuuy5qNaTvSSCINBWQsct+PjRKp0/VS80ORuiG25Fyo3aNTMFEwHQYDVR0OBBYE\n",
+        "FPcDaqoSN2JId/8PL/aAZPLFN+e3MB8GA1UdIwQYMBaAFPcDaqoSN2JId/8PL/aA\n",
+        "ZPLFN+e3MA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIgWY8AsqR/\n",
+        "dZhCoZ2ZkTbnxAjLU8QiFdmwa7DDeCiGRwcCIQDAZxoXQugL3czIHM3wF4tRPwKL\n",
+        "TSozPNygJrFj4MvbNw==\n",
+        "-----END CERTIFICATE-----\n",
+    );
+
+    #[cfg(feature = "sync-secure")]
+    #[test]
+    fn test_pem_bundle() {
+        // as exported by openssl, with bag attributes around the certificates
+        let bundle = format!(
+            "Bag Attributes\n    friendlyName: a\n{}\nsubject=CN = test-b.example\n{}trailing text\n",
+            TEST_CERTIFICATE_A, TEST_CERTIFICATE_B,
+        );
+        assert_eq!(parse_pem_bundle(bundle.as_bytes()).unwrap().len(), 2);
+
+        assert!(parse_pem_bundle(b"no certificates here").is_err());
+        let unterminated = TEST_CERTIFICATE_A.replace(PEM_END, "");
+        assert!(parse_pem_bundle(unterminated.as_bytes()).is_err());
+
+        let mut settings = TlsSettings::new("ftp.example.com:21", TlsPolicy::Required);
+        settings.root_certificates = Some(bundle);
+        let client = FtpClient::with_connector(Box::new(settings), Box::new(FtpFakeConnector::default()));
+        client.tls_connector().unwrap();
+    }
+
+    #[cfg(feature = "sync-secure")]
+    #[test]
+    fn test_tls_preferred() {
+        // the mock server neither advertises nor accepts AUTH TLS
+        let server = server();
+        let mut client = FtpClient::new(Box::new(TlsSettings::new(&server.addr(), TlsPolicy::Preferred)));
+        client.connect().unwrap();
+        assert_eq!(client.tls_mode(), Some(FtpTlsMode::Plain));
+        assert_eq!(client.names("/data").unwrap(), vec!["a.txt"]);
+
+        let mut client = FtpClient::new(Box::new(TlsSettings::new(&server.addr(), TlsPolicy::Required)));
+        assert!(client.connect().is_err());
+        assert_eq!(client.tls_mode(), None);
+
+        let connector = FtpFakeConnector::default();
+        connector.state.lock().unwrap().features = vec!["AUTH TLS".to_string()];
+        let mut client = FtpClient::with_connector(Box::new(TlsSettings::new("ftp.example.com:21", TlsPolicy::Preferred)), Box::new(connector));
+        client.connect().unwrap();
+        assert_eq!(client.tls_mode(), Some(FtpTlsMode::Explicit));
+    }
+
+    #[cfg(feature = "sync-secure")]
+    #[test]
+    fn test_tls_mode_reconnect() {
+        let connector = FtpFakeConnector::default();
+        let state = connector.state.clone();
+        let mut client = FtpClient::with_connector(Box::new(TlsSettings::new("ftp.example.com:21", TlsPolicy::Required)), Box::new(connector));
+
+        client.quote_idempotent("STAT").unwrap();
+        assert_eq!(client.tls_mode(), Some(FtpTlsMode::Explicit));
+
+        // the replacement connection is upgraded again
+        state.lock().unwrap().fail_next = 1;
+        client.quote_idempotent("STAT").unwrap();
+        assert_eq!(state.lock().unwrap().connections, 2);
+        assert_eq!(client.tls_mode(), Some(FtpTlsMode::Explicit));
+    }
+
+    #[derive(Debug)]
+    struct ModeSettings {
+        passive: bool,
+        epsv_all: bool,
+    }
+
+    impl FtpClientSettings for ModeSettings {
+        fn addr(&self) -> &str { "" }
+        fn login(&self) -> &str { crate::mock::MOCK_SERVER_LOGIN }
+        fn password(&self) -> &str { crate::mock::MOCK_SERVER_PASSWORD }
+        fn remote_dir(&self) -> Option<&str> { None }
+
+        #[cfg(feature = "sync-secure")]
+        fn use_secure(&self) -> bool { false }
+
+        fn use_passive_mode(&self) -> bool { self.passive }
+        fn use_epsv_all(&self) -> bool { self.epsv_all }
+    }
+
+    fn mode_client(passive: bool, epsv_all: bool) -> (FtpClient, Arc<Mutex<FtpFakeState>>) {
+        let connector = FtpFakeConnector::default();
+        let state = connector.state.clone();
+        (FtpClient::with_connector(Box::new(ModeSettings { passive, epsv_all }), Box::new(connector)), state)
+    }
+
+    #[test]
+    fn test_fake_data_modes() {
+        let (mut client, state) = mode_client(true, true);
+        client.connect().unwrap();
+        assert!(state.lock().unwrap().commands.contains(&"EPSV ALL".to_string()));
+        assert!(matches!(state.lock().unwrap().mode, Some(Mode::ExtendedPassive)));
+
+        // a server that refuses it fails the connect, before any data connection
+        let (mut client, state) = mode_client(true, true);
+        state.lock().unwrap().rejected = vec!["EPSV ALL".to_string()];
+        assert!(matches!(client.connect(), Err(FtpError::UnexpectedResponse(Response { code: 550, .. }))));
+        assert!(state.lock().unwrap().mode.is_none());
+
+        // active mode can't work after EPSV ALL, so no connection is even made
+        let (mut client, state) = mode_client(false, true);
+        assert!(matches!(client.connect(), Err(FtpError::ConnectionError(e)) if e.kind() == io::ErrorKind::InvalidInput));
+        assert_eq!(state.lock().unwrap().connections, 0);
+
+        let (mut client, state) = mode_client(false, false);
+        client.connect().unwrap();
+        assert!(matches!(state.lock().unwrap().mode, Some(Mode::Active)));
+    }
+
+    #[derive(Debug)]
+    struct IdleSettings;
+
+    impl FtpClientSettings for IdleSettings {
+        fn addr(&self) -> &str { "" }
+        fn login(&self) -> &str { crate::mock::MOCK_SERVER_LOGIN }
+        fn password(&self) -> &str { crate::mock::MOCK_SERVER_PASSWORD }
+        fn remote_dir(&self) -> Option<&str> { None }
+
+        #[cfg(feature = "sync-secure")]
+        fn use_secure(&self) -> bool { false }
+
+        fn max_idle(&self) -> Option<Duration> { Some(Duration::ZERO) }
+    }
+
+    #[test]
+    fn test_fake_close_if_idle() {
+        let connector = FtpFakeConnector::default();
+        let state = connector.state.clone();
+        let mut client = FtpClient::with_connector(Box::new(IdleSettings), Box::new(connector));
+
+        client.chdir("/dir").unwrap();
+        let connections = state.lock().unwrap().connections;
+        state.lock().unwrap().commands.clear();
+
+        // idle at once, so the next operation gets a new connection in the same directory
+        client.quote_idempotent("STAT").unwrap();
+        assert_eq!(state.lock().unwrap().connections, connections + 1);
+        assert_eq!(state.lock().unwrap().commands, ["QUIT", "USER test", "PASS test", "CWD /dir", "STAT"]);
+
+        assert!(client.close_if_idle());
+        assert!(!client.is_connected());
+        assert!(!client.close_if_idle());
+    }
+
+    #[test]
+    fn test_fake_account() {
+        let (mut client, state) = fake_client(None);
+        state.lock().unwrap().account_required = true;
+
+        assert_eq!(client.tls_mode(), None);
+        client.connect().unwrap();
+        assert_eq!(client.tls_mode(), Some(FtpTlsMode::Plain));
+        let commands = state.lock().unwrap().commands.clone();
+        assert_eq!(commands.last().map(String::as_str), Some("ACCT acct"));
+    }
+
+    #[test]
+    fn test_fake_replies() {
+        let (mut client, state) = fake_client(None);
+        state.lock().unwrap().welcome = Some("220 Maintenance at 02:00".to_string());
+        state.lock().unwrap().replies.push(Response { code: 214, body: "214 Help OK".to_string() });
+
+        client.quote("HELP").unwrap();
+        assert_eq!(client.welcome_message(), Some("220 Maintenance at 02:00"));
+        assert_eq!(client.last_reply().map(|reply| reply.code), Some(214));
+    }
+
+    #[test]
+    fn test_fake_supports_command() {
+        let (mut client, state) = fake_client(None);
+        state.lock().unwrap().features = vec!["MDTM".to_string(), "EPSV".to_string()];
+        state.lock().unwrap().replies.push(Response {
+            code: 214,
+            body: "214-The following commands are recognized (* =>'s unimplemented):\r\n CWD     XCWD    MFMT    SMNT*   QUIT\r\n214 Direct comments to root".to_string(),
+        });
+
+        assert!(client.supports_command("mdtm").unwrap());
+        assert!(client.supports_command("EPSV").unwrap());
+        assert!(client.supports_command("MFMT").unwrap());
+        assert!(!client.supports_command("SMNT").unwrap());
+        assert!(!client.supports_command("SITE").unwrap());
+
+        let commands = state.lock().unwrap().commands.clone();
+        assert_eq!(commands.iter().filter(|command| *command == "HELP").count(), 1);
+
+        // a single-line reply is all prose
+        assert!(parse_help_reply("214 Help OK", 3..=4).is_empty());
+        assert!(parse_help_reply("200 Direct SITE commands are OK", 2..=16).is_empty());
+        assert!(parse_help_reply("214-Commands:\r\n CWD PWD\r\n214 End", 3..=4).into_iter().eq(["CWD", "PWD"]));
+    }
+
+    #[test]
+    fn test_fake_list_with_facts() {
+        let (mut client, state) = fake_client(Some(FtpClientListMode::Mlsd));
+        state.lock().unwrap().features = vec!["MLST type*;size*;modify*;unique*;perm;".to_string()];
+        state.lock().unwrap().mlsd = vec!["type=file;size=1; a.txt".to_string()];
+
+        let list = client.list_path_with_facts("/data", &[MlstFact::Ty, MlstFact::Size]).unwrap();
+        assert_eq!(list.items[0].path.as_deref(), Some("/data/a.txt"));
+
+        let commands = state.lock().unwrap().commands.clone();
+        let start = commands.len() - 3;
+        assert_eq!(&commands[start..], ["OPTS MLST type;size;", "MLSD", "OPTS MLST type;size;modify;unique;"]);
+    }
+
+    #[test]
+    fn test_with_paths_parent() {
+        let list = with_paths(FtpList::default(), "/data/sub");
+        assert_eq!(list.parent.and_then(|parent| parent.path).as_deref(), Some("/data"));
+        assert!(with_paths(FtpList::default(), "/").parent.is_none());
+    }
+
+    #[test]
+    fn test_fake_list_chunked() {
+        let (mut client, state) = fake_client(Some(FtpClientListMode::Mlsd));
+        state.lock().unwrap().features = vec!["MLST type*;size*;".to_string()];
+        let mut mlsd = vec!["type=cdir; data".to_string()];
+        mlsd.extend((0..5).map(|i| format!("type=file;size={}; {}.txt", i, i)));
+        state.lock().unwrap().mlsd = mlsd;
+
+        let mut chunks = vec![];
+        client.list_path_chunked("/data", 2, |items| {
+            chunks.push(items.iter().map(|item| item.path.clone().unwrap()).collect::<Vec<_>>());
+            Ok(chunks.len() < 2)
+        }).unwrap();
+        assert_eq!(chunks, vec![vec!["/data/0.txt", "/data/1.txt"], vec!["/data/2.txt", "/data/3.txt"]]);
+        // the rest of the listing was abandoned along with the connection
+        assert!(!client.is_connected());
+    }
+
+    #[test]
+    fn test_list_nlst() {
+        let server = server();
+        let mut client = FtpClient::new(Box::new(TestSettings { addr: server.addr(), list_mode: Some(FtpClientListMode::Nlst) }));
+
+        let list = client.list_path("/data").unwrap();
+        assert_eq!(list.items.len(), 1);
+        assert_eq!((list.items[0].name.as_str(), list.items[0].ty), ("a.txt", FtpItemType::File));
+        assert_eq!(list.items[0].path.as_deref(), Some("/data/a.txt"));
+    }
+
+    #[test]
+    fn test_list_chunked() {
+        let server = server();
+        server.add_file("/data/b.txt", "b");
+        let mut client = FtpClient::new(settings(&server));
+
+        let mut names = vec![];
+        client.list_path_chunked("/data", 1, |items| {
+            names.extend(items.into_iter().map(|item| item.name));
+            Ok(true)
+        }).unwrap();
+        names.sort();
+        assert_eq!(names, vec!["a.txt", "b.txt"]);
+
+        // the closing reply was read, so the connection is still in step
+        assert_eq!(client.size("/data/a.txt").unwrap(), Some(5));
+        assert_eq!(client.retry_stats().connections, 1);
+    }
+
+    #[test]
+    fn test_feat_extensions() {
+        let features = FtpClientFeatures::from(vec![" HASH SHA-256*;MD5".to_string(), " SITE UTIME".to_string(), " LANG EN*".to_string(), " EPSV".to_string()]);
+        assert_eq!(features.extension_params("hash"), Some("SHA-256*;MD5"));
+        assert!(features.has_extension("SITE UTIME") && !features.has_extension("SITE"));
+        assert!(features.has_extension("EPSV") && features.extension_params("EPSV").is_none());
+    }
+
+    #[test]
+    fn test_fake_capabilities() {
+        let (mut client, state) = fake_client(None);
+        state.lock().unwrap().features = vec![
+            "MODE Z".to_string(),
+            "HASH SHA-256*;MD5".to_string(),
+            "XSHA1".to_string(),
+            "SITE UTIME".to_string(),
+            "MLST type*;size*;modify;".to_string(),
+            "UTF8".to_string(),
+        ];
+
+        let capabilities = client.capabilities(false).unwrap();
+        assert!(capabilities.mode_z && capabilities.utf8 && capabilities.mlsd());
+        assert!(!capabilities.mdtm && !capabilities.tvfs);
+        assert_eq!(capabilities.hash_algorithms, vec![("SHA-256".to_string(), true), ("MD5".to_string(), false)]);
+        assert_eq!(capabilities.x_hashes.iter().collect::<Vec<_>>(), vec!["XSHA1"]);
+        assert_eq!(capabilities.site.iter().collect::<Vec<_>>(), vec!["UTIME"]);
+        assert!(!state.lock().unwrap().commands.iter().any(|command| command.contains("HELP")));
+
+        let features = client.features().unwrap();
+        assert!(features.has_extension("mode") && features.has_extension("SITE UTIME"));
+        assert_eq!(features.extension_params("MODE"), Some("Z"));
+    }
+
+    #[test]
+    fn test_fake_chown() {
+        let (mut client, state) = fake_client(None);
+        client.chown("/a.txt", "www-data").unwrap();
+        client.chgrp("/a.txt", 33u32).unwrap();
+
+        let commands = state.lock().unwrap().commands.clone();
+        assert_eq!(&commands[commands.len() - 2..], ["SITE CHOWN www-data /a.txt", "SITE CHGRP 33 /a.txt"]);
+    }
+
+    #[test]
+    fn test_fake_symlink() {
+        let (mut client, state) = fake_client(Some(FtpClientListMode::Mlsd));
+        state.lock().unwrap().features = vec!["SITE SYMLINK".to_string()];
+        state.lock().unwrap().mlsd = vec!["type=OS.unix=symlink;size=1; current".to_string()];
+
+        client.symlink("release-1.2.3", "/current").unwrap();
+        let commands = state.lock().unwrap().commands.clone();
+        assert!(commands.contains(&"DELE /current".to_string()));
+        assert_eq!(commands.last().map(String::as_str), Some("SITE SYMLINK release-1.2.3 /current"));
+
+        let (mut client, state) = fake_client(Some(FtpClientListMode::Mlsd));
+        state.lock().unwrap().features = vec!["SITE SYMLINK".to_string()];
+        state.lock().unwrap().mlsd = vec!["type=file;size=1; current".to_string()];
+
+        let error = client.symlink("release-1.2.3", "/current").unwrap_err();
+        assert!(matches!(error, FtpError::ConnectionError(e) if e.kind() == io::ErrorKind::AlreadyExists));
+        assert!(!state.lock().unwrap().commands.iter().any(|command| command.starts_with("DELE") || command.starts_with("SITE SYMLINK")));
+    }
+
+    #[test]
+    fn test_fake_mdtm() {
+        let modified = NaiveDateTime::parse_from_str("20210101000000", "%Y%m%d%H%M%S").unwrap();
+        let (mut client, state) = fake_client(None);
+        state.lock().unwrap().features = vec!["MDTM".to_string()];
+        state.lock().unwrap().mdtm = Some(modified);
+
+        // not connected yet, so FEAT hasn't been read
+        assert_eq!(client.modified("/a.txt").unwrap(), Some(modified));
+        client.calibrate_clock("tmp//probe").unwrap();
+
+        let commands = state.lock().unwrap().commands.clone();
+        assert!(commands.contains(&"MDTM /a.txt".to_string()));
+        assert!(commands.contains(&"MDTM tmp/probe".to_string()));
+    }
+
+    #[test]
+    fn test_fake_set_modified() {
+        let modified = NaiveDateTime::parse_from_str("20210101000000", "%Y%m%d%H%M%S").unwrap();
+
+        let (mut client, state) = fake_client(None);
+        state.lock().unwrap().features = vec!["SITE UTIME".to_string()];
+        client.set_modified("/a.txt", modified).unwrap();
+        assert!(state.lock().unwrap().commands.contains(&"SITE UTIME 20210101000000 /a.txt".to_string()));
+
+        let (mut client, state) = fake_client(None);
+        state.lock().unwrap().features = vec!["MFMT".to_string()];
+        state.lock().unwrap().replies.push(Response { code: 213, body: "213 Modify=20210101000000; /a.txt".to_string() });
+        client.set_modified("/a.txt", modified).unwrap();
+        assert_eq!(state.lock().unwrap().commands.last().map(String::as_str), Some("MFMT 20210101000000 /a.txt"));
+
+        // neither is listed and SITE HELP doesn't mention UTIME
+        let (mut client, _) = fake_client(None);
+        assert!(client.set_modified("/a.txt", modified).is_err());
+    }
+
+    #[test]
+    fn test_fake_stat() {
+        let (mut client, state) = fake_client(None);
+        state.lock().unwrap().replies.push(Response {
+            code: 213,
+            body: "213-Status of /a.txt:\r\n -rw-r--r-- 1 user group 5 Jan 01  2021 a.txt\r\n213 End of status".to_string(),
+        });
+
+        let items = client.stat("/a.txt").unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].name, "a.txt");
+        assert_eq!(items[0].size, Some(5));
+        assert_eq!(state.lock().unwrap().commands.last().map(String::as_str), Some("STAT /a.txt"));
+    }
+
+    #[test]
+    fn test_fake_list_recursive() {
+        let (mut client, state) = fake_client(None);
+        state.lock().unwrap().cwd = "/".to_string();
+        state.lock().unwrap().list = vec![
+            ".:",
+            "total 2",
+            "drwxr-xr-x 1 user group 0 Jan 01  2021 data",
+            "-rw-r--r-- 1 user group 6 Jan 01  2021 readme",
+            "",
+            "./data:",
+            "-rw-r--r-- 1 user group 5 Jan 01  2021 a.txt",
+        ].into_iter().map(String::from).collect();
+
+        let tree = client.list_recursive("/").unwrap();
+        assert_eq!(tree.keys().collect::<Vec<_>>(), vec!["/", "/data"]);
+        assert_eq!(tree["/"].items.len(), 2);
+        assert_eq!(tree["/data"].items[0].name, "a.txt");
     }
-}
\ No newline at end of file
+}
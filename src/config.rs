@@ -0,0 +1,222 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use crate::rate::RateLimit;
+use crate::client::{DEFAULT_PING_TIMEOUT, DEFAULT_TRANSFER_BUFFER_SIZE, FtpAddressPreference, FtpClientListMode, FtpClientSettings, FtpListTimezone, FtpTlsVersion, OverwritePolicy, TlsPolicy};
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn default_login() -> String {
+    "anonymous".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_address_preference() -> FtpAddressPreference {
+    FtpAddressPreference::Any
+}
+
+// Connection profile loadable from TOML/YAML/JSON; timeouts are in seconds.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct FtpConnectionConfig {
+    pub addr:           String,
+    #[serde(default = "default_login")]
+    pub login:          String,
+    #[serde(default)]
+    pub password:       String,
+    #[serde(default)]
+    pub account:        Option<String>,
+    #[serde(default)]
+    pub remote_dir:     Option<String>,
+
+    #[serde(default = "default_true")]
+    pub secure:         bool,
+    #[serde(default)]
+    pub sni:            Option<String>,
+    #[serde(default = "default_true")]
+    pub use_sni:        bool,
+    // overrides `secure` when set
+    #[serde(default)]
+    pub tls_policy:     Option<TlsPolicy>,
+    #[serde(default)]
+    pub tls_min_version: Option<FtpTlsVersion>,
+    #[serde(default)]
+    pub tls_max_version: Option<FtpTlsVersion>,
+    // PEM text, may hold several certificates
+    #[serde(default)]
+    pub root_certificates: Option<String>,
+
+    #[serde(default = "default_address_preference")]
+    pub address_preference: FtpAddressPreference,
+    #[serde(default)]
+    pub connect_timeout: Option<u64>,
+
+    #[serde(default = "default_true")]
+    pub feat:           bool,
+    #[serde(default = "default_true")]
+    pub passive:        bool,
+    #[serde(default)]
+    pub epsv_all:       bool,
+    #[serde(default)]
+    pub list_mode:      Option<FtpClientListMode>,
+    #[serde(default)]
+    pub list_hidden:    bool,
+    #[serde(default)]
+    pub list_timezone:  FtpListTimezone,
+
+    #[serde(default)]
+    pub list_cache_ttl: Option<u64>,
+    #[serde(default)]
+    pub ping_timeout:   Option<u64>,
+    #[serde(default)]
+    pub max_idle:       Option<u64>,
+    #[serde(default)]
+    pub transfer_buffer_size: Option<usize>,
+    // bytes per second
+    #[serde(default)]
+    pub rate_limit:     Option<u64>,
+    #[serde(default)]
+    pub pipelining:     bool,
+    #[serde(default)]
+    pub max_retries:    Option<u32>,
+    // by operation name, e.g. `retr = 3`; `max_retries` applies to the others
+    #[serde(default)]
+    pub operation_retries: BTreeMap<String, u32>,
+    // before the first retry, doubled for each further one
+    #[serde(default)]
+    pub retry_backoff:  Option<u64>,
+    #[serde(default)]
+    pub overwrite:      OverwritePolicy,
+}
+
+impl FtpClientSettings for FtpConnectionConfig {
+    fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    fn login(&self) -> &str {
+        &self.login
+    }
+
+    fn password(&self) -> &str {
+        &self.password
+    }
+
+    fn remote_dir(&self) -> Option<&str> {
+        self.remote_dir.as_deref()
+    }
+
+    fn account(&self) -> Option<&str> {
+        self.account.as_deref()
+    }
+
+    #[cfg(feature = "sync-secure")]
+    fn use_secure(&self) -> bool {
+        self.secure
+    }
+
+    #[cfg(feature = "sync-secure")]
+    fn sni(&self) -> Option<&str> {
+        self.sni.as_deref()
+    }
+
+    #[cfg(feature = "sync-secure")]
+    fn use_sni(&self) -> bool {
+        self.use_sni
+    }
+
+    #[cfg(feature = "sync-secure")]
+    fn tls_policy(&self) -> TlsPolicy {
+        match self.tls_policy {
+            Some(policy) => policy,
+            None if self.secure => TlsPolicy::Required,
+            None => TlsPolicy::Disabled,
+        }
+    }
+
+    #[cfg(feature = "sync-secure")]
+    fn tls_min_version(&self) -> Option<FtpTlsVersion> {
+        self.tls_min_version
+    }
+
+    #[cfg(feature = "sync-secure")]
+    fn tls_max_version(&self) -> Option<FtpTlsVersion> {
+        self.tls_max_version
+    }
+
+    #[cfg(feature = "sync-secure")]
+    fn root_certificates(&self) -> Option<&[u8]> {
+        self.root_certificates.as_deref().map(str::as_bytes)
+    }
+
+    fn address_preference(&self) -> FtpAddressPreference {
+        self.address_preference
+    }
+
+    fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout.map(Duration::from_secs)
+    }
+
+    fn use_feat(&self) -> bool {
+        self.feat
+    }
+
+    fn use_passive_mode(&self) -> bool {
+        self.passive
+    }
+
+    fn use_epsv_all(&self) -> bool {
+        self.epsv_all
+    }
+
+    fn list_mode(&self) -> Option<FtpClientListMode> {
+        self.list_mode
+    }
+
+    fn list_hidden(&self) -> bool {
+        self.list_hidden
+    }
+
+    fn list_timezone(&self) -> FtpListTimezone {
+        self.list_timezone
+    }
+
+    fn list_cache_ttl(&self) -> Option<Duration> {
+        self.list_cache_ttl.map(Duration::from_secs)
+    }
+
+    fn ping_timeout(&self) -> Duration {
+        self.ping_timeout.map(Duration::from_secs).unwrap_or(DEFAULT_PING_TIMEOUT)
+    }
+
+    fn max_idle(&self) -> Option<Duration> {
+        self.max_idle.map(Duration::from_secs)
+    }
+
+    fn transfer_buffer_size(&self) -> usize {
+        self.transfer_buffer_size.unwrap_or(DEFAULT_TRANSFER_BUFFER_SIZE)
+    }
+
+    fn rate_limit(&self) -> RateLimit {
+        self.rate_limit.map(RateLimit::BytesPerSecond).unwrap_or(RateLimit::Unlimited)
+    }
+
+    fn use_pipelining(&self) -> bool {
+        self.pipelining
+    }
+
+    fn max_retries(&self, operation: &str) -> u32 {
+        self.operation_retries.get(operation).copied().or(self.max_retries).unwrap_or(1)
+    }
+
+    fn retry_backoff(&self, attempt: u32) -> Duration {
+        let base = self.retry_backoff.map(Duration::from_secs).unwrap_or_default();
+        base.checked_mul(2u32.saturating_pow(attempt.saturating_sub(1))).unwrap_or(Duration::MAX)
+    }
+
+    fn overwrite_policy(&self) -> OverwritePolicy {
+        self.overwrite.clone()
+    }
+}
@@ -0,0 +1,116 @@
+use crate::types::{FtpItem, FtpList};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct FtpFormatOptions {
+    pub human_readable: bool,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+const SIZE_UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+
+pub fn format_size(size: u64, human_readable: bool) -> String {
+    if !human_readable || size < 1024 {
+        return size.to_string();
+    }
+
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < SIZE_UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if value < 10.0 {
+        format!("{:.1}{}", value, SIZE_UNITS[unit])
+    } else {
+        format!("{:.0}{}", value, SIZE_UNITS[unit])
+    }
+}
+
+pub fn format_mode(item: &FtpItem) -> String {
+    let mode = match (item.unix_mode, item.perm.as_ref()) {
+        (Some(mode), _) => mode,
+        (None, Some(perm)) => (perm.as_pex() as u16) << 6,
+        (None, None) => 0,
+    };
+
+    let mut result = String::with_capacity(10);
+    result.push(if item.ty.is_dir() {'d'} else if item.ty.is_symlink() {'l'} else {'-'});
+
+    for shift in &[6, 3, 0] {
+        let bits = (mode >> *shift) & 0o7;
+        result.push(if bits & 0o4 != 0 {'r'} else {'-'});
+        result.push(if bits & 0o2 != 0 {'w'} else {'-'});
+        result.push(if bits & 0o1 != 0 {'x'} else {'-'});
+    }
+
+    result
+}
+
+fn format_owner(name: Option<&str>, id: Option<u32>) -> String {
+    match (name, id) {
+        (Some(name), _) => name.to_string(),
+        (None, Some(id)) => id.to_string(),
+        (None, None) => "-".to_string(),
+    }
+}
+
+fn format_columns(item: &FtpItem, options: &FtpFormatOptions) -> [String; 6] {
+    [
+        format_mode(item),
+        format_owner(item.unix_ownername.as_deref(), item.unix_owner),
+        format_owner(item.unix_groupname.as_deref(), item.unix_group),
+        item.size.map(|size| format_size(size, options.human_readable)).unwrap_or_else(|| "-".to_string()),
+        item.modified.map(|dt| dt.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_else(|| "-".to_string()),
+        item.name.clone(),
+    ]
+}
+
+fn format_rows(rows: &[[String; 6]]) -> String {
+    let mut widths = [0usize; 5];
+    for row in rows {
+        for (width, column) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+
+    rows.iter()
+        .map(|row| format!(
+            "{:<w0$} {:<w1$} {:<w2$} {:>w3$} {:<w4$} {}",
+            row[0], row[1], row[2], row[3], row[4], row[5],
+            w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3], w4 = widths[4],
+        ))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn format_item(item: &FtpItem, options: &FtpFormatOptions) -> String {
+    format_rows(&[format_columns(item, options)])
+}
+
+pub fn format_list(list: &FtpList, options: &FtpFormatOptions) -> String {
+    let rows: Vec<_> = list.current.iter()
+        .chain(list.parent.iter())
+        .chain(list.items.iter())
+        .map(|item| format_columns(item, options))
+        .collect();
+
+    format_rows(&rows)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(1000, false), "1000");
+        assert_eq!(format_size(1000, true), "1000");
+        assert_eq!(format_size(1536, true), "1.5K");
+        assert_eq!(format_size(200 * 1024 * 1024, true), "200M");
+    }
+}
@@ -1,9 +1,70 @@
 
 pub mod types;
 pub mod mlst;
+pub mod listing;
+pub mod format;
+pub mod pattern;
+pub mod rate;
+
+mod cache;
+mod path;
 
 #[cfg(any(test, not(any(feature = "async", feature = "async-secure"))))]
 mod client;
 
+#[cfg(any(test, not(any(feature = "async", feature = "async-secure"))))]
+mod transport;
+
+#[cfg(any(test, not(any(feature = "async", feature = "async-secure"))))]
+pub mod middleware;
+
+#[cfg(any(test, not(any(feature = "async", feature = "async-secure"))))]
+pub mod audit;
+
+#[cfg(not(any(feature = "async", feature = "async-secure")))]
+pub mod walk;
+
+#[cfg(not(any(feature = "async", feature = "async-secure")))]
+pub mod pool;
+
+#[cfg(not(any(feature = "async", feature = "async-secure")))]
+pub mod poll;
+
+#[cfg(not(any(feature = "async", feature = "async-secure")))]
+pub mod mirror;
+
+#[cfg(not(any(feature = "async", feature = "async-secure")))]
+pub mod checksum;
+
+#[cfg(not(any(feature = "async", feature = "async-secure")))]
+pub mod manifest;
+
+#[cfg(not(any(feature = "async", feature = "async-secure")))]
+pub mod queue;
+
+#[cfg(not(any(feature = "async", feature = "async-secure")))]
+pub mod session;
+
+#[cfg(all(feature = "watch", not(any(feature = "async", feature = "async-secure"))))]
+pub mod watch;
+
+#[cfg(all(feature = "publish", not(any(feature = "async", feature = "async-secure"))))]
+pub mod publish;
+
+#[cfg(not(any(feature = "async", feature = "async-secure")))]
+pub mod transfer;
+
+#[cfg(not(any(feature = "async", feature = "async-secure")))]
+pub mod vfs;
+
+#[cfg(all(feature = "serde", not(any(feature = "async", feature = "async-secure"))))]
+pub mod config;
+
+#[cfg(all(any(test, feature = "test-util"), not(any(feature = "async", feature = "async-secure"))))]
+pub mod mock;
+
+#[cfg(all(feature = "remotefs", not(any(feature = "async", feature = "async-secure"))))]
+mod remote_fs;
+
 #[cfg(not(any(feature = "async", feature = "async-secure")))]
-pub use client::FtpClient;
+pub use client::{FtpAddressPreference, FtpCapabilities, FtpClient, FtpClientListMode, FtpClientSettings, FtpLatency, FtpListTimezone, FtpRetryStats, FtpSocketOptions, FtpTlsMode, FtpTlsVersion, FtpUnixId, OverwritePolicy, TlsPolicy};
@@ -0,0 +1,119 @@
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::str::FromStr;
+use suppaftp::list;
+use crate::{
+    mlst::list_to_ftp,
+    types::{FtpItem, FtpItemType},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FtpListFormat {
+    Unix,
+    Dos,
+}
+
+// User-supplied LIST line parser for servers the built-in formats don't cover.
+// Returning `None` means the line isn't recognised and the next parser is tried.
+pub trait ListLineParser: std::fmt::Debug + Send {
+    fn parse_line(&self, line: &str) -> Option<FtpItem>;
+}
+
+lazy_static! {
+    // 01-23-24  03:15PM       <DIR>          foo
+    // 01-23-2024  15:15             1024 bar.txt
+    static ref DOS_LINE: Regex = Regex::new(
+        r"^(\d{2})-(\d{2})-(\d{2}|\d{4})\s+(\d{1,2}):(\d{2})\s*([AaPp][Mm])?\s+(<DIR>|\d+)\s+(.+)$"
+    ).unwrap();
+}
+
+impl FtpListFormat {
+    pub fn detect<S: AsRef<str>>(lines: &[S]) -> Option<Self> {
+        let line = lines.iter()
+            .map(|line| line.as_ref().trim_end())
+            .find(|line| !line.is_empty() && !is_total_line(line))?;
+
+        if DOS_LINE.is_match(line) {
+            Some(Self::Dos)
+        } else {
+            Some(Self::Unix)
+        }
+    }
+
+    pub fn parse_line(&self, line: &str) -> Result<FtpItem, list::ParseError> {
+        match self {
+            Self::Unix => list::File::from_str(line).map(|file| list_to_ftp(&file)),
+            Self::Dos => parse_dos_line(line),
+        }
+    }
+}
+
+pub(crate) fn is_total_line(line: &str) -> bool {
+    line.starts_with("total ")
+}
+
+pub fn parse_dos_line(line: &str) -> Result<FtpItem, list::ParseError> {
+    let captures = DOS_LINE.captures(line.trim_end()).ok_or(list::ParseError::SyntaxError)?;
+    let number = |i: usize| captures[i].parse::<u32>().map_err(|_| list::ParseError::SyntaxError);
+
+    let month = number(1)?;
+    let day = number(2)?;
+    let year = match number(3)? {
+        year if captures[3].len() == 4 => year as i32,
+        year if year < 70 => 2000 + year as i32,
+        year => 1900 + year as i32,
+    };
+
+    let mut hour = number(4)?;
+    let minute = number(5)?;
+    match captures.get(6).map(|m| m.as_str().to_ascii_uppercase()).as_deref() {
+        Some("AM") if hour == 12 => hour = 0,
+        Some("PM") if hour < 12 => hour += 12,
+        _ => {},
+    }
+
+    let modified = NaiveDate::from_ymd_opt(year, month, day)
+        .zip(NaiveTime::from_hms_opt(hour, minute, 0))
+        .map(|(date, time)| NaiveDateTime::new(date, time))
+        .ok_or(list::ParseError::SyntaxError)?;
+
+    let name = captures[8].to_string();
+    let mut item = match &captures[7] {
+        "<DIR>" => match name.as_str() {
+            "." => FtpItem::new(name, FtpItemType::CurrentDir),
+            ".." => FtpItem::new(name, FtpItemType::ParentDir),
+            _ => FtpItem::new(name, FtpItemType::Dir),
+        },
+        size => FtpItem {
+            size: Some(size.parse().map_err(|_| list::ParseError::SyntaxError)?),
+            ..FtpItem::new(name, FtpItemType::File)
+        },
+    };
+    item.modified = Some(modified);
+
+    Ok(item)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_dos_line() {
+        let dir = parse_dos_line("01-23-24  03:15PM       <DIR>          foo bar").unwrap();
+        assert_eq!(dir.name, "foo bar");
+        assert_eq!(dir.ty, FtpItemType::Dir);
+        assert_eq!(dir.modified, NaiveDate::from_ymd_opt(2024, 1, 23).and_then(|date| date.and_hms_opt(15, 15, 0)));
+
+        let file = parse_dos_line("12-31-1999  23:59                 1024 a.txt").unwrap();
+        assert_eq!(file.ty, FtpItemType::File);
+        assert_eq!(file.size, Some(1024));
+
+        assert_eq!(FtpListFormat::detect(&["01-23-24  03:15PM  <DIR>  foo"]), Some(FtpListFormat::Dos));
+        assert_eq!(FtpListFormat::detect(&["total 1", "-rw-r--r-- 1 user group 5 Jan 01  2021 a.txt"]), Some(FtpListFormat::Unix));
+    }
+}
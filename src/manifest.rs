@@ -0,0 +1,180 @@
+use suppaftp::types::FtpResult;
+use crate::{
+    client::FtpClient,
+    mirror::modified_secs,
+    path::normalize_path,
+    types::FtpItemType,
+    walk::FtpWalkControl,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FtpRemoteChecksum {
+    // as the server names it, e.g. `SHA-256` or `MD5`
+    pub algorithm:  String,
+    // lowercase hex
+    pub value:      String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FtpManifestEntry {
+    // relative to the manifest root
+    pub path:       String,
+    pub size:       Option<u64>,
+    // seconds since the Unix epoch, UTC
+    pub modified:   Option<u64>,
+    // `None` when the server has no hash command or refused it for this file
+    pub checksum:   Option<FtpRemoteChecksum>,
+}
+
+// Files of a remote tree, sorted by path.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FtpManifest {
+    pub root:       String,
+    pub entries:    Vec<FtpManifestEntry>,
+}
+
+impl FtpManifest {
+    pub fn get(&self, path: &str) -> Option<&FtpManifestEntry> {
+        self.entries.binary_search_by(|entry| entry.path.as_str().cmp(path))
+            .ok()
+            .map(|index| &self.entries[index])
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum FtpHashCommand {
+    // draft-bryan-ftpext-hash, with the algorithm the server selected
+    Hash,
+    Xmd5,
+}
+
+fn is_hex(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|ch| ch.is_ascii_hexdigit())
+}
+
+// `213 SHA-256 0-49 <hash> <name>`
+fn parse_hash_reply(body: &str) -> Option<FtpRemoteChecksum> {
+    let mut words = body.split_whitespace().skip(1);
+    let algorithm = words.next()?;
+    let _range = words.next()?;
+    let value = words.next().filter(|value| is_hex(value))?;
+
+    Some(FtpRemoteChecksum { algorithm: algorithm.to_string(), value: value.to_ascii_lowercase() })
+}
+
+// servers disagree on what surrounds the hash, so the first 32-digit word is taken
+fn parse_xmd5_reply(body: &str) -> Option<FtpRemoteChecksum> {
+    let value = body.split_whitespace().skip(1).find(|word| word.len() == 32 && is_hex(word))?;
+    Some(FtpRemoteChecksum { algorithm: "MD5".to_string(), value: value.to_ascii_lowercase() })
+}
+
+impl FtpClient {
+    fn hash_command(&mut self) -> FtpResult<Option<FtpHashCommand>> {
+        if self.supports_command("HASH")? {
+            return Ok(Some(FtpHashCommand::Hash));
+        }
+        if self.supports_command("XMD5")? {
+            return Ok(Some(FtpHashCommand::Xmd5));
+        }
+        Ok(None)
+    }
+
+    fn checksum_with(&mut self, command: FtpHashCommand, path: &str) -> FtpResult<Option<FtpRemoteChecksum>> {
+        let (verb, parse): (_, fn(&str) -> Option<FtpRemoteChecksum>) = match command {
+            FtpHashCommand::Hash => ("HASH", parse_hash_reply),
+            FtpHashCommand::Xmd5 => ("XMD5", parse_xmd5_reply),
+        };
+
+        let reply = self.quote_idempotent(&format!("{} {}", verb, path))?;
+        Ok(Some(reply).filter(|reply| reply.code / 100 == 2).and_then(|reply| parse(&reply.body)))
+    }
+
+    // HASH if the server has it, XMD5 otherwise; `None` when neither is available
+    // or the server refused to hash the file.
+    pub fn remote_checksum(&mut self, path: &str) -> FtpResult<Option<FtpRemoteChecksum>> {
+        match self.hash_command()? {
+            Some(command) => self.checksum_with(command, path),
+            None => Ok(None),
+        }
+    }
+
+    // Every file under `root`, with a checksum from the server where it can
+    // compute one. Hashing makes the server read each file, so this takes as long
+    // as downloading the tree would on a fast link.
+    pub fn manifest(&mut self, root: &str) -> FtpResult<FtpManifest> {
+        let root = normalize_path(root);
+        let mut entries = vec![];
+
+        self.walk(&root, |entry| {
+            if entry.item.ty == FtpItemType::File {
+                entries.push((entry.path.clone(), FtpManifestEntry {
+                    path: entry.path[root.len()..].trim_start_matches('/').to_string(),
+                    size: entry.item.size,
+                    modified: modified_secs(entry.item.modified),
+                    checksum: None,
+                }));
+            }
+            Ok(FtpWalkControl::Continue)
+        })?;
+
+        if let Some(command) = self.hash_command()? {
+            for (path, entry) in entries.iter_mut() {
+                entry.checksum = self.checksum_with(command, path)?;
+            }
+        }
+
+        let mut entries: Vec<FtpManifestEntry> = entries.into_iter().map(|(_, entry)| entry).collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(FtpManifest { root, entries })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::client::FtpClientListMode;
+    use crate::mock::support::*;
+    use suppaftp::types::Response;
+
+    #[test]
+    fn test_parse_checksum_reply() {
+        let checksum = parse_hash_reply("213 SHA-256 0-5 2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824 a file.txt").unwrap();
+        assert_eq!(checksum.algorithm, "SHA-256");
+        assert_eq!(checksum.value, "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+
+        let checksum = parse_xmd5_reply("250 5d41402abc4b2a76b9719d911017c592").unwrap();
+        assert_eq!(checksum.value, "5d41402abc4b2a76b9719d911017c592");
+        assert_eq!(parse_xmd5_reply("250 a.txt 5d41402abc4b2a76b9719d911017c592").map(|checksum| checksum.algorithm), Some("MD5".to_string()));
+        assert!(parse_hash_reply("213 SHA-256 0-5").is_none());
+    }
+
+    #[test]
+    fn test_fake_manifest() {
+        let (mut client, state) = fake_client(Some(FtpClientListMode::Mlsd));
+        state.lock().unwrap().features = vec!["HASH SHA-256*;MD5".to_string()];
+        state.lock().unwrap().mlsd = vec![
+            "type=file;size=5;modify=20210101000000; b.txt".to_string(),
+            "type=file;size=1; a.txt".to_string(),
+        ];
+        state.lock().unwrap().replies = vec![
+            Response { code: 213, body: "213 SHA-256 0-5 ABCDEF b.txt".to_string() },
+            Response { code: 550, body: "550 Not allowed".to_string() },
+        ];
+
+        let manifest = client.manifest("/").unwrap();
+        let paths: Vec<_> = manifest.entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, vec!["a.txt", "b.txt"]);
+        assert_eq!(manifest.get("a.txt").unwrap().checksum, None);
+
+        let b = manifest.get("b.txt").unwrap();
+        assert_eq!((b.size, b.modified), (Some(5), Some(1609459200)));
+        assert_eq!(b.checksum.as_ref().map(|checksum| checksum.value.as_str()), Some("abcdef"));
+        assert!(state.lock().unwrap().commands.contains(&"HASH /b.txt".to_string()));
+    }
+}
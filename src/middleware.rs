@@ -0,0 +1,30 @@
+use std::time::Duration;
+use suppaftp::types::{FtpError, Response};
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Hooks invoked by `FtpClient` around every operation it performs on the control
+// connection. `operation` is the client-level operation name (e.g. "cwd", "mlsd",
+// "retr"). Control commands (the login, CWD, MKD, STAT, ..., and raw ones sent
+// through `FtpClient::quote`) can be rewritten and their replies inspected; the
+// commands that open a data connection (LIST, RETR, STOR, ...) are sent by the
+// transport and only show up as operations.
+pub trait FtpMiddleware: std::fmt::Debug + Send {
+    fn after_login(&mut self) -> Vec<String> {
+        vec![]
+    }
+
+    fn before_operation(&mut self, _operation: &str) {}
+
+    fn after_operation(&mut self, _operation: &str, _result: Result<(), &FtpError>, _elapsed: Duration) {}
+
+    // A recoverable error of `operation`, before the client reconnects to send it
+    // again; `retried` is false for operations that are never repeated.
+    fn on_retry(&mut self, _operation: &str, _error: &FtpError, _retried: bool) {}
+
+    fn rewrite_command(&mut self, command: String) -> String {
+        command
+    }
+
+    fn on_reply(&mut self, _command: &str, _reply: &mut Response) {}
+}
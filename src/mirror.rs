@@ -0,0 +1,646 @@
+use chrono::NaiveDateTime;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use suppaftp::types::{FtpError, FtpResult};
+use crate::{
+    checksum::{FtpChecksumCache, FtpChecksumEntry, file_checksum, local_entry},
+    client::{FtpClient, FtpRetryStats, OverwritePolicy},
+    mlst::MlstFact,
+    path::join_path,
+    pattern::wildcard_match_any,
+    pool::{FtpPool, lock, run_parallel},
+    transfer::FtpTransferOptions,
+    walk::{FollowSymlinks, FtpWalkControl, FtpWalkOptions},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FtpMirrorDirection {
+    Download,
+    Upload,
+}
+
+// One file about to be mirrored. Hooks may change either path to rename the
+// file on the fly.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FtpMirrorTransfer {
+    pub direction:  FtpMirrorDirection,
+    pub remote:     String,
+    pub local:      PathBuf,
+}
+
+#[derive(Debug)]
+pub enum FtpMirrorOutcome<'a> {
+    Transferred(u64),
+    Skipped,
+    Failed(&'a FtpError),
+}
+
+// Called around every file transfer of a mirror run, from the worker threads
+// when the run goes through a pool.
+pub trait FtpMirrorHook: std::fmt::Debug + Send + Sync {
+    // Returning `false` skips the file.
+    fn before_transfer(&self, _transfer: &mut FtpMirrorTransfer) -> bool {
+        true
+    }
+
+    fn after_transfer(&self, _transfer: &FtpMirrorTransfer, _outcome: &FtpMirrorOutcome<'_>) {}
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FtpMirrorOptions {
+    pub exclude:        Vec<String>,
+    pub preserve_mtime: bool,
+    // local links are guarded against cycles by their canonical path, so there
+    // `Always` and `Safe` behave the same
+    pub follow_symlinks:    FollowSymlinks,
+    pub concurrency:    usize,
+    // skip files that are unchanged since the previous run recorded in this cache
+    pub checksum_cache: Option<PathBuf>,
+    // applies to files that aren't skipped as unchanged; `None` uses the client's default
+    pub overwrite:      Option<OverwritePolicy>,
+    // downloads are written under this suffix until complete; `None` is ".part"
+    pub part_suffix:    Option<String>,
+    pub verify_size:    bool,
+    pub hooks:          Vec<Arc<dyn FtpMirrorHook>>,
+}
+
+impl FtpMirrorOptions {
+    fn transfer_options(&self) -> FtpTransferOptions {
+        let mut options = FtpTransferOptions {
+            overwrite: self.overwrite.clone(),
+            verify_size: self.verify_size,
+            ..FtpTransferOptions::default()
+        };
+        if let Some(suffix) = self.part_suffix.as_ref() {
+            options.part_suffix = suffix.clone();
+        }
+        options
+    }
+
+    // `None` from `transfer` means the file was already up to date.
+    fn with_hooks<F>(&self, mut transfer: FtpMirrorTransfer, f: F) -> FtpResult<Option<u64>>
+    where
+        F: FnOnce(&FtpMirrorTransfer) -> FtpResult<Option<u64>>
+    {
+        let result = if self.hooks.iter().all(|hook| hook.before_transfer(&mut transfer)) {
+            f(&transfer)
+        } else {
+            Ok(None)
+        };
+
+        let outcome = match &result {
+            Ok(Some(bytes)) => FtpMirrorOutcome::Transferred(*bytes),
+            Ok(None) => FtpMirrorOutcome::Skipped,
+            Err(e) => FtpMirrorOutcome::Failed(e),
+        };
+        for hook in &self.hooks {
+            hook.after_transfer(&transfer, &outcome);
+        }
+
+        result
+    }
+}
+
+fn open_checksum_cache(options: &FtpMirrorOptions) -> FtpResult<Option<Mutex<FtpChecksumCache>>> {
+    match options.checksum_cache.as_ref() {
+        Some(path) => Ok(Some(Mutex::new(FtpChecksumCache::open(path).map_err(FtpError::ConnectionError)?))),
+        None => Ok(None),
+    }
+}
+
+fn save_checksum_cache(cache: Option<Mutex<FtpChecksumCache>>) -> FtpResult<()> {
+    match cache {
+        Some(cache) => cache.into_inner().unwrap_or_else(|e| e.into_inner()).save().map_err(FtpError::ConnectionError),
+        None => Ok(()),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SyncError {
+    // remote path of the file
+    pub path:       String,
+    pub message:    String,
+}
+
+// Outcome of one mirror run. A file that fails is recorded in `errors` and the
+// run goes on with the rest; `deleted` stays 0 as long as mirroring never removes
+// extraneous files.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SyncReport {
+    pub uploaded:    usize,
+    pub downloaded:  usize,
+    pub deleted:     usize,
+    pub skipped:     usize,
+    // local copies renamed after their remote file, matched by its `unique` fact
+    pub moved:       usize,
+    pub bytes:       u64,
+    pub errors:      Vec<SyncError>,
+    pub duration:    Duration,
+    // reconnects and retries of the transfers, over all connections of the run
+    pub retry_stats: FtpRetryStats,
+}
+
+impl SyncReport {
+    // `None` means the file was already up to date.
+    fn record(&mut self, direction: FtpMirrorDirection, path: &str, result: FtpResult<Option<u64>>) {
+        match result {
+            Ok(Some(bytes)) => {
+                match direction {
+                    FtpMirrorDirection::Download => self.downloaded += 1,
+                    FtpMirrorDirection::Upload => self.uploaded += 1,
+                }
+                self.bytes += bytes;
+            },
+            Ok(None) => self.skipped += 1,
+            Err(e) => self.errors.push(SyncError {
+                path: path.to_string(),
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    // A moved file that its job then finds unchanged is counted in `moved` only.
+    fn record_download(&mut self, moved: &HashSet<String>, path: &str, result: FtpResult<Option<u64>>) {
+        match result {
+            Ok(None) if moved.contains(path) => {},
+            result => self.record(FtpMirrorDirection::Download, path, result),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+pub(crate) fn modified_secs(modified: Option<NaiveDateTime>) -> Option<u64> {
+    modified.map(|modified| modified.and_utc().timestamp().max(0) as u64)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.contains(|ch| ch == '/' || ch == '\\' || ch == '\0')
+        && !(cfg!(windows) && name.contains(':'))
+}
+
+fn invalid_data_error(message: String) -> FtpError {
+    FtpError::ConnectionError(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+fn unsafe_name_error(name: &str) -> FtpError {
+    invalid_data_error(format!("refusing to mirror unsafe remote file name {:?}", name))
+}
+
+fn local_path_for(local_dir: &Path, remote_dir: &str, remote_path: &str) -> PathBuf {
+    remote_path[remote_dir.len()..]
+        .trim_start_matches('/')
+        .split('/')
+        .fold(local_dir.to_path_buf(), |path, name| path.join(name))
+}
+
+#[derive(Debug, Clone)]
+struct FtpDownloadJob {
+    remote:     String,
+    local:      PathBuf,
+    size:       Option<u64>,
+    modified:   Option<NaiveDateTime>,
+    unique:     Option<String>,
+}
+
+fn is_under_dir(remote_dir: &str, path: &str) -> bool {
+    path.strip_prefix(remote_dir).is_some_and(|rest| remote_dir.ends_with('/') || rest.starts_with('/'))
+}
+
+// A file that is new to the cache while its `unique` fact is recorded for a path
+// gone from the server was renamed there; the local copy is renamed to match
+// instead of being downloaded again, and the job then finds it unchanged. Returns
+// the remote paths of the moved files.
+fn apply_remote_moves(jobs: &[FtpDownloadJob], remote_dir: &str, local_dir: &Path, cache: &Mutex<FtpChecksumCache>) -> HashSet<String> {
+    let mut cache = lock(cache);
+    let current: HashSet<&str> = jobs.iter().map(|job| job.remote.as_str()).collect();
+    let mut known = cache.keys_by_unique();
+    let mut moved = HashSet::new();
+
+    for job in jobs {
+        let unique = match job.unique.as_deref() {
+            Some(unique) if cache.get(&job.remote).is_none() && !job.local.exists() => unique,
+            _ => continue,
+        };
+        let old = match known.remove(unique) {
+            Some(old) if !current.contains(old.as_str()) && is_under_dir(remote_dir, &old) => old,
+            _ => continue,
+        };
+
+        if fs::rename(local_path_for(local_dir, remote_dir, &old), &job.local).is_ok() {
+            cache.rename(&old, job.remote.clone());
+            moved.insert(job.remote.clone());
+        }
+    }
+
+    moved
+}
+
+impl FtpClient {
+    fn collect_download_jobs(&mut self, remote_dir: &str, local_dir: &Path, options: &FtpMirrorOptions) -> FtpResult<Vec<FtpDownloadJob>> {
+        fs::create_dir_all(local_dir).map_err(FtpError::ConnectionError)?;
+
+        let mut jobs = vec![];
+        // all a job needs, and `type` for the walk itself
+        let walk_options = FtpWalkOptions {
+            follow_symlinks: options.follow_symlinks,
+            facts: Some(vec![MlstFact::Ty, MlstFact::Size, MlstFact::Modify, MlstFact::Unique]),
+            ..FtpWalkOptions::default()
+        };
+
+        self.walk_with(remote_dir, &walk_options, |entry| {
+            if wildcard_match_any(&options.exclude, &entry.item.name) {
+                return Ok(FtpWalkControl::SkipDir);
+            }
+
+            // a link the walker didn't follow
+            if entry.item.ty.is_symlink() {
+                return Ok(FtpWalkControl::Continue);
+            }
+
+            if !is_safe_name(&entry.item.name) {
+                return Err(unsafe_name_error(&entry.item.name));
+            }
+
+            let local = local_path_for(local_dir, remote_dir, &entry.path);
+
+            if entry.item.ty.is_dir() {
+                fs::create_dir_all(&local).map_err(FtpError::ConnectionError)?;
+            } else {
+                jobs.push(FtpDownloadJob {
+                    remote: entry.path.clone(),
+                    local,
+                    size: entry.item.size,
+                    modified: entry.item.modified,
+                    unique: entry.item.unique.clone(),
+                });
+            }
+
+            Ok(FtpWalkControl::Continue)
+        })?;
+
+        Ok(jobs)
+    }
+
+    fn download_job(&mut self, job: &FtpDownloadJob, options: &FtpMirrorOptions, cache: Option<&Mutex<FtpChecksumCache>>) -> FtpResult<Option<u64>> {
+        self.wait_while_paused();
+        let transfer = FtpMirrorTransfer {
+            direction: FtpMirrorDirection::Download,
+            remote: job.remote.clone(),
+            local: job.local.clone(),
+        };
+
+        options.with_hooks(transfer, |transfer| {
+            let job = FtpDownloadJob {
+                remote: transfer.remote.clone(),
+                local: transfer.local.clone(),
+                ..job.clone()
+            };
+            self.download_unless_unchanged(&job, options, cache)
+        })
+    }
+
+    fn download_unless_unchanged(&mut self, job: &FtpDownloadJob, options: &FtpMirrorOptions, cache: Option<&Mutex<FtpChecksumCache>>) -> FtpResult<Option<u64>> {
+        let known = cache.and_then(|cache| lock(cache).get(&job.remote).cloned());
+        let remote_unchanged = match (known, job.size, modified_secs(job.modified)) {
+            (Some(known), Some(size), Some(modified)) => known.size == size && known.modified == Some(modified),
+            _ => false,
+        };
+
+        // the remote side is unchanged, so only the local copy has to be verified
+        if let Some(known) = known.filter(|_| remote_unchanged) {
+            if file_checksum(&job.local).is_ok_and(|checksum| checksum == known.checksum) {
+                return Ok(None);
+            }
+        }
+
+        let size = match self.download_file_checked(&job.remote, &job.local, &options.transfer_options())? {
+            Some(size) => size,
+            None => return Ok(None),
+        };
+
+        if options.preserve_mtime {
+            if let Some(modified) = job.modified {
+                self.set_local_mtime(&job.local, modified)?;
+            }
+        }
+
+        if let Some(cache) = cache {
+            let entry = FtpChecksumEntry {
+                size: job.size.unwrap_or_default(),
+                modified: modified_secs(job.modified),
+                checksum: file_checksum(&job.local).map_err(FtpError::ConnectionError)?,
+                unique: job.unique.clone(),
+            };
+            lock(cache).insert(job.remote.clone(), entry);
+        }
+
+        Ok(Some(size))
+    }
+
+    pub fn download_dir<P: AsRef<Path>>(&mut self, remote_dir: &str, local_dir: P, options: &FtpMirrorOptions) -> FtpResult<SyncReport> {
+        let started = Instant::now();
+        let jobs = self.collect_download_jobs(remote_dir, local_dir.as_ref(), options)?;
+        let cache = open_checksum_cache(options)?;
+
+        let moved = match cache.as_ref() {
+            Some(cache) => apply_remote_moves(&jobs, remote_dir, local_dir.as_ref(), cache),
+            None => HashSet::new(),
+        };
+
+        let (mut report, retry_stats) = self.counting_retries(|client| {
+            let mut report = SyncReport { moved: moved.len(), ..SyncReport::default() };
+            for job in &jobs {
+                let result = client.download_job(job, options, cache.as_ref());
+                report.record_download(&moved, &job.remote, result);
+            }
+            report
+        });
+        report.retry_stats = retry_stats;
+
+        save_checksum_cache(cache)?;
+        report.duration = started.elapsed();
+        Ok(report)
+    }
+}
+
+impl FtpPool {
+    pub fn download_dir<P: AsRef<Path>>(&self, remote_dir: &str, local_dir: P, options: &FtpMirrorOptions) -> FtpResult<SyncReport> {
+        let started = Instant::now();
+        let jobs = self.get().collect_download_jobs(remote_dir, local_dir.as_ref(), options)?;
+        let cache = open_checksum_cache(options)?;
+
+        let moved = match cache.as_ref() {
+            Some(cache) => apply_remote_moves(&jobs, remote_dir, local_dir.as_ref(), cache),
+            None => HashSet::new(),
+        };
+
+        let report = Mutex::new(SyncReport { moved: moved.len(), ..SyncReport::default() });
+        let retry_stats = run_parallel(self, jobs, options.concurrency, |client, job| {
+            let result = client.download_job(job, options, cache.as_ref());
+            lock(&report).record_download(&moved, &job.remote, result);
+            Ok(())
+        })?;
+
+        save_checksum_cache(cache)?;
+        let mut report = report.into_inner().unwrap_or_else(|e| e.into_inner());
+        report.retry_stats = retry_stats;
+        report.duration = started.elapsed();
+        Ok(report)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone)]
+struct FtpUploadJob {
+    local:      PathBuf,
+    remote:     String,
+}
+
+impl FtpClient {
+    fn upload_job(&mut self, job: &FtpUploadJob, options: &FtpMirrorOptions, cache: Option<&Mutex<FtpChecksumCache>>) -> FtpResult<Option<u64>> {
+        self.wait_while_paused();
+        let transfer = FtpMirrorTransfer {
+            direction: FtpMirrorDirection::Upload,
+            remote: job.remote.clone(),
+            local: job.local.clone(),
+        };
+
+        options.with_hooks(transfer, |transfer| {
+            let job = FtpUploadJob {
+                local: transfer.local.clone(),
+                remote: transfer.remote.clone(),
+            };
+            self.upload_unless_unchanged(&job, options, cache)
+        })
+    }
+
+    fn upload_unless_unchanged(&mut self, job: &FtpUploadJob, options: &FtpMirrorOptions, cache: Option<&Mutex<FtpChecksumCache>>) -> FtpResult<Option<u64>> {
+        let transfer_options = options.transfer_options();
+        let cache = match cache {
+            Some(cache) => cache,
+            None => return self.upload_file_checked(&job.local, &job.remote, &transfer_options),
+        };
+
+        let known = lock(cache).get(&job.remote).cloned();
+        let (entry, unchanged) = local_entry(&job.local, known.as_ref()).map_err(FtpError::ConnectionError)?;
+        let size = if unchanged {
+            None
+        } else {
+            // a file the policy skips still differs from the one on the server
+            match self.upload_file_checked(&job.local, &job.remote, &transfer_options)? {
+                Some(size) => Some(size),
+                None => return Ok(None),
+            }
+        };
+
+        lock(cache).insert(job.remote.clone(), entry);
+        Ok(size)
+    }
+
+    fn ensure_dir(&mut self, path: &str) -> FtpResult<()> {
+        match self.mkdir(path) {
+            Err(FtpError::UnexpectedResponse(_)) => Ok(()),
+            result => result,
+        }
+    }
+
+    fn ensure_dir_all(&mut self, path: &str) -> FtpResult<()> {
+        let mut current = if path.starts_with('/') { "/".to_string() } else { String::new() };
+
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            current = join_path(&current, name);
+            self.ensure_dir(&current)?;
+        }
+
+        Ok(())
+    }
+
+    fn collect_upload_jobs(&mut self, local_dir: &Path, remote_dir: &str, options: &FtpMirrorOptions) -> FtpResult<Vec<FtpUploadJob>> {
+        self.ensure_dir_all(remote_dir)?;
+
+        let mut jobs = vec![];
+        let mut visited = HashSet::new();
+        if let Ok(canonical) = fs::canonicalize(local_dir) {
+            visited.insert(canonical);
+        }
+
+        let mut stack = vec![(local_dir.to_path_buf(), remote_dir.to_string())];
+
+        while let Some((local, remote)) = stack.pop() {
+            for entry in fs::read_dir(&local).map_err(FtpError::ConnectionError)? {
+                let entry = entry.map_err(FtpError::ConnectionError)?;
+                let name = entry.file_name()
+                    .into_string()
+                    .map_err(|name| invalid_data_error(format!("local file name {:?} is not valid UTF-8", name)))?;
+
+                if wildcard_match_any(&options.exclude, &name) {
+                    continue;
+                }
+
+                let file_type = entry.file_type().map_err(FtpError::ConnectionError)?;
+                let (is_dir, is_file) = if file_type.is_symlink() {
+                    if options.follow_symlinks == FollowSymlinks::Never {
+                        continue;
+                    }
+                    let metadata = fs::metadata(entry.path()).map_err(FtpError::ConnectionError)?;
+                    (metadata.is_dir(), metadata.is_file())
+                } else {
+                    (file_type.is_dir(), file_type.is_file())
+                };
+
+                let remote_path = join_path(&remote, &name);
+
+                if is_dir {
+                    if let Ok(canonical) = fs::canonicalize(entry.path()) {
+                        if !visited.insert(canonical) {
+                            continue;
+                        }
+                    }
+                    self.ensure_dir(&remote_path)?;
+                    stack.push((entry.path(), remote_path));
+                } else if is_file {
+                    jobs.push(FtpUploadJob {
+                        local: entry.path(),
+                        remote: remote_path,
+                    });
+                }
+            }
+        }
+
+        Ok(jobs)
+    }
+
+    pub fn upload_dir<P: AsRef<Path>>(&mut self, local_dir: P, remote_dir: &str, options: &FtpMirrorOptions) -> FtpResult<SyncReport> {
+        let started = Instant::now();
+        let jobs = self.collect_upload_jobs(local_dir.as_ref(), remote_dir, options)?;
+        let cache = open_checksum_cache(options)?;
+
+        let (mut report, retry_stats) = self.counting_retries(|client| {
+            let mut report = SyncReport::default();
+            for job in &jobs {
+                let result = client.upload_job(job, options, cache.as_ref());
+                report.record(FtpMirrorDirection::Upload, &job.remote, result);
+            }
+            report
+        });
+        report.retry_stats = retry_stats;
+
+        save_checksum_cache(cache)?;
+        report.duration = started.elapsed();
+        Ok(report)
+    }
+}
+
+impl FtpPool {
+    pub fn upload_dir<P: AsRef<Path>>(&self, local_dir: P, remote_dir: &str, options: &FtpMirrorOptions) -> FtpResult<SyncReport> {
+        let started = Instant::now();
+        let jobs = self.get().collect_upload_jobs(local_dir.as_ref(), remote_dir, options)?;
+        let cache = open_checksum_cache(options)?;
+
+        let report = Mutex::new(SyncReport::default());
+        let retry_stats = run_parallel(self, jobs, options.concurrency, |client, job| {
+            let result = client.upload_job(job, options, cache.as_ref());
+            lock(&report).record(FtpMirrorDirection::Upload, &job.remote, result);
+            Ok(())
+        })?;
+
+        save_checksum_cache(cache)?;
+        let mut report = report.into_inner().unwrap_or_else(|e| e.into_inner());
+        report.retry_stats = retry_stats;
+        report.duration = started.elapsed();
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::mock::support::*;
+
+    #[test]
+    fn test_mirror_report() {
+        let server = server();
+        let mut client = FtpClient::new(settings(&server));
+
+        let local = temp_path("mirror");
+        let report = client.download_dir("/data", &local, &FtpMirrorOptions::default()).unwrap();
+        assert_eq!((report.downloaded, report.skipped, report.bytes), (1, 0, 5));
+        assert!(report.is_success());
+        // the listing opened the connection, the download had nothing to retry
+        assert_eq!(report.retry_stats, FtpRetryStats::default());
+
+        #[derive(Debug)]
+        struct RenameHook;
+
+        impl FtpMirrorHook for RenameHook {
+            fn before_transfer(&self, transfer: &mut FtpMirrorTransfer) -> bool {
+                transfer.local.set_extension("bak");
+                true
+            }
+        }
+
+        let options = FtpMirrorOptions { hooks: vec![Arc::new(RenameHook)], ..FtpMirrorOptions::default() };
+        client.download_dir("/data", &local, &options).unwrap();
+        assert!(local.join("a.bak").is_file());
+        std::fs::remove_dir_all(&local).unwrap();
+    }
+
+    #[test]
+    fn test_mirror_facts() {
+        let server = server();
+        server.set_features(vec!["MLST type*;size*;modify*;unique*;perm*;".to_string()]);
+        let mut client = FtpClient::new(settings(&server));
+
+        let local = temp_path("mirror-facts");
+        let report = client.download_dir("/data", &local, &FtpMirrorOptions::default()).unwrap();
+        assert_eq!((report.downloaded, report.bytes), (1, 5));
+
+        // the listing only asks for what the jobs use, and perm is selected again afterwards
+        let commands: Vec<_> = server.commands().into_iter().filter(|command| command.starts_with("OPTS") || command.starts_with("MLSD")).collect();
+        assert_eq!(commands, ["OPTS MLST type;size;modify;unique;", "MLSD /data", "OPTS MLST type;size;modify;unique;perm;"]);
+        std::fs::remove_dir_all(&local).unwrap();
+    }
+
+    #[test]
+    fn test_mirror_remote_move() {
+        let server = server();
+        let mut client = FtpClient::new(settings(&server));
+
+        let local = temp_path("mirror-move");
+        let options = FtpMirrorOptions { checksum_cache: Some(local.with_extension("cache")), ..FtpMirrorOptions::default() };
+        let report = client.download_dir("/data", &local, &options).unwrap();
+        assert_eq!((report.downloaded, report.moved), (1, 0));
+
+        server.rename("/data/a.txt", "/data/b.txt");
+        let report = client.download_dir("/data", &local, &options).unwrap();
+        assert_eq!((report.downloaded, report.skipped, report.moved), (0, 0, 1));
+        assert!(!local.join("a.txt").exists());
+        assert_eq!(std::fs::read_to_string(local.join("b.txt")).unwrap(), "hello");
+
+        let report = client.download_dir("/data", &local, &options).unwrap();
+        assert_eq!((report.downloaded, report.skipped, report.moved), (0, 1, 0));
+
+        std::fs::remove_dir_all(&local).unwrap();
+        std::fs::remove_file(local.with_extension("cache")).unwrap();
+    }
+}
@@ -1,10 +1,9 @@
-use chrono::{NaiveDateTime, NaiveDate, NaiveTime, Local, TimeZone, DateTime};
-use std::collections::HashMap;
+use chrono::{DateTime, NaiveDateTime, NaiveDate, NaiveTime};
 use std::convert::{From, TryFrom, TryInto};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use suppaftp::list;
-use crate::types::{FtpItem, FtpItemType};
+use crate::types::{FtpItem, FtpItemType, FtpOtherFacts, FtpStringInterner};
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -99,25 +98,49 @@ pub enum MlstFact {
     UnixMode,         
 }
 
+impl MlstFact {
+    // as spelled in FEAT, MLSD lines and OPTS MLST
+    pub fn name(&self) -> &str {
+        match self {
+            MlstFact::Other(name) => name,
+            MlstFact::Ty => "type",
+            MlstFact::Size => "size",
+            MlstFact::Modify => "modify",
+            MlstFact::Create => "create",
+            MlstFact::Unique => "unique",
+            MlstFact::Perm => "perm",
+            MlstFact::Lang => "lang",
+            MlstFact::MediaType => "media-type",
+            MlstFact::Charset => "charset",
+            MlstFact::UnixOwner => "unix.owner",
+            MlstFact::UnixOwnerName => "unix.ownername",
+            MlstFact::UnixGroup => "unix.group",
+            MlstFact::UnixGroupName => "unix.groupname",
+            MlstFact::UnixMode => "unix.mode",
+        }
+    }
+}
+
 impl From<&str> for MlstFact {
+    // case-insensitive on the slice; only an unknown fact allocates
     fn from(name: &str) -> Self {
-        let name = name.to_ascii_lowercase();
-        match name.as_str() {
-            "size" => MlstFact::Size,
-            "modify" => MlstFact::Modify,
-            "create" => MlstFact::Create,
-            "type" => MlstFact::Ty,
-            "unique" => MlstFact::Unique,
-            "perm" => MlstFact::Perm,
-            "lang" => MlstFact::Lang,
-            "media-type" => MlstFact::MediaType,
-            "charset" => MlstFact::Charset,
-            "unix.owner" => MlstFact::UnixOwner,
-            "unix.ownername" => MlstFact::UnixOwnerName,
-            "unix.group" => MlstFact::UnixGroup,
-            "unix.groupname" => MlstFact::UnixGroupName,
-            "unix.mode" => MlstFact::UnixMode,
-            _ => MlstFact::Other(name)
+        let is = |known: &str| name.eq_ignore_ascii_case(known);
+        match name {
+            _ if is("size") => MlstFact::Size,
+            _ if is("modify") => MlstFact::Modify,
+            _ if is("create") => MlstFact::Create,
+            _ if is("type") => MlstFact::Ty,
+            _ if is("unique") => MlstFact::Unique,
+            _ if is("perm") => MlstFact::Perm,
+            _ if is("lang") => MlstFact::Lang,
+            _ if is("media-type") => MlstFact::MediaType,
+            _ if is("charset") => MlstFact::Charset,
+            _ if is("unix.owner") => MlstFact::UnixOwner,
+            _ if is("unix.ownername") => MlstFact::UnixOwnerName,
+            _ if is("unix.group") => MlstFact::UnixGroup,
+            _ if is("unix.groupname") => MlstFact::UnixGroupName,
+            _ if is("unix.mode") => MlstFact::UnixMode,
+            _ => MlstFact::Other(name.to_ascii_lowercase())
         }
     }
 }
@@ -187,6 +210,12 @@ pub fn parse_mlst_feat(line: &str) -> Vec<(MlstFact, bool)> {
 }
 
 pub fn parse_mlst_line(line: &str) -> Result<FtpItem, list::ParseError> {
+    parse_mlst_line_interned(line, &mut FtpStringInterner::default())
+}
+
+// Like `parse_mlst_line`, with the text facts taken from `strings`; pass the
+// same interner for every line of a listing.
+pub fn parse_mlst_line_interned(line: &str, strings: &mut FtpStringInterner) -> Result<FtpItem, list::ParseError> {
 
     let mut file_ty:        Option<_> = None;
     let mut file_size:      Option<_> = None;
@@ -202,6 +231,7 @@ pub fn parse_mlst_line(line: &str) -> Result<FtpItem, list::ParseError> {
     let mut file_unix_group:        Option<_> = None;
     let mut file_unix_groupname:    Option<_> = None;
     let mut file_unix_mode:     Option<_> = None;
+    let mut file_link_target:   Option<_> = None;
     let mut file_others:        Option<_> = None;
 
     let mut fact_name   = String::with_capacity(20);
@@ -215,6 +245,7 @@ pub fn parse_mlst_line(line: &str) -> Result<FtpItem, list::ParseError> {
     const SPACE:        char = ' ';
     const EQUAL:        char = '=';
     const SEMICOLON:    char = ';';
+    const SLINK_PREFIX: &str = "OS.unix=slink:";
 
     let mut state = FSM::Name;
 
@@ -240,7 +271,8 @@ pub fn parse_mlst_line(line: &str) -> Result<FtpItem, list::ParseError> {
                 }
             },
             FSM::Value => {
-                if ch == SPACE || ch == EQUAL {
+                // values may contain '=', as in `type=OS.unix=symlink`
+                if ch == SPACE {
                     return Err(list::ParseError::SyntaxError);
                 } else if ch == SEMICOLON {
                     // do nothing, just move on
@@ -265,6 +297,9 @@ pub fn parse_mlst_line(line: &str) -> Result<FtpItem, list::ParseError> {
                     },
                     MlstFact::Ty => {
                         file_ty = Some(fact_value.as_str().try_into()?);
+                        if fact_value.get(..SLINK_PREFIX.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(SLINK_PREFIX)) {
+                            file_link_target = Some(fact_value[SLINK_PREFIX.len()..].to_string());
+                        }
                     },
                     MlstFact::Unique => {
                         file_unique = Some(fact_value.clone());
@@ -273,31 +308,31 @@ pub fn parse_mlst_line(line: &str) -> Result<FtpItem, list::ParseError> {
                         file_perm = Some(fact_value.as_str().try_into()?);
                     },
                     MlstFact::Lang => {
-                        file_lang = Some(fact_value.clone());
+                        file_lang = Some(strings.intern(&fact_value));
                     },
                     MlstFact::MediaType => {
-                        file_media_type = Some(fact_value.clone());
+                        file_media_type = Some(strings.intern(&fact_value));
                     },
                     MlstFact::Charset => {
-                        file_charset = Some(fact_value.clone());
+                        file_charset = Some(strings.intern(&fact_value));
                     },
                     MlstFact::UnixOwner => {
                         file_unix_owner = Some(fact_value.parse().map_err(|_| list::ParseError::SyntaxError)?);
                     },
                     MlstFact::UnixOwnerName => {
-                        file_unix_ownername = Some(fact_value.clone());
+                        file_unix_ownername = Some(strings.intern(&fact_value));
                     },
                     MlstFact::UnixGroup => {
                         file_unix_group = Some(fact_value.parse().map_err(|_| list::ParseError::SyntaxError)?);
                     },
                     MlstFact::UnixGroupName => {
-                        file_unix_groupname = Some(fact_value.clone());
+                        file_unix_groupname = Some(strings.intern(&fact_value));
                     },
                     MlstFact::UnixMode => {
                         file_unix_mode = Some(u16::from_str_radix(&fact_value, 8).map_err(|_| list::ParseError::SyntaxError)?);
                     },
                     MlstFact::Other(fact_name) => {
-                        file_others.get_or_insert_with(|| HashMap::new()).insert( fact_name, fact_value.clone() );
+                        file_others.get_or_insert_with(FtpOtherFacts::default).insert(fact_name, fact_value.clone());
                     }
                 }
 
@@ -318,6 +353,7 @@ pub fn parse_mlst_line(line: &str) -> Result<FtpItem, list::ParseError> {
 
     let file = FtpItem {
         name,
+        path: None,
         ty: ty_unwrapped,
         size:   file_size,
         modified: file_modify,
@@ -332,25 +368,50 @@ pub fn parse_mlst_line(line: &str) -> Result<FtpItem, list::ParseError> {
         unix_group:     file_unix_group,
         unix_groupname: file_unix_groupname,
         unix_mode:  file_unix_mode,
+        link_target:    file_link_target,
         others:     file_others,        
     };
 
     Ok(file)
 }
 
-fn systemtime_to_naivedatetime( t: SystemTime ) -> NaiveDateTime {
-    let dt: DateTime<Local> = t.into();
-    dt.naive_local()    
+// `FtpItem` times are UTC, as MLSD reports them; these convert them to and from
+// `SystemTime` without going through the local timezone. `None` for a time the
+// other type can't represent.
+pub fn naivedatetime_to_systemtime( t: NaiveDateTime ) -> Option<SystemTime> {
+    let t = t.and_utc();
+    let secs = t.timestamp();
+    let whole = if secs >= 0 {
+        UNIX_EPOCH.checked_add(Duration::from_secs(secs as u64))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs()))
+    };
+    whole?.checked_add(Duration::from_nanos(t.timestamp_subsec_nanos() as u64))
+}
+
+pub fn systemtime_to_naivedatetime( t: SystemTime ) -> Option<NaiveDateTime> {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(since) => from_timestamp(i64::try_from(since.as_secs()).ok()?, since.subsec_nanos()),
+        Err(e) => {
+            // rounded down to a whole second, so that the nanoseconds stay positive
+            let before = e.duration();
+            let secs = i64::try_from(before.as_secs()).ok()?;
+            match before.subsec_nanos() {
+                0 => from_timestamp(-secs, 0),
+                nanos => from_timestamp(-secs - 1, 1_000_000_000 - nanos),
+            }
+        },
+    }
 }
 
-fn naivedatetime_to_systemtime( t: NaiveDateTime ) -> SystemTime {
-    Local.from_local_datetime(&t).unwrap().into()
+fn from_timestamp(secs: i64, nanos: u32) -> Option<NaiveDateTime> {
+    DateTime::from_timestamp(secs, nanos).map(|dt| dt.naive_utc())
 }
 
 pub fn ftp_to_list( file: FtpItem ) -> list::File {
     let is_dir      = file.ty.is_dir();
     let size        = file.size.unwrap_or(0);
-    let modified    = naivedatetime_to_systemtime( file.modified.unwrap_or(NaiveDateTime::from_timestamp(0, 0)) );
+    let modified    = file.modified.and_then(naivedatetime_to_systemtime).unwrap_or(UNIX_EPOCH);
     let pex         = file.perm.as_ref().map(MlstFilePermissions::as_pex).unwrap_or(0);
 
     list::File::from_raw(file.name, is_dir, size as usize, modified, file.unix_owner, file.unix_group, (pex, pex, pex))
@@ -381,11 +442,16 @@ pub fn list_to_ftp( file: &list::File ) -> FtpItem {
             ".." => FtpItemType::ParentDir,
             _ => FtpItemType::Dir
         }
+    } else if file.is_symlink() {
+        FtpItemType::Symlink
     } else {
         FtpItemType::File
     };
+    let link_target = file.symlink().map(|target| target.to_string_lossy().into_owned());
     let size    = Some(file.size() as u64);
-    let modified  = Some(systemtime_to_naivedatetime(file.modified()));
+    // the LIST parser stores the listed wall-clock time as if it were UTC; the
+    // client converts it with its `FtpListTimezone`
+    let modified  = systemtime_to_naivedatetime(file.modified());
 
     let mut perm: MlstFilePermissions = Default::default();
     perm.read   = file.can_read(list::PosixPexQuery::Owner);
@@ -396,6 +462,7 @@ pub fn list_to_ftp( file: &list::File ) -> FtpItem {
 
     FtpItem {
         name,
+        path: None,
         ty,
         size,
         modified,
@@ -410,6 +477,7 @@ pub fn list_to_ftp( file: &list::File ) -> FtpItem {
         unix_group:     file.gid(),
         unix_groupname: None,
         unix_mode,
+        link_target,
         others: None, 
     }
 }
@@ -424,4 +492,61 @@ impl From<list::File> for FtpItem {
     fn from(file: list::File) -> Self {
         list_to_ftp(&file)    
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_symlink_line() {
+        let link = parse_mlst_line("type=OS.unix=slink:/srv/data;unique=801g4; data").unwrap();
+        assert_eq!(link.ty, FtpItemType::Symlink);
+        assert_eq!(link.link_target.as_deref(), Some("/srv/data"));
+        assert_eq!(link.unique.as_deref(), Some("801g4"));
+
+        let link = parse_mlst_line("type=OS.unix=symlink;size=4; latest").unwrap();
+        assert_eq!(link.ty, FtpItemType::Symlink);
+        assert_eq!(link.link_target, None);
+    }
+
+    #[test]
+    fn test_interned_facts() {
+        let mut strings = FtpStringInterner::default();
+        let a = parse_mlst_line_interned("type=file;unix.ownername=ftp;unix.groupname=ftp; a.txt", &mut strings).unwrap();
+        let b = parse_mlst_line_interned("type=file;unix.ownername=ftp;charset=UTF-8; b.txt", &mut strings).unwrap();
+
+        assert_eq!(a.unix_ownername.as_deref(), Some("ftp"));
+        assert!(std::sync::Arc::ptr_eq(a.unix_ownername.as_ref().unwrap(), b.unix_ownername.as_ref().unwrap()));
+        assert!(std::sync::Arc::ptr_eq(a.unix_groupname.as_ref().unwrap(), a.unix_ownername.as_ref().unwrap()));
+        assert_eq!(strings.len(), 2);
+    }
+
+    #[test]
+    fn test_systemtime_conversions() {
+        let time = NaiveDateTime::parse_from_str("2021-03-28 02:30:00.250", "%Y-%m-%d %H:%M:%S%.f").unwrap();
+        let system = naivedatetime_to_systemtime(time).unwrap();
+        assert_eq!(system.duration_since(UNIX_EPOCH).unwrap(), Duration::new(1616898600, 250_000_000));
+        assert_eq!(systemtime_to_naivedatetime(system), Some(time));
+
+        let before = NaiveDateTime::parse_from_str("1969-12-31 23:59:59.500", "%Y-%m-%d %H:%M:%S%.f").unwrap();
+        assert_eq!(naivedatetime_to_systemtime(before).and_then(systemtime_to_naivedatetime), Some(before));
+    }
+
+    #[test]
+    fn test_fact_names() {
+        assert_eq!(MlstFact::from("Unix.OwnerName"), MlstFact::UnixOwnerName);
+        assert_eq!(MlstFact::from("TYPE"), MlstFact::Ty);
+        assert_eq!(MlstFact::from("X.Tag"), MlstFact::Other("x.tag".to_string()));
+    }
+
+    #[test]
+    fn test_other_facts() {
+        let item = parse_mlst_line("type=file;x.Tag=b;x.id=7;size=3; a.txt").unwrap();
+        let others = item.others.unwrap();
+        assert_eq!(others.iter().collect::<Vec<_>>(), vec![("x.id", "7"), ("x.tag", "b")]);
+        assert_eq!(others.get("x.tag"), Some("b"));
+
+        assert!(parse_mlst_line("type=file;size=3; a.txt").unwrap().others.is_none());
+    }
+}
@@ -0,0 +1,555 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use crate::{
+    path::{join_path, parent_path},
+    types::FtpItemType,
+    vfs::{FtpFs, FtpMemoryFs},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub const MOCK_SERVER_LOGIN: &str = "test";
+pub const MOCK_SERVER_PASSWORD: &str = "test";
+
+const MOCK_MODIFY: &str = "20210101000000";
+
+#[derive(Debug, Default)]
+struct FtpMockShared {
+    fs: Mutex<FtpMemoryFs>,
+    features: Mutex<Vec<String>>,
+    commands: Mutex<Vec<String>>,
+    sessions: Mutex<Vec<TcpStream>>,
+    // `unique` facts by path; a rename keeps them
+    uniques: Mutex<HashMap<String, usize>>,
+    next_unique: AtomicUsize,
+    connections: AtomicUsize,
+    shutdown: AtomicBool,
+}
+
+impl FtpMockShared {
+    fn unique(&self, path: &str) -> usize {
+        *lock(&self.uniques).entry(path.to_string()).or_insert_with(|| self.next_unique.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> bool {
+        if lock(&self.fs).rename(from, to).is_err() {
+            return false;
+        }
+
+        let mut uniques = lock(&self.uniques);
+        let prefix = join_path(from, "");
+        let moved: Vec<String> = uniques.keys().filter(|path| *path == from || path.starts_with(&prefix)).cloned().collect();
+        for path in moved {
+            if let Some(unique) = uniques.remove(&path) {
+                uniques.insert(format!("{}{}", to, &path[from.len()..]), unique);
+            }
+        }
+        true
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Debug)]
+pub struct FtpMockServer {
+    addr: SocketAddr,
+    shared: Arc<FtpMockShared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl FtpMockServer {
+    pub fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        let shared = Arc::new(FtpMockShared::default());
+        *lock(&shared.features) = vec![
+            "MLST type*;size*;modify*;unique*;".to_string(),
+            "UTF8".to_string(),
+            "SIZE".to_string(),
+            "MDTM".to_string(),
+        ];
+
+        let accept_shared = shared.clone();
+        let handle = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accept_shared.shutdown.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                if let Ok(stream) = stream {
+                    accept_shared.connections.fetch_add(1, Ordering::SeqCst);
+                    if let Ok(session) = stream.try_clone() {
+                        lock(&accept_shared.sessions).push(session);
+                    }
+
+                    let session_shared = accept_shared.clone();
+                    thread::spawn(move || {
+                        let _ = FtpMockSession::new(session_shared, stream).and_then(FtpMockSession::run);
+                    });
+                }
+            }
+        });
+
+        Ok(Self {
+            addr,
+            shared,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+
+    pub fn add_dir(&self, path: &str) {
+        let _ = lock(&self.shared.fs).create_dir(path);
+    }
+
+    pub fn add_file<C: Into<Vec<u8>>>(&self, path: &str, content: C) {
+        lock(&self.shared.fs).insert_file(path, content);
+    }
+
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        lock(&self.shared.fs).file(path).map(<[u8]>::to_vec)
+    }
+
+    // As if renamed by another client, keeping the `unique` fact.
+    pub fn rename(&self, from: &str, to: &str) {
+        self.shared.rename(from, to);
+    }
+
+    pub fn set_features(&self, features: Vec<String>) {
+        *lock(&self.shared.features) = features;
+    }
+
+    pub fn commands(&self) -> Vec<String> {
+        lock(&self.shared.commands).clone()
+    }
+
+    pub fn connections(&self) -> usize {
+        self.shared.connections.load(Ordering::SeqCst)
+    }
+
+    pub fn drop_connections(&self) {
+        for session in lock(&self.shared.sessions).drain(..) {
+            let _ = session.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Drop for FtpMockServer {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        self.drop_connections();
+
+        // wake up the accept loop so it can observe the shutdown flag
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct FtpMockSession {
+    shared: Arc<FtpMockShared>,
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    cwd: String,
+    user_ok: bool,
+    logged_in: bool,
+    rename_from: Option<String>,
+    rest: usize,
+    passive: Option<TcpListener>,
+    // as selected with OPTS MLST
+    mlst_facts: Vec<String>,
+}
+
+impl FtpMockSession {
+    fn new(shared: Arc<FtpMockShared>, stream: TcpStream) -> io::Result<Self> {
+        Ok(Self {
+            shared,
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            cwd: "/".to_string(),
+            user_ok: false,
+            logged_in: false,
+            rename_from: None,
+            rest: 0,
+            passive: None,
+            mlst_facts: ["type", "size", "modify", "unique"].iter().map(|fact| fact.to_string()).collect(),
+        })
+    }
+
+    fn reply(&mut self, code: u32, text: &str) -> io::Result<()> {
+        write!(self.writer, "{} {}\r\n", code, text)
+    }
+
+    fn resolve(&self, arg: &str) -> String {
+        if arg.is_empty() {
+            self.cwd.clone()
+        } else if arg.starts_with('/') {
+            arg.to_string()
+        } else {
+            join_path(&self.cwd, arg)
+        }
+    }
+
+    fn data_connection(&mut self) -> io::Result<TcpStream> {
+        match self.passive.take() {
+            Some(listener) => Ok(listener.accept()?.0),
+            None => Err(io::Error::from(io::ErrorKind::NotConnected)),
+        }
+    }
+
+    fn send_data(&mut self, data: &[u8]) -> io::Result<()> {
+        self.reply(150, "Opening data connection")?;
+        let mut stream = self.data_connection()?;
+        stream.write_all(data)?;
+        drop(stream);
+        self.reply(226, "Transfer complete")
+    }
+
+    fn listing(&self, path: &str, mlsd: bool) -> Option<String> {
+        let mut fs = lock(&self.shared.fs);
+        let list = fs.read_dir(path).ok()?;
+
+        let lines = list.items.iter()
+            .map(|item| {
+                let size = item.size.unwrap_or(0);
+                let unique = self.shared.unique(&join_path(path, &item.name));
+                match (mlsd, item.ty == FtpItemType::File) {
+                    (true, file) => {
+                        let mut facts = vec![("type", if file { "file".to_string() } else { "dir".to_string() })];
+                        if file {
+                            facts.push(("size", size.to_string()));
+                        }
+                        facts.push(("modify", MOCK_MODIFY.to_string()));
+                        facts.push(("unique", format!("{:x}", unique)));
+
+                        let facts: String = facts.iter()
+                            .filter(|(name, _)| self.mlst_facts.iter().any(|selected| selected == name))
+                            .map(|(name, value)| format!("{}={};", name, value))
+                            .collect();
+                        format!("{} {}\r\n", facts, item.name)
+                    },
+                    (false, true) => format!("-rw-r--r-- 1 test test {} Jan 01  2021 {}\r\n", size, item.name),
+                    (false, false) => format!("drwxr-xr-x 1 test test 0 Jan 01  2021 {}\r\n", item.name),
+                }
+            })
+            .collect();
+
+        Some(lines)
+    }
+
+    fn run(mut self) -> io::Result<()> {
+        self.reply(220, "Mock FTP server ready")?;
+
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+
+            let line = line.trim_end_matches(|ch| ch == '\r' || ch == '\n');
+            lock(&self.shared.commands).push(line.to_string());
+
+            let (command, arg) = match line.split_once(' ') {
+                Some((command, arg)) => (command.to_ascii_uppercase(), arg.to_string()),
+                None => (line.to_ascii_uppercase(), String::new()),
+            };
+
+            if !self.handle(&command, &arg)? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn handle(&mut self, command: &str, arg: &str) -> io::Result<bool> {
+        match command {
+            "USER" => {
+                self.user_ok = arg == MOCK_SERVER_LOGIN;
+                self.reply(331, "Password required")?;
+            },
+            "PASS" => {
+                self.logged_in = self.user_ok && arg == MOCK_SERVER_PASSWORD;
+                if self.logged_in {
+                    self.reply(230, "Logged in")?;
+                } else {
+                    self.reply(530, "Login incorrect")?;
+                }
+            },
+            "FEAT" => {
+                let features = lock(&self.shared.features).clone();
+                write!(self.writer, "211-Features:\r\n")?;
+                for feature in features {
+                    write!(self.writer, " {}\r\n", feature)?;
+                }
+                self.reply(211, "End")?;
+            },
+            "QUIT" => {
+                self.reply(221, "Goodbye")?;
+                return Ok(false);
+            },
+            "NOOP" => self.reply(200, "OK")?,
+            _ if !self.logged_in => self.reply(530, "Not logged in")?,
+            "SYST" => self.reply(215, "UNIX Type: L8")?,
+            "OPTS" => {
+                let (option, facts) = arg.split_once(' ').unwrap_or((arg, ""));
+                if option.eq_ignore_ascii_case("MLST") {
+                    self.mlst_facts = facts.split(';').filter(|fact| !fact.is_empty()).map(str::to_ascii_lowercase).collect();
+                    let text = format!("MLST OPTS {}", facts);
+                    self.reply(200, &text)?;
+                } else {
+                    self.reply(501, "Option not understood")?;
+                }
+            },
+            "TYPE" => self.reply(200, "Type set")?,
+            "PWD" => {
+                let text = format!("\"{}\" is the current directory", self.cwd);
+                self.reply(257, &text)?;
+            },
+            "CWD" => {
+                let path = self.resolve(arg);
+                if lock(&self.shared.fs).read_dir(&path).is_ok() {
+                    self.cwd = path;
+                    self.reply(250, "Directory changed")?;
+                } else {
+                    self.reply(550, "No such directory")?;
+                }
+            },
+            "CDUP" => {
+                let parent = parent_path(&self.cwd);
+                self.cwd = if parent.is_empty() { "/".to_string() } else { parent.to_string() };
+                self.reply(250, "Directory changed")?;
+            },
+            "PASV" => {
+                let listener = TcpListener::bind("127.0.0.1:0")?;
+                let port = listener.local_addr()?.port();
+                self.passive = Some(listener);
+                let text = format!("Entering Passive Mode (127,0,0,1,{},{})", port >> 8, port & 0xff);
+                self.reply(227, &text)?;
+            },
+            "EPSV" if arg.eq_ignore_ascii_case("ALL") => {
+                self.reply(200, "EPSV ALL ok")?;
+            },
+            "EPSV" => {
+                let listener = TcpListener::bind("127.0.0.1:0")?;
+                let port = listener.local_addr()?.port();
+                self.passive = Some(listener);
+                let text = format!("Entering Extended Passive Mode (|||{}|)", port);
+                self.reply(229, &text)?;
+            },
+            "MLSD" | "LIST" | "NLST" => {
+                let path = self.resolve(arg.trim_start_matches("-a").trim());
+                let listing = match command {
+                    "MLSD" => self.listing(&path, true),
+                    "LIST" => self.listing(&path, false),
+                    _ => lock(&self.shared.fs).read_dir(&path).ok().map(|list| {
+                        list.items.iter().map(|item| format!("{}\r\n", item.name)).collect()
+                    }),
+                };
+                match listing {
+                    Some(listing) => self.send_data(listing.as_bytes())?,
+                    None => self.reply(550, "No such directory")?,
+                }
+            },
+            "RETR" => {
+                let path = self.resolve(arg);
+                let rest = std::mem::take(&mut self.rest);
+                let content = lock(&self.shared.fs).file(&path).map(|content| content.get(rest..).unwrap_or_default().to_vec());
+                match content {
+                    Some(content) => self.send_data(&content)?,
+                    None => self.reply(550, "No such file")?,
+                }
+            },
+            "STOR" => {
+                let path = self.resolve(arg);
+                self.reply(150, "Ready to receive")?;
+                let mut content = vec![];
+                self.data_connection()?.read_to_end(&mut content)?;
+                lock(&self.shared.fs).insert_file(&path, content);
+                self.reply(226, "Transfer complete")?;
+            },
+            "APPE" => {
+                let path = self.resolve(arg);
+                self.reply(150, "Ready to receive")?;
+                let mut content = vec![];
+                self.data_connection()?.read_to_end(&mut content)?;
+                let mut fs = lock(&self.shared.fs);
+                let mut file = fs.file(&path).map(<[u8]>::to_vec).unwrap_or_default();
+                file.extend(content);
+                fs.insert_file(&path, file);
+                drop(fs);
+                self.reply(226, "Transfer complete")?;
+            },
+            // COMB "target" "part" ...: the parts are joined in order and removed
+            "COMB" => {
+                let mut names = arg.split('"').skip(1).step_by(2).map(|name| self.resolve(name));
+                let target = names.next().unwrap_or_default();
+                let parts: Vec<String> = names.collect();
+
+                let mut fs = lock(&self.shared.fs);
+                let content: Option<Vec<u8>> = parts.iter()
+                    .map(|part| fs.file(part).map(<[u8]>::to_vec))
+                    .collect::<Option<Vec<_>>>()
+                    .map(|contents| contents.concat());
+                let combined = match content {
+                    Some(content) if !parts.is_empty() => {
+                        for part in &parts {
+                            let _ = fs.remove(part);
+                        }
+                        fs.insert_file(&target, content);
+                        true
+                    },
+                    _ => false,
+                };
+                drop(fs);
+
+                if combined {
+                    self.reply(250, "Files combined")?;
+                } else {
+                    self.reply(550, "Cannot combine")?;
+                }
+            },
+            "REST" => match arg.parse() {
+                Ok(offset) => {
+                    self.rest = offset;
+                    self.reply(350, "Restarting")?;
+                },
+                Err(_) => self.reply(501, "Invalid offset")?,
+            },
+            "SIZE" => {
+                let path = self.resolve(arg);
+                let size = lock(&self.shared.fs).file(&path).map(<[u8]>::len);
+                match size {
+                    Some(size) => self.reply(213, &size.to_string())?,
+                    None => self.reply(550, "No such file")?,
+                }
+            },
+            "MDTM" => {
+                let path = self.resolve(arg);
+                let exists = lock(&self.shared.fs).file(&path).is_some();
+                if exists {
+                    self.reply(213, MOCK_MODIFY)?;
+                } else {
+                    self.reply(550, "No such file")?;
+                }
+            },
+            "MKD" => {
+                let path = self.resolve(arg);
+                let result = lock(&self.shared.fs).create_dir(&path);
+                match result {
+                    Ok(()) => self.reply(257, "Directory created")?,
+                    Err(_) => self.reply(550, "Cannot create directory")?,
+                }
+            },
+            "DELE" | "RMD" => {
+                let path = self.resolve(arg);
+                let result = lock(&self.shared.fs).remove(&path);
+                match result {
+                    Ok(()) => {
+                        lock(&self.shared.uniques).remove(&path);
+                        self.reply(250, "Removed")?
+                    },
+                    Err(_) => self.reply(550, "Cannot remove")?,
+                }
+            },
+            "RNFR" => {
+                self.rename_from = Some(self.resolve(arg));
+                self.reply(350, "Ready for RNTO")?;
+            },
+            "RNTO" => {
+                let to = self.resolve(arg);
+                let result = match self.rename_from.take() {
+                    Some(from) => self.shared.rename(&from, &to),
+                    None => false,
+                };
+                if result {
+                    self.reply(250, "Renamed")?;
+                } else {
+                    self.reply(550, "Cannot rename")?;
+                }
+            },
+            _ => self.reply(502, "Command not implemented")?,
+        }
+
+        Ok(true)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Fixtures for the crate's own tests of the modules built on FtpClient.
+#[cfg(test)]
+pub(crate) mod support {
+
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use crate::client::{FtpClient, FtpClientListMode, FtpClientSettings};
+    use crate::pool::FtpPool;
+    use crate::transport::fake::{FtpFakeConnector, FtpFakeState};
+    use super::{FtpMockServer, MOCK_SERVER_LOGIN, MOCK_SERVER_PASSWORD};
+
+    #[derive(Debug)]
+    pub struct TestSettings {
+        pub addr: String,
+        pub list_mode: Option<FtpClientListMode>,
+    }
+
+    impl FtpClientSettings for TestSettings {
+        fn addr(&self) -> &str { &self.addr }
+        fn login(&self) -> &str { MOCK_SERVER_LOGIN }
+        fn password(&self) -> &str { MOCK_SERVER_PASSWORD }
+        fn remote_dir(&self) -> Option<&str> { None }
+        fn account(&self) -> Option<&str> { Some("acct") }
+
+        #[cfg(feature = "sync-secure")]
+        fn use_secure(&self) -> bool { false }
+
+        #[cfg(feature = "sync-secure")]
+        fn sni(&self) -> Option<&str> { None }
+
+        fn list_mode(&self) -> Option<FtpClientListMode> {
+            self.list_mode
+        }
+    }
+
+    // /data holding a.txt ("hello"), and /readme ("readme")
+    pub fn server() -> FtpMockServer {
+        let server = FtpMockServer::start().unwrap();
+        server.add_dir("/data");
+        server.add_file("/data/a.txt", "hello");
+        server.add_file("/readme", "readme");
+        server
+    }
+
+    pub fn settings(server: &FtpMockServer) -> Box<dyn FtpClientSettings> {
+        Box::new(TestSettings { addr: server.addr(), list_mode: Some(FtpClientListMode::Mlsd) })
+    }
+
+    pub fn pool(server: &FtpMockServer, max_size: usize) -> FtpPool {
+        let addr = server.addr();
+        FtpPool::new(max_size, move || Box::new(TestSettings { addr: addr.clone(), list_mode: Some(FtpClientListMode::Mlsd) }))
+    }
+
+    pub fn fake_client(list_mode: Option<FtpClientListMode>) -> (FtpClient, Arc<Mutex<FtpFakeState>>) {
+        let connector = FtpFakeConnector::default();
+        let state = connector.state.clone();
+        let settings = Box::new(TestSettings { addr: String::new(), list_mode });
+        (FtpClient::with_connector(settings, Box::new(connector)), state)
+    }
+
+    // Unique to the test process; nothing is created there.
+    pub fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("suppaftp-client-{}-{}", name, std::process::id()))
+    }
+}
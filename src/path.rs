@@ -0,0 +1,66 @@
+////////////////////////////////////////////////////////////////////////////////
+
+pub(crate) fn join_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else if dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+pub(crate) fn parent_path(path: &str) -> &str {
+    let trimmed = path.trim_end_matches('/');
+    match trimmed.rfind('/') {
+        Some(0) => "/",
+        Some(pos) => &trimmed[..pos],
+        None => "",
+    }
+}
+
+// Collapses repeated slashes and `.` segments and drops a trailing slash, so
+// `dir/` and `dir` name the same entry. `..` is kept as is.
+pub(crate) fn normalize_path(path: &str) -> String {
+    let segments: Vec<&str> = path.split('/')
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+        .collect();
+
+    if path.starts_with('/') {
+        format!("/{}", segments.join("/"))
+    } else if segments.is_empty() && !path.is_empty() {
+        ".".to_string()
+    } else {
+        segments.join("/")
+    }
+}
+
+// `..` resolved lexically; `path` must be absolute. Going above the root stays at the root.
+pub(crate) fn resolve_dot_segments(path: &str) -> String {
+    let mut segments = vec![];
+    for segment in path.split('/').filter(|segment| !segment.is_empty() && *segment != ".") {
+        if segment == ".." {
+            segments.pop();
+        } else {
+            segments.push(segment);
+        }
+    }
+
+    format!("/{}", segments.join("/"))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("/a//b/./c/"), "/a/b/c");
+        assert_eq!(normalize_path("a/../b"), "a/../b");
+        assert_eq!(normalize_path("./"), ".");
+        assert_eq!(normalize_path("//"), "/");
+        assert_eq!(normalize_path(""), "");
+        assert_eq!(resolve_dot_segments("/a/b/../../../c"), "/c");
+    }
+}
@@ -0,0 +1,52 @@
+////////////////////////////////////////////////////////////////////////////////
+
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let mut p = 0;
+    let mut n = 0;
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    while p < pattern.len() && pattern[p] == '*' {
+        p += 1;
+    }
+
+    p == pattern.len()
+}
+
+pub fn wildcard_match_any<S: AsRef<str>>(patterns: &[S], name: &str) -> bool {
+    patterns.iter().any(|pattern| wildcard_match(pattern.as_ref(), name))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("*.csv", "export.csv"));
+        assert!(!wildcard_match("*.csv", "export.csv.gz"));
+        assert!(wildcard_match("exp?rt*", "export.csv"));
+        assert!(wildcard_match("a*b*c", "aXXbYYc"));
+        assert!(!wildcard_match("a*b*c", "aXXbYY"));
+    }
+}
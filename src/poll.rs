@@ -0,0 +1,193 @@
+use std::collections::VecDeque;
+use std::io::Read;
+use std::thread;
+use std::time::Duration;
+use suppaftp::types::{FileType, FtpError, FtpResult};
+use crate::{
+    client::{FtpClient, FtpRetry},
+    types::{FtpItem, FtpList},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum FtpDirEvent {
+    Added(FtpItem),
+    Removed(FtpItem),
+    Changed {
+        old: FtpItem,
+        new: FtpItem,
+    },
+    // only detected when the server reports `unique` facts
+    Renamed {
+        old: FtpItem,
+        new: FtpItem,
+    },
+}
+
+// Endless iterator of changes in one remote directory, computed by diffing
+// listings taken `interval` apart. A failed listing is yielded as an error and the
+// next poll compares against the last successful one.
+#[derive(Debug)]
+pub struct FtpDirWatch<'a> {
+    client: &'a mut FtpClient,
+    path: String,
+    interval: Duration,
+    last: FtpList,
+    events: VecDeque<FtpDirEvent>,
+}
+
+impl FtpDirWatch<'_> {
+    fn poll(&mut self) -> FtpResult<()> {
+        self.client.invalidate_cache(&self.path)?;
+        let list = self.client.list_path(&self.path)?;
+
+        let diff = self.last.diff(&list);
+        self.events.extend(diff.added.into_iter().map(FtpDirEvent::Added));
+        self.events.extend(diff.removed.into_iter().map(FtpDirEvent::Removed));
+        self.events.extend(diff.changed.into_iter().map(|(old, new)| FtpDirEvent::Changed { old, new }));
+        self.events.extend(diff.renamed.into_iter().map(|(old, new)| FtpDirEvent::Renamed { old, new }));
+
+        self.last = list;
+        Ok(())
+    }
+}
+
+impl Iterator for FtpDirWatch<'_> {
+    type Item = FtpResult<FtpDirEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(Ok(event));
+            }
+
+            thread::sleep(self.interval);
+            if let Err(e) = self.poll() {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl FtpClient {
+    // The current contents of `path` form the baseline; only later changes are reported.
+    pub fn watch_dir(&mut self, path: &str, interval: Duration) -> FtpResult<FtpDirWatch<'_>> {
+        self.invalidate_cache(path)?;
+        let last = self.list_path(path)?;
+
+        Ok(FtpDirWatch {
+            client: self,
+            path: path.to_string(),
+            interval,
+            last,
+            events: VecDeque::new(),
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Endless iterator of the bytes appended to a remote file, like `tail -f`. The
+// size is polled every `interval` and only the new range is fetched with REST.
+// A file that shrinks is assumed to have been rotated and is read from the start.
+#[derive(Debug)]
+pub struct FtpTail<'a> {
+    client: &'a mut FtpClient,
+    path: String,
+    interval: Duration,
+    offset: u64,
+}
+
+impl FtpTail<'_> {
+    // Bytes of the file consumed so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn poll(&mut self) -> FtpResult<Option<Vec<u8>>> {
+        let size = self.client.size(&self.path)?.ok_or(FtpError::BadResponse)?;
+        if size < self.offset {
+            self.offset = 0;
+        }
+        if size == self.offset {
+            return Ok(None);
+        }
+
+        let chunk = self.client.retr_from(&self.path, self.offset, FtpRetry::Reconnect, &mut |reader| {
+            let mut chunk = vec![];
+            reader.read_to_end(&mut chunk).map_err(FtpError::ConnectionError)?;
+            Ok(chunk)
+        })?;
+
+        self.offset += chunk.len() as u64;
+        Ok(Some(chunk).filter(|chunk| !chunk.is_empty()))
+    }
+}
+
+impl Iterator for FtpTail<'_> {
+    type Item = FtpResult<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.poll() {
+                Ok(Some(chunk)) => return Some(Ok(chunk)),
+                Ok(None) => thread::sleep(self.interval),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl FtpClient {
+    // Follows `path` from its current end.
+    pub fn tail(&mut self, path: &str, interval: Duration) -> FtpResult<FtpTail<'_>> {
+        self.transfer_type(FileType::Binary)?;
+        let offset = self.size(path)?.ok_or(FtpError::BadResponse)?;
+
+        Ok(FtpTail {
+            client: self,
+            path: path.to_string(),
+            interval,
+            offset,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::client::FtpClientListMode;
+    use crate::mock::support::*;
+
+    #[test]
+    fn test_tail() {
+        let server = server();
+        server.add_file("/log", "first\n");
+        let mut client = FtpClient::new(settings(&server));
+
+        let mut tail = client.tail("/log", Duration::from_millis(10)).unwrap();
+        server.add_file("/log", "first\nsecond\n");
+        assert_eq!(tail.next().unwrap().unwrap(), b"second\n");
+        assert_eq!(tail.offset(), 13);
+
+        // rotated
+        server.add_file("/log", "new\n");
+        assert_eq!(tail.next().unwrap().unwrap(), b"new\n");
+    }
+
+    #[test]
+    fn test_fake_watch_dir() {
+        let (mut client, state) = fake_client(Some(FtpClientListMode::Mlsd));
+        state.lock().unwrap().mlsd = vec!["type=file;size=1; a.txt".to_string()];
+
+        let mut watch = client.watch_dir("/", Duration::from_millis(0)).unwrap();
+        state.lock().unwrap().mlsd.push("type=file;size=2; b.txt".to_string());
+
+        match watch.next() {
+            Some(Ok(FtpDirEvent::Added(item))) => assert_eq!(item.path.as_deref(), Some("/b.txt")),
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+}
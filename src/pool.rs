@@ -0,0 +1,309 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use suppaftp::types::{FtpError, FtpResult};
+use crate::{
+    audit::FtpAuditSink,
+    client::{FtpClient, FtpClientSettings, FtpRetryStats},
+    rate::{FtpRateLimiter, FtpRateSchedule, RateLimit},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FtpPoolOptions {
+    // connections older than this are closed instead of being reused
+    pub max_lifetime:   Option<Duration>,
+    // connections checked out this many times are closed on release
+    pub max_uses:       Option<usize>,
+    // NOOP an idle connection before handing it out; a dead one is reopened on first use
+    pub validate_on_checkout: bool,
+}
+
+impl Default for FtpPoolOptions {
+    fn default() -> Self {
+        Self {
+            max_lifetime: None,
+            max_uses: None,
+            validate_on_checkout: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FtpPoolEntry {
+    client: FtpClient,
+    created: Instant,
+    uses: usize,
+}
+
+impl FtpPoolEntry {
+    fn is_expired(&self, options: &FtpPoolOptions) -> bool {
+        options.max_lifetime.is_some_and(|max_lifetime| self.created.elapsed() >= max_lifetime)
+            || options.max_uses.is_some_and(|max_uses| self.uses >= max_uses)
+    }
+}
+
+struct FtpPoolState {
+    idle: Vec<FtpPoolEntry>,
+    size: usize,
+}
+
+pub struct FtpPool {
+    settings: Box<dyn Fn() -> Box<dyn FtpClientSettings> + Send + Sync>,
+    max_size: usize,
+    options: FtpPoolOptions,
+    limiter: Option<FtpRateLimiter>,
+    audit_sink: Option<Arc<dyn FtpAuditSink>>,
+    state: Mutex<FtpPoolState>,
+    released: Condvar,
+    retry_stats: Mutex<FtpRetryStats>,
+}
+
+impl std::fmt::Debug for FtpPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FtpPool")
+            .field("max_size", &self.max_size)
+            .field("options", &self.options)
+            .finish()
+    }
+}
+
+impl FtpPool {
+    pub fn new<F>(max_size: usize, settings: F) -> Self 
+    where
+        F: Fn() -> Box<dyn FtpClientSettings> + Send + Sync + 'static
+    {
+        Self {
+            settings: Box::new(settings),
+            max_size: max_size.max(1),
+            options: FtpPoolOptions::default(),
+            limiter: None,
+            audit_sink: None,
+            state: Mutex::new(FtpPoolState {
+                idle: vec![],
+                size: 0,
+            }),
+            released: Condvar::new(),
+            retry_stats: Mutex::new(FtpRetryStats::default()),
+        }
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    pub fn set_options(&mut self, options: FtpPoolOptions) {
+        self.options = options;
+    }
+
+    // Caps the combined rate of all transfers made through the pool's clients.
+    pub fn set_total_rate_limit(&mut self, limit: RateLimit) {
+        self.limiter = FtpRateLimiter::new(limit);
+    }
+
+    pub fn set_audit_sink(&mut self, sink: Option<Arc<dyn FtpAuditSink>>) {
+        self.audit_sink = sink;
+    }
+
+    pub(crate) fn rate_limiter(&self) -> Option<FtpRateLimiter> {
+        self.limiter.clone()
+    }
+
+    // The retry stats of all the pool's clients, added up as each is handed back;
+    // a client that is checked out counts once it is released.
+    pub fn retry_stats(&self) -> FtpRetryStats {
+        lock(&self.retry_stats).clone()
+    }
+
+    pub fn reset_retry_stats(&self) {
+        *lock(&self.retry_stats) = FtpRetryStats::default();
+    }
+
+    // Like `set_total_rate_limit`, with the limit depending on the time of day.
+    pub fn set_rate_schedule(&mut self, schedule: FtpRateSchedule) {
+        self.limiter = Some(FtpRateLimiter::with_schedule(schedule));
+    }
+
+    pub fn get(&self) -> FtpPooledClient<'_> {
+        let mut state = lock(&self.state);
+
+        loop {
+            if let Some(mut entry) = state.idle.pop() {
+                if entry.is_expired(&self.options) {
+                    state.size -= 1;
+                    drop(state);
+                    let _ = entry.client.quit();
+                    state = lock(&self.state);
+                    continue;
+                }
+
+                drop(state);
+                // a dead idle connection is dropped by ping and reopened on first use
+                if self.options.validate_on_checkout && entry.client.is_connected() {
+                    entry.client.is_alive();
+                }
+                return FtpPooledClient::new(self, entry);
+            }
+
+            if state.size < self.max_size {
+                state.size += 1;
+                drop(state);
+                let entry = FtpPoolEntry {
+                    client: FtpClient::new((self.settings)()),
+                    created: Instant::now(),
+                    uses: 0,
+                };
+                return FtpPooledClient::new(self, entry);
+            }
+
+            state = self.released.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    fn release(&self, mut entry: FtpPoolEntry) {
+        // so that every client is counted from zero on its next checkout
+        lock(&self.retry_stats).add(entry.client.retry_stats());
+        entry.client.reset_retry_stats();
+
+        if entry.is_expired(&self.options) {
+            let _ = entry.client.quit();
+            lock(&self.state).size -= 1;
+        } else {
+            lock(&self.state).idle.push(entry);
+        }
+        self.released.notify_one();
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub struct FtpPooledClient<'a> {
+    pool: &'a FtpPool,
+    entry: Option<FtpPoolEntry>,
+}
+
+impl<'a> FtpPooledClient<'a> {
+    fn new(pool: &'a FtpPool, mut entry: FtpPoolEntry) -> Self {
+        entry.uses += 1;
+        entry.client.set_shared_rate_limiter(pool.limiter.clone());
+        entry.client.set_audit_sink(pool.audit_sink.clone());
+        Self {
+            pool,
+            entry: Some(entry),
+        }
+    }
+}
+
+impl Deref for FtpPooledClient<'_> {
+    type Target = FtpClient;
+
+    fn deref(&self) -> &FtpClient {
+        &self.entry.as_ref().unwrap().client
+    }
+}
+
+impl DerefMut for FtpPooledClient<'_> {
+    fn deref_mut(&mut self) -> &mut FtpClient {
+        &mut self.entry.as_mut().unwrap().client
+    }
+}
+
+impl Drop for FtpPooledClient<'_> {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            self.pool.release(entry);
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Returns the retry stats of the run, which the pool counts as well.
+pub(crate) fn run_parallel<J, F>(pool: &FtpPool, jobs: Vec<J>, concurrency: usize, f: F) -> FtpResult<FtpRetryStats>
+where
+    J: Send,
+    F: Fn(&mut FtpClient, &J) -> FtpResult<()> + Sync
+{
+    let workers = concurrency.max(1).min(pool.max_size()).min(jobs.len());
+    let jobs = Mutex::new(jobs.into_iter());
+    let error: Mutex<Option<FtpError>> = Mutex::new(None);
+    let retry_stats = Mutex::new(FtpRetryStats::default());
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                let mut client = pool.get();
+                loop {
+                    if lock(&error).is_some() {
+                        break;
+                    }
+
+                    let job = match lock(&jobs).next() {
+                        Some(job) => job,
+                        None => break,
+                    };
+
+                    if let Err(e) = f(&mut client, &job) {
+                        lock(&error).get_or_insert(e);
+                        break;
+                    }
+                }
+                // a checked out client starts from zero, see `FtpPool::release`
+                lock(&retry_stats).add(client.retry_stats());
+            });
+        }
+    });
+
+    match error.into_inner().unwrap_or_else(PoisonError::into_inner) {
+        Some(e) => Err(e),
+        None => Ok(retry_stats.into_inner().unwrap_or_else(PoisonError::into_inner)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::mock::support::*;
+
+    #[test]
+    fn test_pool_recycling() {
+        let server = server();
+        let mut pool = pool(&server, 1);
+        pool.set_options(FtpPoolOptions { max_uses: Some(2), ..FtpPoolOptions::default() });
+
+        for _ in 0..3 {
+            pool.get().pwd().unwrap();
+        }
+        assert_eq!(server.connections(), 2);
+        assert!(server.commands().iter().any(|command| command == "QUIT"));
+        assert_eq!(pool.retry_stats().connections, 2);
+    }
+
+    #[test]
+    fn test_pool_retry_stats() {
+        let server = server();
+        let pool = pool(&server, 2);
+
+        let (mut a, mut b) = (pool.get(), pool.get());
+        a.pwd().unwrap();
+        b.pwd().unwrap();
+        assert_eq!(pool.retry_stats().connections, 0);
+        drop((a, b));
+        assert_eq!(pool.retry_stats().connections, 2);
+
+        // the idle clients were reset, so nothing is counted twice
+        pool.get().pwd().unwrap();
+        assert_eq!(pool.retry_stats().connections, 2);
+
+        pool.reset_retry_stats();
+        assert_eq!(pool.retry_stats(), FtpRetryStats::default());
+    }
+}
@@ -0,0 +1,171 @@
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use suppaftp::types::{FtpError, FtpResult};
+use crate::{
+    client::{FtpClient, OverwritePolicy},
+    path::join_path,
+    transfer::FtpTransferOptions,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone)]
+pub struct FtpPublishOptions {
+    // write `<name>.sha256` next to the file
+    pub sha256:     bool,
+    // write `<name>.md5` next to the file
+    pub md5:        bool,
+    // name of a link in the same directory repointed at the file with SITE SYMLINK;
+    // publishing fails if a file or directory already has that name
+    pub latest:     Option<String>,
+    // used for the file itself; its overwrite policy is ignored
+    pub transfer:   FtpTransferOptions,
+}
+
+impl Default for FtpPublishOptions {
+    fn default() -> Self {
+        Self {
+            sha256: true,
+            md5: true,
+            latest: None,
+            transfer: FtpTransferOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FtpPublishReport {
+    pub path:       String,
+    pub size:       u64,
+    // lowercase hex, for the requested sidecars
+    pub sha256:     Option<String>,
+    pub md5:        Option<String>,
+}
+
+const PUBLISH_TEMP_SUFFIX: &str = ".publishing";
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Both digests in one pass over the file.
+fn local_digests(path: &Path, options: &FtpPublishOptions) -> io::Result<(Option<String>, Option<String>)> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut sha256 = Some(Sha256::new()).filter(|_| options.sha256);
+    let mut md5 = Some(Md5::new()).filter(|_| options.md5);
+
+    loop {
+        let read = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(sha256) = sha256.as_mut() {
+            sha256.update(&buffer[..read]);
+        }
+        if let Some(md5) = md5.as_mut() {
+            md5.update(&buffer[..read]);
+        }
+    }
+
+    Ok((sha256.map(|sha256| to_hex(&sha256.finalize())), md5.map(|md5| to_hex(&md5.finalize()))))
+}
+
+impl FtpClient {
+    // Uploads under a temporary name and renames it into place, so downloaders
+    // never see a partial file.
+    fn replace_with(&mut self, remote: &str, upload: &mut dyn FnMut(&mut Self, &str) -> FtpResult<u64>) -> FtpResult<u64> {
+        let temp = format!("{}{}", remote, PUBLISH_TEMP_SUFFIX);
+        let size = upload(self, &temp)?;
+
+        // RNTO onto an existing file fails on many servers
+        if self.lookup(remote)?.is_some() {
+            self.rm(remote)?;
+        }
+        self.rename(&temp, remote)?;
+        Ok(size)
+    }
+
+    fn upload_sidecar(&mut self, remote: &str, content: String) -> FtpResult<()> {
+        self.replace_with(remote, &mut |client, temp| {
+            let mut writer = client.open_write(temp)?;
+            writer.write_all(content.as_bytes()).map_err(FtpError::ConnectionError)?;
+            writer.finish()?;
+            Ok(content.len() as u64)
+        })?;
+        Ok(())
+    }
+
+    pub fn publish<P: AsRef<Path>>(&mut self, local_path: P, remote_dir: &str) -> FtpResult<FtpPublishReport> {
+        self.publish_with(local_path, remote_dir, &FtpPublishOptions::default())
+    }
+
+    // Drops a build artifact into `remote_dir`: the file, then its checksum
+    // sidecars in `sha256sum`/`md5sum` format, and finally the `latest` link. A
+    // sidecar therefore never describes a file that isn't complete yet.
+    pub fn publish_with<P: AsRef<Path>>(&mut self, local_path: P, remote_dir: &str, options: &FtpPublishOptions) -> FtpResult<FtpPublishReport> {
+        let local_path = local_path.as_ref();
+        let name = local_path.file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| FtpError::ConnectionError(io::Error::new(io::ErrorKind::InvalidInput, format!("no file name in {}", local_path.display()))))?
+            .to_string();
+        let remote = join_path(remote_dir, &name);
+
+        let (sha256, md5) = local_digests(local_path, options).map_err(FtpError::ConnectionError)?;
+
+        let transfer = FtpTransferOptions { overwrite: Some(OverwritePolicy::Overwrite), ..options.transfer.clone() };
+        let size = self.replace_with(&remote, &mut |client, temp| client.upload_file_with(local_path, temp, &transfer).map(Option::unwrap_or_default))?;
+
+        if let Some(sha256) = sha256.as_ref() {
+            self.upload_sidecar(&format!("{}.sha256", remote), format!("{}  {}\n", sha256, name))?;
+        }
+        if let Some(md5) = md5.as_ref() {
+            self.upload_sidecar(&format!("{}.md5", remote), format!("{}  {}\n", md5, name))?;
+        }
+
+        // relative, so the link survives the directory being moved or mirrored
+        if let Some(latest) = options.latest.as_ref() {
+            self.symlink(&name, &join_path(remote_dir, latest))?;
+        }
+
+        Ok(FtpPublishReport { path: remote, size, sha256, md5 })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::mock::support::*;
+
+    #[test]
+    fn test_publish() {
+        let server = server();
+        let mut client = FtpClient::new(settings(&server));
+
+        let local = temp_path("publish").with_extension("txt");
+        let name = local.file_name().unwrap().to_str().unwrap().to_string();
+        std::fs::write(&local, "hello").unwrap();
+        server.add_file(&format!("/data/{}", name), "old");
+
+        let report = client.publish(&local, "/data").unwrap();
+        assert_eq!(report.size, 5);
+        assert_eq!(server.file(&report.path), Some(b"hello".to_vec()));
+
+        let sha256 = format!("2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824  {}\n", name);
+        assert_eq!(server.file(&format!("/data/{}.sha256", name)), Some(sha256.into_bytes()));
+        let md5 = format!("5d41402abc4b2a76b9719d911017c592  {}\n", name);
+        assert_eq!(server.file(&format!("/data/{}.md5", name)), Some(md5.into_bytes()));
+
+        // the mock server has no SITE commands
+        let options = FtpPublishOptions { latest: Some("latest".to_string()), ..Default::default() };
+        assert!(client.publish_with(&local, "/data", &options).is_err());
+
+        std::fs::remove_file(&local).unwrap();
+    }
+}
@@ -0,0 +1,523 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use suppaftp::types::FtpResult;
+use crate::{
+    client::FtpRetryStats,
+    pool::{FtpPool, lock},
+    rate::{FtpRateLimiter, FtpRateSample},
+    transfer::{FtpTransferObserver, FtpTransferOptions},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum FtpTransferJob {
+    Upload {
+        local:  PathBuf,
+        remote: String,
+    },
+    Download {
+        remote: String,
+        local:  PathBuf,
+    },
+}
+
+pub type FtpJobId = u64;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Default)]
+pub enum FtpJobPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FtpQueueEvent {
+    Queued {
+        id:     FtpJobId,
+        job:    FtpTransferJob,
+    },
+    Started {
+        id:         FtpJobId,
+        attempt:    usize,
+    },
+    // once per sample interval of the transfer
+    Progress {
+        id:     FtpJobId,
+        sample: FtpRateSample,
+    },
+    Completed {
+        id:     FtpJobId,
+        bytes:  u64,
+    },
+    // the job goes back to the queue for another attempt
+    Retried {
+        id:     FtpJobId,
+        error:  String,
+    },
+    Failed {
+        id:     FtpJobId,
+        error:  String,
+    },
+}
+
+// A job that failed on every attempt, with the error of the last one.
+#[derive(Debug, Clone)]
+pub struct FtpFailedJob {
+    pub id:         FtpJobId,
+    pub job:        FtpTransferJob,
+    pub priority:   FtpJobPriority,
+    pub attempts:   usize,
+    pub error:      String,
+}
+
+#[derive(Debug)]
+struct FtpQueuedJob {
+    id:         FtpJobId,
+    job:        FtpTransferJob,
+    priority:   FtpJobPriority,
+    attempts:   usize,
+}
+
+#[derive(Debug, Default)]
+struct FtpQueueState {
+    next_id: FtpJobId,
+    pending: VecDeque<FtpQueuedJob>,
+    active: usize,
+    completed: usize,
+    failed: Vec<FtpFailedJob>,
+    max_attempts: usize,
+    paused: bool,
+    paused_jobs: HashSet<FtpJobId>,
+    // weights per priority, empty when the jobs just share the pool's limit
+    shares: BTreeMap<FtpJobPriority, u32>,
+    running: HashMap<FtpJobId, FtpJobPriority>,
+    subscribers: Vec<mpsc::Sender<FtpQueueEvent>>,
+    closed: bool,
+    retry_stats: FtpRetryStats,
+}
+
+impl FtpQueueState {
+    fn emit(&mut self, event: FtpQueueEvent) {
+        // subscribers whose receiver is gone are dropped
+        self.subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    // behind the jobs of the same or a higher priority
+    fn enqueue(&mut self, queued: FtpQueuedJob) {
+        let position = self.pending.iter()
+            .position(|other| other.priority < queued.priority)
+            .unwrap_or(self.pending.len());
+        self.pending.insert(position, queued);
+    }
+
+    // a closing queue ignores pauses so that it can drain
+    fn is_paused(&self, id: FtpJobId) -> bool {
+        !self.closed && (self.paused || self.paused_jobs.contains(&id))
+    }
+
+    // whether any job, waiting or running, can still make progress
+    fn has_runnable(&self) -> bool {
+        self.pending.iter().any(|queued| !self.is_paused(queued.id))
+            || self.running.keys().any(|id| !self.is_paused(*id))
+    }
+
+    // The part of `total` due to a running job of `priority`, by its weight among
+    // the running jobs; a job running alone gets all of it.
+    fn share_of(&self, total: u64, priority: FtpJobPriority) -> Option<f64> {
+        if self.shares.is_empty() {
+            return None;
+        }
+        let weight = |priority: &FtpJobPriority| self.shares.get(priority).copied().unwrap_or(1) as f64;
+        let own = weight(&priority);
+        let sum: f64 = self.running.values().map(weight).sum();
+        Some(total as f64 * own / sum.max(own))
+    }
+}
+
+#[derive(Debug, Default)]
+struct FtpQueueShared {
+    state: Mutex<FtpQueueState>,
+    changed: Condvar,
+}
+
+// Background transfer queue: jobs are executed by worker threads, each holding a
+// client checked out of the pool. A job that keeps failing is set aside in the
+// failed list and the others go on.
+#[derive(Debug)]
+pub struct FtpTransferQueue {
+    shared: Arc<FtpQueueShared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+// Holds a running transfer in its progress callback while it is paused, which
+// keeps the connection open for as long as the server tolerates the stall. With
+// bandwidth shares it also keeps the transfer to its part of the pool's limit.
+struct FtpQueueGate {
+    shared: Arc<FtpQueueShared>,
+    id: FtpJobId,
+    priority: FtpJobPriority,
+    limiter: Option<FtpRateLimiter>,
+    // bytes seen so far and when they will have been sent at the job's share
+    schedule: Mutex<(u64, Instant)>,
+}
+
+impl FtpTransferObserver for FtpQueueGate {
+    fn on_progress(&self, transferred: u64) {
+        let rate = {
+            let mut state = lock(&self.shared.state);
+            while state.is_paused(self.id) {
+                state = self.shared.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+            self.limiter.as_ref()
+                .and_then(FtpRateLimiter::current_rate)
+                .and_then(|total| state.share_of(total, self.priority))
+        };
+
+        let wait = {
+            let mut schedule = lock(&self.schedule);
+            let bytes = transferred.saturating_sub(schedule.0);
+            schedule.0 = transferred;
+
+            let now = Instant::now();
+            match rate {
+                Some(rate) if rate > 0.0 => {
+                    schedule.1 = schedule.1.max(now) + Duration::from_secs_f64(bytes as f64 / rate);
+                    schedule.1 - now
+                },
+                _ => Duration::ZERO,
+            }
+        };
+
+        // on top of the pool's limiter, which still paces the chunks themselves
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+
+    fn on_sample(&self, sample: &FtpRateSample) {
+        lock(&self.shared.state).emit(FtpQueueEvent::Progress { id: self.id, sample: *sample });
+    }
+}
+
+fn run_job(pool: &FtpPool, shared: &Arc<FtpQueueShared>, queued: &FtpQueuedJob) -> FtpResult<u64> {
+    let gate = FtpQueueGate {
+        shared: shared.clone(),
+        id: queued.id,
+        priority: queued.priority,
+        limiter: pool.rate_limiter(),
+        schedule: Mutex::new((0, Instant::now())),
+    };
+    let options = FtpTransferOptions {
+        observer: Some(Arc::new(gate)),
+        ..FtpTransferOptions::default()
+    };
+
+    let mut client = pool.get();
+    client.wait_while_paused();
+    let result = match &queued.job {
+        FtpTransferJob::Upload { local, remote } => client.upload_file_with(local, remote, &options),
+        FtpTransferJob::Download { remote, local } => client.download_file_with(remote, local, &options),
+    };
+    // a checked out client starts from zero, see `FtpPool::release`
+    lock(&shared.state).retry_stats.add(client.retry_stats());
+
+    // a file the overwrite policy skipped moved no bytes
+    result.map(Option::unwrap_or_default)
+}
+
+impl FtpTransferQueue {
+    pub fn new(pool: Arc<FtpPool>, workers: usize) -> Self {
+        let shared = Arc::new(FtpQueueShared::default());
+        lock(&shared.state).max_attempts = 1;
+
+        let workers = (0..workers.max(1).min(pool.max_size()))
+            .map(|_| {
+                let shared = shared.clone();
+                let pool = pool.clone();
+                thread::spawn(move || Self::worker(&shared, &pool))
+            })
+            .collect();
+
+        Self {
+            shared,
+            workers,
+        }
+    }
+
+    fn worker(shared: &Arc<FtpQueueShared>, pool: &FtpPool) {
+        loop {
+            let mut queued = {
+                let mut state = lock(&shared.state);
+                loop {
+                    let next = state.pending.iter().position(|queued| !state.is_paused(queued.id));
+                    if let Some(queued) = next.and_then(|position| state.pending.remove(position)) {
+                        state.active += 1;
+                        state.running.insert(queued.id, queued.priority);
+                        state.emit(FtpQueueEvent::Started { id: queued.id, attempt: queued.attempts + 1 });
+                        break queued;
+                    }
+                    if state.closed {
+                        return;
+                    }
+                    state = shared.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+                }
+            };
+
+            let result = run_job(pool, shared, &queued);
+            queued.attempts += 1;
+
+            let mut state = lock(&shared.state);
+            state.active -= 1;
+            state.running.remove(&queued.id);
+            match result {
+                Ok(bytes) => {
+                    state.completed += 1;
+                    state.emit(FtpQueueEvent::Completed { id: queued.id, bytes });
+                },
+                // retried after the jobs of its priority already waiting
+                Err(error) if queued.attempts < state.max_attempts => {
+                    state.emit(FtpQueueEvent::Retried { id: queued.id, error: error.to_string() });
+                    state.enqueue(queued);
+                },
+                Err(error) => {
+                    let error = error.to_string();
+                    state.emit(FtpQueueEvent::Failed { id: queued.id, error: error.clone() });
+                    state.failed.push(FtpFailedJob {
+                        id: queued.id,
+                        job: queued.job,
+                        priority: queued.priority,
+                        attempts: queued.attempts,
+                        error,
+                    });
+                },
+            }
+            shared.changed.notify_all();
+        }
+    }
+
+    pub fn push(&self, job: FtpTransferJob) -> FtpJobId {
+        self.push_with_priority(job, FtpJobPriority::Normal)
+    }
+
+    // Runs before every waiting job of a lower priority; jobs already running
+    // aren't interrupted.
+    pub fn push_with_priority(&self, job: FtpTransferJob, priority: FtpJobPriority) -> FtpJobId {
+        let mut state = lock(&self.shared.state);
+        let id = state.next_id;
+        state.next_id += 1;
+        state.emit(FtpQueueEvent::Queued { id, job: job.clone() });
+        state.enqueue(FtpQueuedJob { id, job, priority, attempts: 0 });
+        self.shared.changed.notify_all();
+        id
+    }
+
+    // Events of everything that happens from now on; the receiver may be dropped
+    // at any time.
+    pub fn subscribe(&self) -> mpsc::Receiver<FtpQueueEvent> {
+        let (sender, receiver) = mpsc::channel();
+        lock(&self.shared.state).subscribers.push(sender);
+        receiver
+    }
+
+    // No job is started and running transfers stop reading or writing until
+    // `resume`.
+    pub fn pause(&self) {
+        lock(&self.shared.state).paused = true;
+        self.shared.changed.notify_all();
+    }
+
+    pub fn resume(&self) {
+        lock(&self.shared.state).paused = false;
+        self.shared.changed.notify_all();
+    }
+
+    // Like `pause` for a single job, whether it is running or still waiting.
+    pub fn pause_job(&self, id: FtpJobId) {
+        lock(&self.shared.state).paused_jobs.insert(id);
+        self.shared.changed.notify_all();
+    }
+
+    pub fn resume_job(&self, id: FtpJobId) {
+        lock(&self.shared.state).paused_jobs.remove(&id);
+        self.shared.changed.notify_all();
+    }
+
+    // Splits the pool's total rate limit between running jobs by weight of their
+    // priority, e.g. 7 for `High` and 3 for `Low` keeps interactive transfers at
+    // 70% while background ones run. Unset priorities weigh 1; without any share
+    // or a limit on the pool the jobs simply compete for the bandwidth.
+    pub fn set_bandwidth_share(&self, priority: FtpJobPriority, weight: u32) {
+        lock(&self.shared.state).shares.insert(priority, weight.max(1));
+    }
+
+    // Attempts per job before it is moved to the failed list, 1 by default.
+    pub fn set_max_attempts(&self, attempts: usize) {
+        lock(&self.shared.state).max_attempts = attempts.max(1);
+    }
+
+    pub fn pending(&self) -> usize {
+        lock(&self.shared.state).pending.len()
+    }
+
+    pub fn completed(&self) -> usize {
+        lock(&self.shared.state).completed
+    }
+
+    // Reconnects and retries of the jobs run so far, failed attempts included.
+    pub fn retry_stats(&self) -> FtpRetryStats {
+        lock(&self.shared.state).retry_stats.clone()
+    }
+
+    // The failed jobs, which stay in the queue.
+    pub fn failed(&self) -> Vec<FtpFailedJob> {
+        lock(&self.shared.state).failed.clone()
+    }
+
+    pub fn take_failed(&self) -> Vec<FtpFailedJob> {
+        std::mem::take(&mut lock(&self.shared.state).failed)
+    }
+
+    // Queues the failed jobs among `ids` again with a fresh set of attempts and
+    // returns how many there were.
+    pub fn retry_failed(&self, ids: &[FtpJobId]) -> usize {
+        let mut state = lock(&self.shared.state);
+        let (retried, failed): (Vec<_>, Vec<_>) = std::mem::take(&mut state.failed)
+            .into_iter()
+            .partition(|failed| ids.contains(&failed.id));
+
+        state.failed = failed;
+        let count = retried.len();
+        for failed in retried {
+            state.emit(FtpQueueEvent::Queued { id: failed.id, job: failed.job.clone() });
+            state.enqueue(FtpQueuedJob { id: failed.id, job: failed.job, priority: failed.priority, attempts: 0 });
+        }
+        self.shared.changed.notify_all();
+        count
+    }
+
+    // Blocks until every queued job has run or is paused; the paused ones are
+    // still in the queue (see `pending`) when it returns.
+    pub fn wait_idle(&self) {
+        let mut state = lock(&self.shared.state);
+        while state.has_runnable() {
+            state = self.shared.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    // Lets the workers drain the remaining jobs, then joins them.
+    pub fn shutdown(mut self) {
+        self.shutdown_impl();
+    }
+
+    fn shutdown_impl(&mut self) {
+        lock(&self.shared.state).closed = true;
+        self.shared.changed.notify_all();
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for FtpTransferQueue {
+    fn drop(&mut self) {
+        self.shutdown_impl();
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::mock::support::*;
+
+    #[test]
+    fn test_bandwidth_shares() {
+        let mut state = FtpQueueState::default();
+        state.running.insert(0, FtpJobPriority::High);
+        assert_eq!(state.share_of(1000, FtpJobPriority::High), None);
+
+        state.shares.insert(FtpJobPriority::High, 7);
+        state.shares.insert(FtpJobPriority::Low, 3);
+        assert_eq!(state.share_of(1000, FtpJobPriority::High), Some(1000.0));
+
+        state.running.insert(1, FtpJobPriority::Low);
+        assert_eq!(state.share_of(1000, FtpJobPriority::High), Some(700.0));
+        assert_eq!(state.share_of(1000, FtpJobPriority::Low), Some(300.0));
+    }
+
+    #[test]
+    fn test_queue_failed() {
+        let server = server();
+        let pool = Arc::new(pool(&server, 2));
+        let queue = FtpTransferQueue::new(pool, 2);
+        queue.set_max_attempts(2);
+        let events = queue.subscribe();
+
+        let local = temp_path("queue");
+        let missing = queue.push(FtpTransferJob::Download { remote: "/missing".to_string(), local: local.join("missing") });
+        queue.push(FtpTransferJob::Download { remote: "/readme".to_string(), local: local.join("readme") });
+        queue.wait_idle();
+
+        assert_eq!(queue.completed(), 1);
+        let failed = queue.failed();
+        assert_eq!(failed.len(), 1);
+        assert_eq!((failed[0].id, failed[0].attempts), (missing, 2));
+
+        let events: Vec<_> = events.try_iter().filter(|event| match event {
+            FtpQueueEvent::Queued { id, .. } | FtpQueueEvent::Started { id, .. } | FtpQueueEvent::Retried { id, .. } | FtpQueueEvent::Failed { id, .. } => *id == missing,
+            _ => false,
+        }).collect();
+        assert!(matches!(events.as_slice(), [
+            FtpQueueEvent::Queued { .. },
+            FtpQueueEvent::Started { attempt: 1, .. },
+            FtpQueueEvent::Retried { .. },
+            FtpQueueEvent::Started { attempt: 2, .. },
+            FtpQueueEvent::Failed { .. },
+        ]));
+
+        server.add_file("/missing", "found");
+        assert_eq!(queue.retry_failed(&[missing]), 1);
+        queue.wait_idle();
+        assert_eq!(queue.completed(), 2);
+        assert!(queue.failed().is_empty());
+
+        queue.shutdown();
+        std::fs::remove_dir_all(&local).unwrap();
+    }
+
+    #[test]
+    fn test_queue_priority() {
+        let server = server();
+        server.add_file("/urgent", "urgent");
+        let pool = Arc::new(pool(&server, 1));
+        let queue = FtpTransferQueue::new(pool, 1);
+
+        let local = temp_path("priority");
+        let job = |remote: &str| FtpTransferJob::Download { remote: remote.to_string(), local: local.join(&remote[1..]) };
+
+        queue.pause();
+        queue.push_with_priority(job("/readme"), FtpJobPriority::Low);
+        queue.push(job("/data/a.txt"));
+        queue.push_with_priority(job("/urgent"), FtpJobPriority::High);
+        assert_eq!(queue.pending(), 3);
+        // nothing can run while the queue is paused
+        queue.wait_idle();
+        assert_eq!(queue.pending(), 3);
+
+        queue.resume();
+        queue.wait_idle();
+        let retrieved: Vec<_> = server.commands().into_iter().filter(|command| command.starts_with("RETR")).collect();
+        assert_eq!(retrieved, vec!["RETR /urgent", "RETR /data/a.txt", "RETR /readme"]);
+        // one client did all three, on the connection it opened for the first
+        assert_eq!(queue.retry_stats(), FtpRetryStats { connections: 1, ..FtpRetryStats::default() });
+
+        queue.shutdown();
+        std::fs::remove_dir_all(&local).unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+use chrono::{Local, NaiveTime};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum RateLimit {
+    #[default]
+    Unlimited,
+    BytesPerSecond(u64),
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Keeps a single transfer at or below its rate by sleeping whenever it gets ahead
+// of schedule.
+#[derive(Debug)]
+pub(crate) struct FtpThrottle {
+    rate: u64,
+    started: Instant,
+    transferred: u64,
+}
+
+impl FtpThrottle {
+    pub fn new(limit: RateLimit) -> Option<Self> {
+        match limit {
+            RateLimit::BytesPerSecond(rate) if rate > 0 => Some(Self {
+                rate,
+                started: Instant::now(),
+                transferred: 0,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn consume(&mut self, bytes: u64) {
+        self.transferred += bytes;
+
+        let expected = Duration::from_secs_f64(self.transferred as f64 / self.rate as f64);
+        let elapsed = self.started.elapsed();
+        if expected > elapsed {
+            thread::sleep(expected - elapsed);
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// A daily time window, in local time; a window whose end is before its start
+// runs over midnight.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FtpRateWindow {
+    pub start:  NaiveTime,
+    pub end:    NaiveTime,
+    // `None` pauses transfers during the window
+    pub limit:  Option<RateLimit>,
+}
+
+impl FtpRateWindow {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+// The first window containing the current time decides the rate, `default`
+// applies outside all of them.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct FtpRateSchedule {
+    pub windows:    Vec<FtpRateWindow>,
+    pub default:    RateLimit,
+}
+
+impl FtpRateSchedule {
+    // `None` when transfers are paused.
+    pub fn limit_at(&self, time: NaiveTime) -> Option<RateLimit> {
+        match self.windows.iter().find(|window| window.contains(time)) {
+            Some(window) => window.limit,
+            None => Some(self.default),
+        }
+    }
+}
+
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Caps the combined rate of any number of transfers, e.g. all connections of a
+// pool. Each chunk is scheduled after the ones before it, so the transfers share
+// the bandwidth in the order they ask for it.
+#[derive(Debug, Clone)]
+pub struct FtpRateLimiter {
+    schedule: Arc<FtpRateSchedule>,
+    // when the bytes handed out so far will have been sent
+    next: Arc<Mutex<Instant>>,
+}
+
+impl FtpRateLimiter {
+    pub fn new(limit: RateLimit) -> Option<Self> {
+        match limit {
+            RateLimit::BytesPerSecond(rate) if rate > 0 => Some(Self::with_schedule(FtpRateSchedule {
+                windows: vec![],
+                default: limit,
+            })),
+            _ => None,
+        }
+    }
+
+    pub fn with_schedule(schedule: FtpRateSchedule) -> Self {
+        Self {
+            schedule: Arc::new(schedule),
+            next: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    fn current_limit(&self) -> Option<RateLimit> {
+        self.schedule.limit_at(Local::now().time())
+    }
+
+    // Bytes per second allowed right now; `None` when unlimited or paused.
+    pub(crate) fn current_rate(&self) -> Option<u64> {
+        match self.current_limit() {
+            Some(RateLimit::BytesPerSecond(rate)) if rate > 0 => Some(rate),
+            _ => None,
+        }
+    }
+
+    // Lets callers hold back a transfer instead of starting it only to stall.
+    pub fn wait_while_paused(&self) {
+        while self.current_limit().is_none() {
+            thread::sleep(PAUSE_POLL_INTERVAL);
+        }
+    }
+
+    pub fn consume(&self, bytes: u64) {
+        self.wait_while_paused();
+        let rate = match self.current_limit() {
+            Some(RateLimit::BytesPerSecond(rate)) if rate > 0 => rate,
+            _ => return,
+        };
+
+        let wait = {
+            let mut next = self.next.lock().unwrap_or_else(PoisonError::into_inner);
+            let now = Instant::now();
+            // time left unused while idle isn't saved up for a burst later
+            let start = (*next).max(now);
+            *next = start + Duration::from_secs_f64(bytes as f64 / rate as f64);
+            *next - now
+        };
+
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FtpRateSample {
+    pub transferred:        u64,
+    pub total:              Option<u64>,
+    pub elapsed:            Duration,
+    pub bytes_per_second:   f64,
+    pub eta:                Option<Duration>,
+}
+
+// Exponential moving average of the transfer rate, sampled once per `interval`.
+#[derive(Debug)]
+pub(crate) struct FtpRateMeter {
+    interval: Duration,
+    total: Option<u64>,
+    started: Instant,
+    last_sample: Instant,
+    last_transferred: u64,
+    transferred: u64,
+    average: Option<f64>,
+}
+
+const RATE_SMOOTHING: f64 = 0.3;
+
+impl FtpRateMeter {
+    pub fn new(interval: Duration, total: Option<u64>) -> Self {
+        let now = Instant::now();
+        Self {
+            interval,
+            total,
+            started: now,
+            last_sample: now,
+            last_transferred: 0,
+            transferred: 0,
+            average: None,
+        }
+    }
+
+    pub fn transferred(&self) -> u64 {
+        self.transferred
+    }
+
+    pub fn update(&mut self, bytes: u64) -> Option<FtpRateSample> {
+        self.transferred += bytes;
+
+        let now = Instant::now();
+        let since_last = now.duration_since(self.last_sample);
+        if since_last < self.interval {
+            return None;
+        }
+
+        let current = (self.transferred - self.last_transferred) as f64 / since_last.as_secs_f64().max(f64::EPSILON);
+        let average = match self.average {
+            Some(average) => average + RATE_SMOOTHING * (current - average),
+            None => current,
+        };
+
+        self.average = Some(average);
+        self.last_sample = now;
+        self.last_transferred = self.transferred;
+
+        let eta = match self.total {
+            Some(total) if average > 0.0 => Some(Duration::from_secs_f64(total.saturating_sub(self.transferred) as f64 / average)),
+            _ => None,
+        };
+
+        Some(FtpRateSample {
+            transferred: self.transferred,
+            total: self.total,
+            elapsed: now.duration_since(self.started),
+            bytes_per_second: average,
+            eta,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_rate_meter() {
+        let mut meter = FtpRateMeter::new(Duration::from_secs(0), Some(1000));
+        thread::sleep(Duration::from_millis(10));
+
+        let sample = meter.update(500).unwrap();
+        assert_eq!(sample.transferred, 500);
+        assert!(sample.bytes_per_second > 0.0);
+        assert!(sample.eta.is_some());
+
+        assert!(FtpThrottle::new(RateLimit::Unlimited).is_none());
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = FtpRateLimiter::new(RateLimit::BytesPerSecond(1000)).unwrap();
+        let started = Instant::now();
+
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let limiter = limiter.clone();
+                thread::spawn(move || limiter.consume(50))
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // 100 bytes at 1000 B/s in total, not per thread
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_rate_schedule() {
+        let night = FtpRateWindow {
+            start: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+            limit: Some(RateLimit::Unlimited),
+        };
+        let backup = FtpRateWindow {
+            start: NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(13, 0, 0).unwrap(),
+            limit: None,
+        };
+        let schedule = FtpRateSchedule {
+            windows: vec![night, backup],
+            default: RateLimit::BytesPerSecond(1000),
+        };
+
+        assert_eq!(schedule.limit_at(NaiveTime::from_hms_opt(23, 30, 0).unwrap()), Some(RateLimit::Unlimited));
+        assert_eq!(schedule.limit_at(NaiveTime::from_hms_opt(5, 59, 0).unwrap()), Some(RateLimit::Unlimited));
+        assert_eq!(schedule.limit_at(NaiveTime::from_hms_opt(12, 30, 0).unwrap()), None);
+        assert_eq!(schedule.limit_at(NaiveTime::from_hms_opt(13, 0, 0).unwrap()), Some(RateLimit::BytesPerSecond(1000)));
+    }
+}
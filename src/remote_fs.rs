@@ -0,0 +1,152 @@
+use ::remotefs::fs::{File, FileType, Metadata, ReadStream, RemoteFs, UnixPex, Welcome, WriteStream};
+use ::remotefs::{RemoteError, RemoteErrorType, RemoteResult};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use suppaftp::types::{FileType as FtpFileType, FtpError};
+use crate::{
+    client::{FtpClient, FtpRetry},
+    mlst::naivedatetime_to_systemtime,
+    types::{FtpItem, FtpItemType},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn remote_error(e: FtpError) -> RemoteError {
+    let kind = match &e {
+        FtpError::ConnectionError(e) if e.kind() == io::ErrorKind::Unsupported => RemoteErrorType::UnsupportedFeature,
+        FtpError::ConnectionError(_) => RemoteErrorType::ConnectionError,
+        FtpError::InvalidAddress(_) => RemoteErrorType::BadAddress,
+        _ => RemoteErrorType::ProtocolError,
+    };
+    RemoteError::new_ex(kind, e)
+}
+
+fn path_str(path: &Path) -> RemoteResult<&str> {
+    path.to_str().ok_or_else(|| RemoteError::new_ex(RemoteErrorType::BadFile, "path is not valid UTF-8"))
+}
+
+fn item_to_file(dir: &Path, item: FtpItem) -> File {
+    let file_type = match item.ty {
+        FtpItemType::File => FileType::File,
+        FtpItemType::Symlink => FileType::Symlink,
+        _ => FileType::Directory,
+    };
+
+    let metadata = Metadata {
+        accessed: None,
+        created: item.created.and_then(naivedatetime_to_systemtime),
+        gid: item.unix_group,
+        mode: item.unix_mode.map(|mode| UnixPex::from(mode as u32)),
+        modified: item.modified.and_then(naivedatetime_to_systemtime),
+        size: item.size.unwrap_or(0),
+        symlink: item.link_target.as_ref().map(PathBuf::from),
+        file_type,
+        uid: item.unix_owner,
+    };
+
+    File {
+        path: dir.join(&item.name),
+        metadata,
+    }
+}
+
+impl RemoteFs for FtpClient {
+    fn connect(&mut self) -> RemoteResult<Welcome> {
+        FtpClient::connect(self).map_err(remote_error)?;
+        Ok(Welcome::default())
+    }
+
+    fn disconnect(&mut self) -> RemoteResult<()> {
+        FtpClient::disconnect(self);
+        Ok(())
+    }
+
+    fn is_connected(&mut self) -> bool {
+        FtpClient::is_connected(self)
+    }
+
+    fn pwd(&mut self) -> RemoteResult<PathBuf> {
+        FtpClient::pwd(self).map(PathBuf::from).map_err(remote_error)
+    }
+
+    fn change_dir(&mut self, dir: &Path) -> RemoteResult<PathBuf> {
+        self.chdir(path_str(dir)?).map_err(remote_error)?;
+        RemoteFs::pwd(self)
+    }
+
+    fn list_dir(&mut self, path: &Path) -> RemoteResult<Vec<File>> {
+        let list = self.list_path(path_str(path)?).map_err(remote_error)?;
+        Ok(list.items.into_iter().map(|item| item_to_file(path, item)).collect())
+    }
+
+    fn stat(&mut self, path: &Path) -> RemoteResult<File> {
+        let item = self.lookup(path_str(path)?)
+            .map_err(remote_error)?
+            .ok_or_else(|| RemoteError::new(RemoteErrorType::NoSuchFileOrDirectory))?;
+        Ok(item_to_file(path.parent().unwrap_or_else(|| Path::new("")), item))
+    }
+
+    fn setstat(&mut self, _path: &Path, _metadata: Metadata) -> RemoteResult<()> {
+        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+    }
+
+    fn exists(&mut self, path: &Path) -> RemoteResult<bool> {
+        self.lookup(path_str(path)?).map(|item| item.is_some()).map_err(remote_error)
+    }
+
+    fn remove_file(&mut self, path: &Path) -> RemoteResult<()> {
+        self.rm(path_str(path)?).map_err(remote_error)
+    }
+
+    fn remove_dir(&mut self, path: &Path) -> RemoteResult<()> {
+        self.rmdir(path_str(path)?).map_err(remote_error)
+    }
+
+    fn create_dir(&mut self, path: &Path, _mode: UnixPex) -> RemoteResult<()> {
+        self.mkdir(path_str(path)?).map_err(remote_error)
+    }
+
+    fn symlink(&mut self, path: &Path, target: &Path) -> RemoteResult<()> {
+        FtpClient::symlink(self, path_str(target)?, path_str(path)?).map_err(remote_error)
+    }
+
+    fn copy(&mut self, _src: &Path, _dest: &Path) -> RemoteResult<()> {
+        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+    }
+
+    fn mov(&mut self, src: &Path, dest: &Path) -> RemoteResult<()> {
+        self.rename(path_str(src)?, path_str(dest)?).map_err(remote_error)
+    }
+
+    fn exec(&mut self, _cmd: &str) -> RemoteResult<(u32, String)> {
+        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+    }
+
+    fn append(&mut self, _path: &Path, _metadata: &Metadata) -> RemoteResult<WriteStream> {
+        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+    }
+
+    fn create(&mut self, _path: &Path, _metadata: &Metadata) -> RemoteResult<WriteStream> {
+        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+    }
+
+    fn open(&mut self, _path: &Path) -> RemoteResult<ReadStream> {
+        Err(RemoteError::new(RemoteErrorType::UnsupportedFeature))
+    }
+
+    fn create_file(&mut self, path: &Path, _metadata: &Metadata, reader: Box<dyn Read + Send>) -> RemoteResult<u64> {
+        let path = path_str(path)?;
+        let mut reader = Some(reader);
+
+        self.transfer_type(FtpFileType::Binary).map_err(remote_error)?;
+        self.put_file(path, &mut || reader.take().ok_or(FtpError::BadResponse)).map_err(remote_error)
+    }
+
+    fn open_file(&mut self, src: &Path, mut dest: Box<dyn Write + Send>) -> RemoteResult<u64> {
+        let src = path_str(src)?;
+
+        self.transfer_type(FtpFileType::Binary).map_err(remote_error)?;
+        self.retr(src, FtpRetry::Never, &mut |reader| std::io::copy(reader, &mut dest).map_err(FtpError::ConnectionError))
+            .map_err(remote_error)
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use crate::{
+    client::{FtpClient, FtpClientSettings},
+    pool::{FtpPool, FtpPoolOptions, lock},
+};
+#[cfg(feature = "serde")]
+use crate::config::FtpConnectionConfig;
+
+////////////////////////////////////////////////////////////////////////////////
+
+type FtpSettingsFactory = Arc<dyn Fn() -> Box<dyn FtpClientSettings> + Send + Sync>;
+
+// Named connection profiles for applications that talk to many servers. Clients
+// are created fresh on every call; each profile gets one pool, created on first
+// use and shared afterwards.
+pub struct FtpSessionManager {
+    profiles: BTreeMap<String, FtpSettingsFactory>,
+    pools: Mutex<HashMap<String, Arc<FtpPool>>>,
+    pool_size: usize,
+    pool_options: FtpPoolOptions,
+}
+
+impl std::fmt::Debug for FtpSessionManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FtpSessionManager")
+            .field("profiles", &self.profiles.keys().collect::<Vec<_>>())
+            .field("pool_size", &self.pool_size)
+            .field("pool_options", &self.pool_options)
+            .finish()
+    }
+}
+
+impl FtpSessionManager {
+    pub fn new(pool_size: usize) -> Self {
+        Self {
+            profiles: BTreeMap::new(),
+            pools: Mutex::new(HashMap::new()),
+            pool_size,
+            pool_options: FtpPoolOptions::default(),
+        }
+    }
+
+    // applies to pools created from now on
+    pub fn set_pool_options(&mut self, options: FtpPoolOptions) {
+        self.pool_options = options;
+    }
+
+    // Replaces a profile of the same name, together with its pool.
+    pub fn add_profile<F>(&mut self, name: &str, settings: F)
+    where
+        F: Fn() -> Box<dyn FtpClientSettings> + Send + Sync + 'static
+    {
+        self.profiles.insert(name.to_string(), Arc::new(settings));
+        lock(&self.pools).remove(name);
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn add_config(&mut self, name: &str, config: FtpConnectionConfig) {
+        self.add_profile(name, move || Box::new(config.clone()));
+    }
+
+    // Clients and pools already handed out keep working.
+    pub fn remove_profile(&mut self, name: &str) -> bool {
+        lock(&self.pools).remove(name);
+        self.profiles.remove(name).is_some()
+    }
+
+    pub fn profiles(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+
+    pub fn client(&self, name: &str) -> Option<FtpClient> {
+        self.profiles.get(name).map(|settings| FtpClient::new(settings()))
+    }
+
+    pub fn pool(&self, name: &str) -> Option<Arc<FtpPool>> {
+        let settings = self.profiles.get(name)?.clone();
+        let mut pools = lock(&self.pools);
+
+        let pool = pools.entry(name.to_string()).or_insert_with(|| {
+            let mut pool = FtpPool::new(self.pool_size, move || settings());
+            pool.set_options(self.pool_options);
+            Arc::new(pool)
+        });
+        Some(pool.clone())
+    }
+
+    // The profile among `names` with the lowest median NOOP round trip; profiles
+    // that are unknown or fail to answer are left out.
+    pub fn fastest(&self, names: &[&str], samples: usize) -> Option<String> {
+        names.iter()
+            .filter_map(|name| {
+                let pool = self.pool(name)?;
+                let latency = pool.get().latency(samples).ok()?;
+                Some((latency.median, name.to_string()))
+            })
+            .min()
+            .map(|(_, name)| name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::client::FtpClientListMode;
+    use crate::mock::support::*;
+
+    #[test]
+    fn test_session_manager() {
+        let server = server();
+        let addr = server.addr();
+        let mut manager = FtpSessionManager::new(2);
+        manager.add_profile("partner", move || Box::new(TestSettings { addr: addr.clone(), list_mode: Some(FtpClientListMode::Mlsd) }));
+
+        assert_eq!(manager.profiles().collect::<Vec<_>>(), vec!["partner"]);
+        assert!(manager.client("unknown").is_none());
+        assert_eq!(manager.client("partner").unwrap().names("/data").unwrap(), vec!["a.txt"]);
+
+        let pool = manager.pool("partner").unwrap();
+        assert!(Arc::ptr_eq(&pool, &manager.pool("partner").unwrap()));
+        assert!(manager.remove_profile("partner"));
+        assert!(manager.pool("partner").is_none());
+    }
+}
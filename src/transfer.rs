@@ -0,0 +1,1202 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use filetime::FileTime;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use suppaftp::types::{FileType, FtpError, FtpResult};
+use crate::{
+    audit::FtpAuditOperation,
+    checksum::head_checksum,
+    client::{FtpClient, FtpRetry, OverwritePolicy},
+    path::parent_path,
+    pool::{FtpPool, lock, run_parallel},
+    rate::{FtpRateLimiter, FtpRateMeter, FtpRateSample, FtpThrottle, RateLimit},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct FtpReader<'a> {
+    client: &'a mut FtpClient,
+    stream: Option<Box<dyn Read + Send>>,
+}
+
+impl FtpReader<'_> {
+    pub fn finish(mut self) -> FtpResult<()> {
+        self.finish_impl()
+    }
+
+    fn finish_impl(&mut self) -> FtpResult<()> {
+        match self.stream.take() {
+            Some(stream) => self.client.finalize_retr_stream(stream),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Read for FtpReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.stream.as_mut() {
+            Some(stream) => stream.read(buf),
+            None => Ok(0),
+        }
+    }
+}
+
+impl Drop for FtpReader<'_> {
+    fn drop(&mut self) {
+        let _ = self.finish_impl();
+    }
+}
+
+pub struct FtpWriter<'a> {
+    client: &'a mut FtpClient,
+    path: String,
+    stream: Option<Box<dyn Write + Send>>,
+    written: u64,
+}
+
+impl FtpWriter<'_> {
+    pub fn finish(mut self) -> FtpResult<()> {
+        self.finish_impl()
+    }
+
+    fn finish_impl(&mut self) -> FtpResult<()> {
+        match self.stream.take() {
+            Some(mut stream) => {
+                let result = stream.flush()
+                    .map_err(FtpError::ConnectionError)
+                    .and_then(|_| self.client.finalize_put_stream(stream));
+                self.client.audit(FtpAuditOperation::Upload, &self.path, None, Some(self.written), result.as_ref().map(|_| ()));
+                result?;
+                self.client.invalidate_cache(parent_path(&self.path))
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for FtpWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.stream.as_mut() {
+            Some(stream) => {
+                let written = stream.write(buf)?;
+                self.written += written as u64;
+                Ok(written)
+            },
+            None => Err(io::Error::from(io::ErrorKind::NotConnected)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stream.as_mut() {
+            Some(stream) => stream.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for FtpWriter<'_> {
+    fn drop(&mut self) {
+        let _ = self.finish_impl();
+    }
+}
+
+impl FtpClient {
+    pub fn open_read(&mut self, remote: &str) -> FtpResult<FtpReader<'_>> {
+        self.transfer_type(FileType::Binary)?;
+        let stream = self.retr_stream(remote)?;
+
+        Ok(FtpReader {
+            client: self,
+            stream: Some(stream),
+        })
+    }
+
+    pub fn open_write(&mut self, remote: &str) -> FtpResult<FtpWriter<'_>> {
+        self.transfer_type(FileType::Binary)?;
+        let stream = self.put_stream(remote)?;
+
+        Ok(FtpWriter {
+            client: self,
+            path: remote.to_string(),
+            stream: Some(stream),
+            written: 0,
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Observer methods take `&self` so one observer can be shared between transfers
+// running on several pooled clients.
+pub trait FtpTransferObserver: Send + Sync {
+    fn on_progress(&self, _transferred: u64) {}
+
+    fn on_sample(&self, _sample: &FtpRateSample) {}
+}
+
+// Writer returned by an adapter; `finish` writes out whatever it still buffers,
+// e.g. a compression trailer.
+pub trait FtpAdapterWrite: Write {
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+// Rewrites file content on the fly, such as compressing or encrypting it. An
+// upload writes through `encoder` wrapped around the data connection, a download
+// through `decoder` wrapped around the local file.
+pub trait FtpTransferAdapter: Send + Sync {
+    fn encoder<'a>(&self, inner: &'a mut dyn Write) -> io::Result<Box<dyn FtpAdapterWrite + 'a>>;
+    fn decoder<'a>(&self, inner: &'a mut dyn Write) -> io::Result<Box<dyn FtpAdapterWrite + 'a>>;
+}
+
+#[cfg(feature = "gzip")]
+impl<W: Write> FtpAdapterWrite for flate2::write::GzEncoder<W> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        flate2::write::GzEncoder::finish(*self).map(|_| ())
+    }
+}
+
+#[cfg(feature = "gzip")]
+impl<W: Write> FtpAdapterWrite for flate2::write::GzDecoder<W> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        flate2::write::GzDecoder::finish(*self).map(|_| ())
+    }
+}
+
+// Stores files gzip-compressed on the server.
+#[cfg(feature = "gzip")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FtpGzipAdapter {
+    pub level: flate2::Compression,
+}
+
+#[cfg(feature = "gzip")]
+impl FtpTransferAdapter for FtpGzipAdapter {
+    fn encoder<'a>(&self, inner: &'a mut dyn Write) -> io::Result<Box<dyn FtpAdapterWrite + 'a>> {
+        Ok(Box::new(flate2::write::GzEncoder::new(inner, self.level)))
+    }
+
+    fn decoder<'a>(&self, inner: &'a mut dyn Write) -> io::Result<Box<dyn FtpAdapterWrite + 'a>> {
+        Ok(Box::new(flate2::write::GzDecoder::new(inner)))
+    }
+}
+
+#[derive(Clone)]
+pub struct FtpTransferOptions {
+    // `None` uses the client's default rate limit
+    pub rate_limit: Option<RateLimit>,
+    pub observer: Option<Arc<dyn FtpTransferObserver>>,
+    pub sample_interval: Duration,
+    // used for the ETA of downloads; uploads use the local file size
+    pub expected_size: Option<u64>,
+    // `None` uses the client's default policy
+    pub overwrite: Option<OverwritePolicy>,
+    // with an adapter, progress and the returned size count the bytes read: from
+    // the local file on upload, from the server on download
+    pub adapter: Option<Arc<dyn FtpTransferAdapter>>,
+    // keep the `.part` file of a failed download and continue it with REST next
+    // time; not done with an adapter
+    pub resume: bool,
+    // bytes before the resume point that are fetched again and compared with the
+    // partial file, which is discarded if they differ; 0 trusts it as it is
+    pub resume_overlap: u64,
+    // appended to the local name while a download is in progress
+    pub part_suffix: String,
+    // compare the downloaded size with SIZE before the file is moved into place
+    pub verify_size: bool,
+    // give the downloaded file the remote modification time, shifted by the
+    // client's clock offset
+    pub preserve_mtime: bool,
+    // upload straight from a memory map of the local file, which must not change
+    // while it is sent; not retried after a reconnect and ignored with an adapter
+    #[cfg(feature = "mmap")]
+    pub memory_map: bool,
+}
+
+pub const DEFAULT_RESUME_OVERLAP: u64 = 64 * 1024;
+pub const DEFAULT_PART_SUFFIX: &str = ".part";
+
+#[cfg(feature = "mmap")]
+const MMAP_SLICE_SIZE: usize = 4 * 1024 * 1024;
+
+impl Default for FtpTransferOptions {
+    fn default() -> Self {
+        Self {
+            rate_limit: None,
+            observer: None,
+            sample_interval: Duration::from_secs(1),
+            expected_size: None,
+            overwrite: None,
+            adapter: None,
+            resume: false,
+            resume_overlap: DEFAULT_RESUME_OVERLAP,
+            part_suffix: DEFAULT_PART_SUFFIX.to_string(),
+            verify_size: false,
+            preserve_mtime: false,
+            #[cfg(feature = "mmap")]
+            memory_map: false,
+        }
+    }
+}
+
+impl std::fmt::Debug for FtpTransferOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("FtpTransferOptions");
+        debug
+            .field("rate_limit", &self.rate_limit)
+            .field("observer", &self.observer.is_some())
+            .field("sample_interval", &self.sample_interval)
+            .field("expected_size", &self.expected_size)
+            .field("overwrite", &self.overwrite)
+            .field("adapter", &self.adapter.is_some())
+            .field("resume", &self.resume)
+            .field("resume_overlap", &self.resume_overlap)
+            .field("part_suffix", &self.part_suffix)
+            .field("verify_size", &self.verify_size)
+            .field("preserve_mtime", &self.preserve_mtime);
+        #[cfg(feature = "mmap")]
+        debug.field("memory_map", &self.memory_map);
+        debug.finish()
+    }
+}
+
+struct FtpTransferProgress {
+    throttle: Option<FtpThrottle>,
+    shared: Option<FtpRateLimiter>,
+    meter: FtpRateMeter,
+    observer: Option<Arc<dyn FtpTransferObserver>>,
+}
+
+impl FtpTransferProgress {
+    fn new(limit: RateLimit, shared: Option<FtpRateLimiter>, options: &FtpTransferOptions, total: Option<u64>) -> Self {
+        Self {
+            throttle: FtpThrottle::new(limit),
+            shared,
+            meter: FtpRateMeter::new(options.sample_interval, total),
+            observer: options.observer.clone(),
+        }
+    }
+
+    fn advance(&mut self, bytes: u64) {
+        if let Some(throttle) = self.throttle.as_mut() {
+            throttle.consume(bytes);
+        }
+        if let Some(shared) = self.shared.as_ref() {
+            shared.consume(bytes);
+        }
+
+        let sample = self.meter.update(bytes);
+        if let Some(observer) = self.observer.as_ref() {
+            observer.on_progress(self.meter.transferred());
+            if let Some(sample) = sample {
+                observer.on_sample(&sample);
+            }
+        }
+    }
+}
+
+struct FtpCallbackReader<R, F> {
+    inner: R,
+    callback: F,
+}
+
+impl<R: Read, F: FnMut(u64)> Read for FtpCallbackReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        (self.callback)(read as u64);
+        Ok(read)
+    }
+}
+
+fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+// Identity of the remote file a partial download was started from, kept next to
+// it in `<part>.resume` as `size<TAB>mtime<TAB>head<TAB>remote` with `-` for what
+// is unknown. `head` is the checksum of the start of the partial file, recorded
+// when the download stops, so a partial file changed since isn't continued.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct FtpResumeInfo {
+    pub remote:     String,
+    pub size:       Option<u64>,
+    // seconds since the Unix epoch, as the server reports them
+    pub modified:   Option<i64>,
+    pub head:       Option<u64>,
+}
+
+const RESUME_SUFFIX: &str = ".resume";
+const RESUME_HEAD_SIZE: u64 = 64 * 1024;
+
+fn optional_field(field: &str) -> Option<&str> {
+    Some(field).filter(|field| *field != "-")
+}
+
+impl FtpResumeInfo {
+    fn path(part_path: &Path) -> PathBuf {
+        suffixed_path(part_path, RESUME_SUFFIX)
+    }
+
+    // `None` when missing or unreadable, either of which rules out resuming.
+    pub fn load(part_path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(Self::path(part_path)).ok()?;
+        let mut fields = content.trim_end_matches('\n').splitn(4, '\t');
+        let size = optional_field(fields.next()?).map(str::parse).transpose().ok()?;
+        let modified = optional_field(fields.next()?).map(str::parse).transpose().ok()?;
+        let head = optional_field(fields.next()?).map(|head| u64::from_str_radix(head, 16)).transpose().ok()?;
+        let remote = fields.next()?.to_string();
+        Some(Self { remote, size, modified, head })
+    }
+
+    pub fn save(&self, part_path: &Path) -> io::Result<()> {
+        let field = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        let line = format!(
+            "{}\t{}\t{}\t{}\n",
+            field(self.size.map(|size| size.to_string())),
+            field(self.modified.map(|modified| modified.to_string())),
+            field(self.head.map(|head| format!("{:016x}", head))),
+            self.remote,
+        );
+        fs::write(Self::path(part_path), line)
+    }
+
+    fn remove(part_path: &Path) {
+        let _ = fs::remove_file(Self::path(part_path));
+    }
+
+    // Whether `part_path` may be continued as a download of the file `self` describes.
+    pub fn allows(&self, saved: &Self, part_path: &Path) -> bool {
+        let same_file = saved.remote == self.remote && saved.size == self.size && saved.modified == self.modified;
+        same_file && saved.head.is_none_or(|head| head_checksum(part_path, RESUME_HEAD_SIZE).ok() == Some(head))
+    }
+}
+
+fn already_exists_error(path: &str) -> FtpError {
+    FtpError::ConnectionError(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", path)))
+}
+
+// A data connection hands out a few KiB per read, so reads are gathered until the
+// buffer is full and written in one go. Progress still advances per read, which
+// keeps throttling smooth.
+fn copy_with_buffer(reader: &mut dyn Read, writer: &mut dyn Write, buffer: &mut [u8], progress: &mut dyn FnMut(u64)) -> io::Result<u64> {
+    let mut total = 0;
+    let mut filled = 0;
+    loop {
+        let read = match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        filled += read;
+        total += read as u64;
+        progress(read as u64);
+
+        if filled == buffer.len() {
+            writer.write_all(buffer)?;
+            filled = 0;
+        }
+    }
+
+    writer.write_all(&buffer[..filled])?;
+    Ok(total)
+}
+
+fn download_to(reader: &mut dyn io::Read, path: &Path, buffer: &mut [u8], adapter: Option<&dyn FtpTransferAdapter>, mut progress: FtpTransferProgress) -> FtpResult<u64> {
+    let mut file = File::create(path).map_err(FtpError::ConnectionError)?;
+
+    let size = match adapter {
+        Some(adapter) => adapter.decoder(&mut file).and_then(|mut writer| {
+            let size = copy_with_buffer(reader, &mut *writer, buffer, &mut |bytes| progress.advance(bytes))?;
+            writer.finish()?;
+            Ok(size)
+        }),
+        None => copy_with_buffer(reader, &mut file, buffer, &mut |bytes| progress.advance(bytes)),
+    }.map_err(FtpError::ConnectionError)?;
+    file.sync_all().map_err(FtpError::ConnectionError)?;
+
+    Ok(size)
+}
+
+// Continues the partial file at `path`, `offset` bytes long when the download
+// was resumed, from a stream that starts `overlap` bytes before that. Anything a
+// failed attempt appended is cut off first. Returns `None`, leaving the file
+// alone, when the overlapping bytes differ.
+fn resume_to(reader: &mut dyn Read, path: &Path, offset: u64, overlap: u64, buffer: &mut [u8], mut progress: FtpTransferProgress) -> io::Result<Option<u64>> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    file.set_len(offset)?;
+    let start = offset - overlap;
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut expected = vec![0; overlap as usize];
+    file.read_exact(&mut expected)?;
+    let mut received = vec![0; overlap as usize];
+    match reader.read_exact(&mut received) {
+        Ok(()) if received == expected => progress.advance(overlap),
+        Ok(()) => return Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let appended = copy_with_buffer(reader, &mut file, buffer, &mut |bytes| progress.advance(bytes))?;
+    file.sync_all()?;
+    Ok(Some(start + overlap + appended))
+}
+
+fn read_limited(reader: &mut dyn Read, limit: Option<u64>) -> FtpResult<Vec<u8>> {
+    let mut buffer = vec![];
+
+    match limit {
+        Some(limit) => {
+            reader.take(limit.saturating_add(1)).read_to_end(&mut buffer).map_err(FtpError::ConnectionError)?;
+            if buffer.len() as u64 > limit {
+                return Err(FtpError::ConnectionError(io::Error::new(
+                    io::ErrorKind::InvalidData, 
+                    format!("remote file exceeds the size limit of {} bytes", limit)
+                )));
+            }
+        },
+        None => {
+            reader.read_to_end(&mut buffer).map_err(FtpError::ConnectionError)?;
+        }
+    }
+
+    Ok(buffer)
+}
+
+impl FtpClient {
+    pub fn download_bytes(&mut self, remote: &str, limit: Option<u64>) -> FtpResult<Vec<u8>> {
+        self.transfer_type(FileType::Binary)?;
+        self.retr(remote, FtpRetry::Reconnect, &mut |reader| read_limited(reader, limit))
+    }
+
+    pub fn download_string(&mut self, remote: &str, limit: Option<u64>) -> FtpResult<String> {
+        let bytes = self.download_bytes(remote, limit)?;
+        String::from_utf8(bytes)
+            .map_err(|e| FtpError::ConnectionError(io::Error::new(io::ErrorKind::InvalidData, e)))
+    }
+
+    // `None` when the overwrite policy skipped the file.
+    pub fn download_file<P: AsRef<Path>>(&mut self, remote: &str, local_path: P) -> FtpResult<Option<u64>> {
+        self.download_file_with(remote, local_path, &FtpTransferOptions::default())
+    }
+
+    pub fn download_file_with<P: AsRef<Path>>(&mut self, remote: &str, local_path: P, options: &FtpTransferOptions) -> FtpResult<Option<u64>> {
+        self.download_file_checked(remote, local_path.as_ref(), options)
+    }
+
+    pub(crate) fn download_file_checked(&mut self, remote: &str, local_path: &Path, options: &FtpTransferOptions) -> FtpResult<Option<u64>> {
+        let limit = options.rate_limit.unwrap_or_else(|| self.rate_limit());
+        let policy = options.overwrite.clone().unwrap_or_else(|| self.overwrite_policy());
+
+        let exists = fs::symlink_metadata(local_path).is_ok();
+        match policy {
+            OverwritePolicy::Skip if exists => return Ok(None),
+            OverwritePolicy::Error if exists => return Err(already_exists_error(&local_path.display().to_string())),
+            _ => {},
+        }
+
+        if let Some(parent) = local_path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent).map_err(FtpError::ConnectionError)?;
+            }
+        }
+
+        let temp_path = suffixed_path(local_path, &options.part_suffix);
+        let resume = if options.resume && options.adapter.is_none() {
+            Some(self.resume_info(remote)?)
+        } else {
+            None
+        };
+        let mut buffer = self.take_transfer_buffer();
+
+        let result = self.transfer_type(FileType::Binary)
+            .and_then(|_| self.download_part(remote, &temp_path, &mut buffer, limit, resume.as_ref(), options))
+            .and_then(|size| {
+                if options.verify_size && options.adapter.is_none() {
+                    self.verify_size(remote, &temp_path)?;
+                }
+                // set before the rename, so a failure leaves the destination untouched
+                if options.preserve_mtime {
+                    if let Some(modified) = self.modified(remote)? {
+                        self.set_local_mtime(&temp_path, modified)?;
+                    }
+                }
+                // the existing file is only moved aside once the new one is complete
+                if let Some(suffix) = policy.backup_suffix().filter(|_| exists) {
+                    fs::rename(local_path, suffixed_path(local_path, suffix)).map_err(FtpError::ConnectionError)?;
+                }
+                fs::rename(&temp_path, local_path).map_err(FtpError::ConnectionError)?;
+                Ok(Some(size))
+            });
+
+        self.restore_transfer_buffer(buffer);
+        match resume {
+            Some(info) if result.is_err() && temp_path.exists() => {
+                let head = head_checksum(&temp_path, RESUME_HEAD_SIZE).ok();
+                let _ = FtpResumeInfo { head, ..info }.save(&temp_path);
+            },
+            None if result.is_err() => {
+                let _ = fs::remove_file(&temp_path);
+            },
+            _ => FtpResumeInfo::remove(&temp_path),
+        }
+
+        result
+    }
+
+    fn resume_info(&mut self, remote: &str) -> FtpResult<FtpResumeInfo> {
+        Ok(FtpResumeInfo {
+            remote: remote.to_string(),
+            size: self.size(remote)?,
+            modified: self.modified(remote)?.map(|modified| modified.and_utc().timestamp()),
+            head: None,
+        })
+    }
+
+    // Remote times are UTC, as MDTM and the modify fact are defined to be.
+    pub(crate) fn set_local_mtime(&self, local_path: &Path, modified: NaiveDateTime) -> FtpResult<()> {
+        let mtime = FileTime::from_unix_time((modified - self.clock_offset()).and_utc().timestamp(), 0);
+        filetime::set_file_mtime(local_path, mtime).map_err(FtpError::ConnectionError)
+    }
+
+    fn verify_size(&mut self, remote: &str, local_path: &Path) -> FtpResult<()> {
+        let local_size = fs::metadata(local_path).map_err(FtpError::ConnectionError)?.len();
+        match self.size(remote)? {
+            Some(size) if size != local_size => Err(FtpError::ConnectionError(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("downloaded {} bytes of {}, which has {}", local_size, remote, size),
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    // `resume` describes the remote file when the partial one may be continued.
+    fn download_part(&mut self, remote: &str, temp_path: &Path, buffer: &mut [u8], limit: RateLimit, resume: Option<&FtpResumeInfo>, options: &FtpTransferOptions) -> FtpResult<u64> {
+        let shared = self.shared_rate_limiter();
+        let offset = match (resume, fs::metadata(temp_path)) {
+            (Some(info), Ok(metadata)) if FtpResumeInfo::load(temp_path).is_some_and(|saved| info.allows(&saved, temp_path)) => metadata.len(),
+            _ => 0,
+        };
+        // written before any data, so that a crash mid-transfer leaves it behind
+        if let Some(info) = resume {
+            info.save(temp_path).map_err(FtpError::ConnectionError)?;
+        }
+
+        // a remote file shorter than the partial one has certainly been replaced
+        if offset > 0 && resume.and_then(|info| info.size).is_none_or(|size| size >= offset) {
+            let overlap = options.resume_overlap.min(offset);
+            let mut stale = false;
+            let resumed = self.retr_from(remote, offset - overlap, FtpRetry::Reconnect, &mut |reader| {
+                let progress = FtpTransferProgress::new(limit, shared.clone(), options, options.expected_size);
+                match resume_to(reader, temp_path, offset, overlap, buffer, progress).map_err(FtpError::ConnectionError)? {
+                    Some(size) => Ok(size),
+                    None => {
+                        stale = true;
+                        Ok(0)
+                    },
+                }
+            });
+
+            if !stale {
+                return resumed;
+            }
+            // the stream was abandoned halfway, so the control connection can't be trusted
+            self.disconnect();
+        }
+
+        self.retr(remote, FtpRetry::Reconnect, &mut |reader| {
+            let progress = FtpTransferProgress::new(limit, shared.clone(), options, options.expected_size);
+            download_to(reader, temp_path, buffer, options.adapter.as_deref(), progress)
+        })
+    }
+
+    // Returns whether the file was transferred.
+    pub fn download_if_newer<P: AsRef<Path>>(&mut self, remote: &str, local_path: P) -> FtpResult<bool> {
+        let local_path = local_path.as_ref();
+
+        if let (Some(remote_modified), Ok(metadata)) = (self.modified(remote)?, fs::metadata(local_path)) {
+            let local_modified: DateTime<Utc> = metadata.modified().map_err(FtpError::ConnectionError)?.into();
+            if remote_modified - self.clock_offset() <= local_modified.naive_utc() {
+                return Ok(false);
+            }
+        }
+
+        // already decided by the times, whatever the client's overwrite policy
+        let options = FtpTransferOptions { overwrite: Some(OverwritePolicy::Overwrite), ..FtpTransferOptions::default() };
+        let size = self.download_file_checked(remote, local_path, &options)?;
+        Ok(size.is_some())
+    }
+
+    // `None` when the overwrite policy skipped the file.
+    pub fn upload_file<P: AsRef<Path>>(&mut self, local_path: P, remote: &str) -> FtpResult<Option<u64>> {
+        self.upload_file_with(local_path, remote, &FtpTransferOptions::default())
+    }
+
+    pub fn upload_file_with<P: AsRef<Path>>(&mut self, local_path: P, remote: &str, options: &FtpTransferOptions) -> FtpResult<Option<u64>> {
+        self.upload_file_checked(local_path.as_ref(), remote, options)
+    }
+
+    pub(crate) fn upload_file_checked(&mut self, local_path: &Path, remote: &str, options: &FtpTransferOptions) -> FtpResult<Option<u64>> {
+        let limit = options.rate_limit.unwrap_or_else(|| self.rate_limit());
+        let policy = options.overwrite.clone().unwrap_or_else(|| self.overwrite_policy());
+
+        if policy != OverwritePolicy::Overwrite && self.lookup(remote)?.is_some() {
+            match policy.backup_suffix() {
+                // FTP can't replace a file atomically, so the existing one is
+                // moved aside before the upload starts
+                Some(suffix) => self.rename(remote, &format!("{}{}", remote, suffix))?,
+                None if policy == OverwritePolicy::Skip => return Ok(None),
+                None => return Err(already_exists_error(remote)),
+            }
+        }
+
+        let result = self.transfer_type(FileType::Binary).and_then(|_| match options.adapter.as_deref() {
+            Some(adapter) => self.upload_adapted(local_path, remote, adapter, limit, options),
+            #[cfg(feature = "mmap")]
+            None if options.memory_map => self.upload_mapped(local_path, remote, limit, options),
+            None => self.upload_plain(local_path, remote, limit, options),
+        });
+        self.audit(FtpAuditOperation::Upload, remote, None, result.as_ref().ok().copied(), result.as_ref().map(|_| ()));
+
+        let size = result?;
+        self.invalidate_cache(parent_path(remote))?;
+        Ok(Some(size))
+    }
+
+    fn upload_plain(&mut self, local_path: &Path, remote: &str, limit: RateLimit, options: &FtpTransferOptions) -> FtpResult<u64> {
+        let buffer_size = self.transfer_buffer_size();
+        let shared = self.shared_rate_limiter();
+        self.put_file(remote, &mut || {
+            let file = File::open(local_path).map_err(FtpError::ConnectionError)?;
+            let size = file.metadata().map(|metadata| metadata.len()).ok();
+            // fresh progress per attempt, so a retried upload starts on schedule
+            let mut progress = FtpTransferProgress::new(limit, shared.clone(), options, size);
+            Ok(FtpCallbackReader {
+                inner: BufReader::with_capacity(buffer_size, file),
+                callback: move |bytes| progress.advance(bytes),
+            })
+        })
+    }
+
+    // The map is written to the data connection in large slices, skipping the copy
+    // through a read buffer.
+    #[cfg(feature = "mmap")]
+    fn upload_mapped(&mut self, local_path: &Path, remote: &str, limit: RateLimit, options: &FtpTransferOptions) -> FtpResult<u64> {
+        let file = File::open(local_path).map_err(FtpError::ConnectionError)?;
+        let size = file.metadata().map_err(FtpError::ConnectionError)?.len();
+        // zero-length maps are rejected on some platforms
+        if size == 0 {
+            return self.upload_plain(local_path, remote, limit, options);
+        }
+
+        // SAFETY: the caller opted in and promised not to modify the file meanwhile
+        let map = unsafe { memmap2::Mmap::map(&file) }.map_err(FtpError::ConnectionError)?;
+        let mut progress = FtpTransferProgress::new(limit, self.shared_rate_limiter(), options, Some(size));
+
+        let mut stream = self.put_stream(remote)?;
+        let result = map.chunks(MMAP_SLICE_SIZE).try_for_each(|slice| {
+            stream.write_all(slice)?;
+            progress.advance(slice.len() as u64);
+            Ok(())
+        });
+
+        let finalized = self.finalize_put_stream(stream);
+        result.map_err(FtpError::ConnectionError)?;
+        finalized?;
+
+        Ok(size)
+    }
+
+    // Streamed through the adapter, so unlike a plain upload it isn't retried
+    // after a reconnect.
+    fn upload_adapted(&mut self, local_path: &Path, remote: &str, adapter: &dyn FtpTransferAdapter, limit: RateLimit, options: &FtpTransferOptions) -> FtpResult<u64> {
+        let mut file = File::open(local_path).map_err(FtpError::ConnectionError)?;
+        let size = file.metadata().map(|metadata| metadata.len()).ok();
+        let mut progress = FtpTransferProgress::new(limit, self.shared_rate_limiter(), options, size);
+
+        let mut stream = self.put_stream(remote)?;
+        let mut buffer = self.take_transfer_buffer();
+
+        let result = adapter.encoder(&mut stream).and_then(|mut writer| {
+            let size = copy_with_buffer(&mut file, &mut *writer, &mut buffer, &mut |bytes| progress.advance(bytes))?;
+            writer.finish()?;
+            Ok(size)
+        });
+
+        self.restore_transfer_buffer(buffer);
+        let finalized = self.finalize_put_stream(stream);
+        let size = result.map_err(FtpError::ConnectionError)?;
+        finalized?;
+
+        Ok(size)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl FtpClient {
+    // Writes bytes `start..end` of `remote` at the same offset of `path`. A segment
+    // that stops short of the end of the file abandons the rest of the stream,
+    // after which the control connection can't be trusted, so it is dropped.
+    fn download_segment(&mut self, remote: &str, path: &Path, start: u64, end: u64, size: u64) -> FtpResult<()> {
+        let mut file = OpenOptions::new().write(true).open(path).map_err(FtpError::ConnectionError)?;
+
+        self.transfer_type(FileType::Binary)?;
+        let mut buffer = self.take_transfer_buffer();
+        let mut copied = 0;
+        // A retry starts the segment over and rewrites the same bytes, unless the
+        // failed attempt had already fetched all of it before the stream was dropped.
+        let result = self.retr_from(remote, start, FtpRetry::Reconnect, &mut |reader| {
+            if copied == end - start {
+                return Ok(());
+            }
+            copied = 0;
+            file.seek(SeekFrom::Start(start)).map_err(FtpError::ConnectionError)?;
+            copied = copy_with_buffer(&mut reader.take(end - start), &mut file, &mut buffer, &mut |_| {}).map_err(FtpError::ConnectionError)?;
+            Ok(())
+        });
+        self.restore_transfer_buffer(buffer);
+
+        let complete = copied == end - start;
+        if complete && end < size {
+            self.disconnect();
+            return Ok(());
+        }
+
+        result?;
+        if !complete {
+            return Err(FtpError::ConnectionError(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{} ended at {} bytes, before the end of its segment", remote, start + copied),
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl FtpPool {
+    // Downloads `remote` over up to `segments` connections, each fetching its own
+    // range with REST. The local file is sized up front, sparsely where the file
+    // system allows, so every segment is written in place and nothing has to be
+    // joined afterwards.
+    pub fn download_segmented<P: AsRef<Path>>(&self, remote: &str, local_path: P, segments: usize) -> FtpResult<u64> {
+        let local_path = local_path.as_ref();
+        let size = self.get().size(remote)?.ok_or(FtpError::BadResponse)?;
+
+        let segments = (segments.clamp(1, self.max_size()) as u64).min(size.max(1));
+        let segment_size = size.div_ceil(segments).max(1);
+        let ranges: Vec<(u64, u64)> = (0..segments)
+            .map(|index| (index * segment_size, ((index + 1) * segment_size).min(size)))
+            .filter(|(start, end)| start < end)
+            .collect();
+
+        if let Some(parent) = local_path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent).map_err(FtpError::ConnectionError)?;
+            }
+        }
+
+        let temp_path = suffixed_path(local_path, DEFAULT_PART_SUFFIX);
+        File::create(&temp_path).and_then(|file| file.set_len(size)).map_err(FtpError::ConnectionError)?;
+
+        let result = run_parallel(self, ranges, segments as usize, |client, &(start, end)| {
+                client.download_segment(remote, &temp_path, start, end, size)
+            })
+            .and_then(|_| File::open(&temp_path).and_then(|file| file.sync_all()).map_err(FtpError::ConnectionError))
+            .and_then(|_| fs::rename(&temp_path, local_path).map_err(FtpError::ConnectionError));
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result.map(|_| size)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn read_chunk(reader: &mut dyn Read, size: usize) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(size);
+    reader.take(size as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+fn chunk_part_path(path: &str, index: usize) -> String {
+    format!("{}.part{}", path, index)
+}
+
+#[derive(Debug, Default)]
+struct FtpChunkState {
+    next: usize,
+    done: bool,
+    bytes: u64,
+    error: Option<FtpError>,
+}
+
+impl FtpClient {
+    // The first chunk replaces the file, the rest are appended to it.
+    fn append_chunks(&mut self, path: &str, reader: &mut dyn Read, chunk_size: usize) -> FtpResult<u64> {
+        let mut total = 0;
+
+        for index in 0.. {
+            let chunk = read_chunk(reader, chunk_size).map_err(FtpError::ConnectionError)?;
+            if chunk.is_empty() && index > 0 {
+                break;
+            }
+
+            total += if index == 0 {
+                self.put_file(path, &mut || Ok(Cursor::new(chunk.as_slice())))?
+            } else {
+                self.append_file(path, &mut Cursor::new(chunk.as_slice()))?
+            };
+
+            if chunk.len() < chunk_size {
+                break;
+            }
+        }
+
+        self.invalidate_cache(parent_path(path))?;
+        Ok(total)
+    }
+}
+
+impl FtpPool {
+    // Uploads `reader` in `chunk_size` parts, as many at a time as the pool has
+    // connections, and has the server join them with COMB. Where COMB isn't
+    // supported the parts are appended one by one on a single connection.
+    pub fn upload_chunked<R: Read + Send>(&self, path: &str, reader: R, chunk_size: usize) -> FtpResult<u64> {
+        let result = self.upload_chunks(path, reader, chunk_size.max(1));
+        self.get().audit(FtpAuditOperation::Upload, path, None, result.as_ref().ok().copied(), result.as_ref().map(|_| ()));
+        result
+    }
+
+    fn upload_chunks<R: Read + Send>(&self, path: &str, mut reader: R, chunk_size: usize) -> FtpResult<u64> {
+        {
+            let mut client = self.get();
+            if !client.supports_comb()? {
+                client.transfer_type(FileType::Binary)?;
+                return client.append_chunks(path, &mut reader, chunk_size);
+            }
+        }
+
+        let reader = Mutex::new(reader);
+        let state = Mutex::new(FtpChunkState::default());
+
+        thread::scope(|scope| {
+            for _ in 0..self.max_size() {
+                scope.spawn(|| {
+                    let mut client = self.get();
+                    if let Err(e) = client.transfer_type(FileType::Binary) {
+                        lock(&state).error.get_or_insert(e);
+                        return;
+                    }
+
+                    loop {
+                        // parts are numbered in the order they are read
+                        let (index, chunk) = {
+                            let mut reader = lock(&reader);
+                            let mut state = lock(&state);
+                            if state.done || state.error.is_some() {
+                                break;
+                            }
+                            match read_chunk(&mut *reader, chunk_size) {
+                                Ok(chunk) if chunk.is_empty() && state.next > 0 => {
+                                    state.done = true;
+                                    break;
+                                },
+                                Ok(chunk) => {
+                                    state.done = chunk.len() < chunk_size;
+                                    state.next += 1;
+                                    (state.next - 1, chunk)
+                                },
+                                Err(e) => {
+                                    state.error = Some(FtpError::ConnectionError(e));
+                                    break;
+                                },
+                            }
+                        };
+
+                        let result = client.put_file(&chunk_part_path(path, index), &mut || Ok(Cursor::new(chunk.as_slice())));
+                        let mut state = lock(&state);
+                        match result {
+                            Ok(bytes) => state.bytes += bytes,
+                            Err(e) => {
+                                state.error.get_or_insert(e);
+                                break;
+                            },
+                        }
+                    }
+                });
+            }
+        });
+
+        let state = state.into_inner().unwrap_or_else(|e| e.into_inner());
+        let parts: Vec<String> = (0..state.next).map(|index| chunk_part_path(path, index)).collect();
+        let mut client = self.get();
+
+        let result = match state.error {
+            Some(e) => Err(e),
+            None => client.combine(path, &parts),
+        };
+        if let Err(e) = result {
+            let parts: Vec<&str> = parts.iter().map(String::as_str).collect();
+            let _ = client.delete_many(&parts);
+            return Err(e);
+        }
+
+        Ok(state.bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::client::FtpClientSettings;
+    use crate::mock::support::*;
+
+    #[test]
+    fn test_download_if_newer() {
+        let server = server();
+        let mut client = FtpClient::new(settings(&server));
+
+        let local = temp_path("newer");
+        let _ = std::fs::remove_file(&local);
+
+        assert!(client.download_if_newer("/readme", &local).unwrap());
+        assert!(!client.download_if_newer("/readme", &local).unwrap());
+
+        // the server clock runs an hour ahead
+        client.set_clock_offset(chrono::Duration::hours(1));
+        let options = FtpTransferOptions { preserve_mtime: true, overwrite: Some(OverwritePolicy::Overwrite), ..FtpTransferOptions::default() };
+        client.download_file_with("/readme", &local, &options).unwrap();
+        let mtime = filetime::FileTime::from_last_modification_time(&std::fs::metadata(&local).unwrap());
+        let expected = NaiveDateTime::parse_from_str("20210101000000", "%Y%m%d%H%M%S").unwrap() - chrono::Duration::hours(1);
+        assert_eq!(mtime.unix_seconds(), expected.and_utc().timestamp());
+        std::fs::remove_file(&local).unwrap();
+    }
+
+    #[derive(Debug)]
+    struct SkipSettings(String);
+
+    impl FtpClientSettings for SkipSettings {
+        fn addr(&self) -> &str { &self.0 }
+        fn login(&self) -> &str { crate::mock::MOCK_SERVER_LOGIN }
+        fn password(&self) -> &str { crate::mock::MOCK_SERVER_PASSWORD }
+        fn remote_dir(&self) -> Option<&str> { None }
+
+        #[cfg(feature = "sync-secure")]
+        fn use_secure(&self) -> bool { false }
+
+        fn overwrite_policy(&self) -> OverwritePolicy { OverwritePolicy::Skip }
+    }
+
+    #[test]
+    fn test_download_if_newer_skip_policy() {
+        let server = server();
+        let mut client = FtpClient::new(Box::new(SkipSettings(server.addr())));
+
+        let local = temp_path("newer-skip");
+        std::fs::write(&local, "old").unwrap();
+        filetime::set_file_mtime(&local, filetime::FileTime::from_unix_time(0, 0)).unwrap();
+
+        assert!(client.download_if_newer("/readme", &local).unwrap());
+        assert_eq!(std::fs::read_to_string(&local).unwrap(), "readme");
+        std::fs::remove_file(&local).unwrap();
+    }
+
+    #[test]
+    fn test_overwrite_policy() {
+        let server = server();
+        let mut client = FtpClient::new(settings(&server));
+
+        let local = temp_path("overwrite");
+        std::fs::write(&local, "local").unwrap();
+
+        let options = |overwrite| FtpTransferOptions { overwrite: Some(overwrite), ..FtpTransferOptions::default() };
+        assert_eq!(client.download_file_with("/readme", &local, &options(OverwritePolicy::Skip)).unwrap(), None);
+        assert!(client.download_file_with("/readme", &local, &options(OverwritePolicy::Error)).is_err());
+        assert_eq!(std::fs::read_to_string(&local).unwrap(), "local");
+
+        client.download_file_with("/readme", &local, &options(OverwritePolicy::Backup)).unwrap();
+        assert_eq!(std::fs::read_to_string(&local).unwrap(), "readme");
+        let backup = PathBuf::from(format!("{}.bak", local.display()));
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "local");
+
+        client.upload_file_with(&backup, "/readme", &options(OverwritePolicy::RenameExisting(".old".to_string()))).unwrap();
+        assert_eq!(server.file("/readme.old"), Some(b"readme".to_vec()));
+        assert_eq!(server.file("/readme"), Some(b"local".to_vec()));
+
+        std::fs::remove_file(&local).unwrap();
+        std::fs::remove_file(&backup).unwrap();
+    }
+
+    #[test]
+    fn test_resume_download() {
+        let server = server();
+        let mut client = FtpClient::new(settings(&server));
+
+        let local = temp_path("resume");
+        let part = PathBuf::from(format!("{}.partial", local.display()));
+        let options = FtpTransferOptions {
+            resume: true,
+            resume_overlap: 2,
+            part_suffix: ".partial".to_string(),
+            verify_size: true,
+            ..FtpTransferOptions::default()
+        };
+
+        let info = FtpResumeInfo {
+            remote: "/data/a.txt".to_string(),
+            size: Some(5),
+            modified: client.modified("/data/a.txt").unwrap().map(|modified| modified.and_utc().timestamp()),
+            head: None,
+        };
+
+        std::fs::write(&part, "hel").unwrap();
+        info.save(&part).unwrap();
+        assert_eq!(client.download_file_with("/data/a.txt", &local, &options).unwrap(), Some(5));
+        assert_eq!(std::fs::read_to_string(&local).unwrap(), "hello");
+        assert!(FtpResumeInfo::load(&part).is_none());
+
+        // the partial file is from a different version of the remote one
+        std::fs::write(&part, "hex").unwrap();
+        info.save(&part).unwrap();
+        client.download_file_with("/data/a.txt", &local, &options).unwrap();
+        assert_eq!(std::fs::read_to_string(&local).unwrap(), "hello");
+        assert!(!part.exists());
+
+        // started from another remote file, or changed locally since it stopped
+        std::fs::write(&part, "hel").unwrap();
+        assert!(info.allows(&info, &part));
+        assert!(!info.allows(&FtpResumeInfo { size: Some(4), ..info.clone() }, &part));
+        assert!(!info.allows(&FtpResumeInfo { head: Some(0), ..info.clone() }, &part));
+        std::fs::remove_file(&part).unwrap();
+
+        std::fs::remove_file(&local).unwrap();
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_upload_mapped() {
+        let server = server();
+        let mut client = FtpClient::new(settings(&server));
+
+        let local = temp_path("mmap");
+        std::fs::write(&local, "mapped").unwrap();
+
+        let options = FtpTransferOptions { memory_map: true, ..FtpTransferOptions::default() };
+        assert_eq!(client.upload_file_with(&local, "/mapped", &options).unwrap(), Some(6));
+        assert_eq!(server.file("/mapped"), Some(b"mapped".to_vec()));
+
+        std::fs::remove_file(&local).unwrap();
+    }
+
+    #[test]
+    fn test_transfer_adapter() {
+        struct XorWriter<'a>(&'a mut dyn Write);
+
+        impl Write for XorWriter<'_> {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let data: Vec<u8> = buf.iter().map(|byte| byte ^ 0x55).collect();
+                self.0.write_all(&data)?;
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                self.0.flush()
+            }
+        }
+
+        impl FtpAdapterWrite for XorWriter<'_> {
+            fn finish(self: Box<Self>) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        struct XorAdapter;
+
+        impl FtpTransferAdapter for XorAdapter {
+            fn encoder<'a>(&self, inner: &'a mut dyn Write) -> io::Result<Box<dyn FtpAdapterWrite + 'a>> {
+                Ok(Box::new(XorWriter(inner)))
+            }
+
+            fn decoder<'a>(&self, inner: &'a mut dyn Write) -> io::Result<Box<dyn FtpAdapterWrite + 'a>> {
+                Ok(Box::new(XorWriter(inner)))
+            }
+        }
+
+        let server = server();
+        let mut client = FtpClient::new(settings(&server));
+        let options = FtpTransferOptions { adapter: Some(Arc::new(XorAdapter)), ..FtpTransferOptions::default() };
+
+        let local = temp_path("adapter");
+        std::fs::write(&local, "secret").unwrap();
+        assert_eq!(client.upload_file_with(&local, "/secret", &options).unwrap(), Some(6));
+        assert_eq!(server.file("/secret"), Some(b"secret".iter().map(|byte| byte ^ 0x55).collect()));
+
+        client.download_file_with("/secret", &local, &options).unwrap();
+        assert_eq!(std::fs::read_to_string(&local).unwrap(), "secret");
+        std::fs::remove_file(&local).unwrap();
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip_adapter() {
+        let server = server();
+        let mut client = FtpClient::new(settings(&server));
+        let options = FtpTransferOptions { adapter: Some(Arc::new(FtpGzipAdapter::default())), ..FtpTransferOptions::default() };
+
+        let local = temp_path("gzip");
+        let content = "compressible ".repeat(100);
+        std::fs::write(&local, &content).unwrap();
+        assert_eq!(client.upload_file_with(&local, "/content.gz", &options).unwrap(), Some(content.len() as u64));
+
+        // stored as a complete gzip stream, smaller than the file
+        let stored = server.file("/content.gz").unwrap();
+        assert!(stored.starts_with(&[0x1f, 0x8b]) && stored.len() < content.len());
+        let mut unpacked = String::new();
+        flate2::read::GzDecoder::new(stored.as_slice()).read_to_string(&mut unpacked).unwrap();
+        assert_eq!(unpacked, content);
+
+        client.download_file_with("/content.gz", &local, &options).unwrap();
+        assert_eq!(std::fs::read_to_string(&local).unwrap(), content);
+        std::fs::remove_file(&local).unwrap();
+    }
+
+    #[test]
+    fn test_upload_chunked() {
+        let server = server();
+        let content: Vec<u8> = (0..100u8).collect();
+
+        // without COMB the parts are appended in order
+        assert_eq!(pool(&server, 3).upload_chunked("/chunked", content.as_slice(), 30).unwrap(), 100);
+        assert_eq!(server.file("/chunked"), Some(content.clone()));
+        assert!(server.commands().iter().any(|command| command == "APPE /chunked"));
+
+        server.set_features(vec!["MLST type*;size*;modify*;".to_string(), "COMB".to_string()]);
+        assert_eq!(pool(&server, 3).upload_chunked("/combined", content.as_slice(), 30).unwrap(), 100);
+        assert_eq!(server.file("/combined"), Some(content));
+        assert_eq!(server.file("/combined.part0"), None);
+    }
+
+    #[test]
+    fn test_download_segmented() {
+        let server = server();
+        let content: Vec<u8> = (0..100u8).collect();
+        server.add_file("/big", content.clone());
+        let pool = pool(&server, 3);
+
+        let local = temp_path("segmented");
+        assert_eq!(pool.download_segmented("/big", &local, 3).unwrap(), 100);
+        assert_eq!(std::fs::read(&local).unwrap(), content);
+        assert!(server.commands().iter().any(|command| command == "REST 68"));
+
+        std::fs::remove_file(&local).unwrap();
+    }
+}
@@ -0,0 +1,680 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use suppaftp::{sync_ftp::FtpStream, types::{FtpError, FtpResult, Mode, Response}};
+use socket2::{SockRef, TcpKeepalive};
+use crate::client::{FtpAddressPreference, FtpSocketOptions};
+#[cfg(feature = "sync-secure")]
+use native_tls::TlsConnector;
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub(crate) trait FtpTransport: std::fmt::Debug + Send {
+    #[cfg(feature = "sync-secure")]
+    fn into_secure(self: Box<Self>, tls_connector: TlsConnector, domain: &str) -> FtpResult<Box<dyn FtpTransport>>;
+
+    fn welcome_message(&self) -> Option<String>;
+    fn command(&mut self, command: &str) -> FtpResult<Response>;
+    // all commands are written before the first reply is read; plain connections only
+    fn pipeline(&mut self, commands: &[String]) -> FtpResult<Vec<Response>>;
+    fn noop(&mut self) -> FtpResult<()>;
+    fn quit(&mut self) -> FtpResult<()>;
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> FtpResult<()>;
+    // how later data connections are opened
+    fn set_mode(&mut self, mode: Mode);
+    fn feat(&mut self) -> FtpResult<Vec<String>>;
+
+    fn list(&mut self, path: Option<&str>) -> FtpResult<Vec<String>>;
+    fn nlst(&mut self, path: Option<&str>) -> FtpResult<Vec<String>>;
+    fn mlsd(&mut self, path: Option<&str>) -> FtpResult<Vec<String>>;
+    // The MLSD reply read as it arrives. Finalizing reads the closing reply; a
+    // stream dropped early instead leaves the control connection unusable.
+    fn mlsd_as_stream(&mut self, path: Option<&str>) -> FtpResult<Box<dyn BufRead + Send>>;
+    fn finalize_mlsd_stream(&mut self, stream: Box<dyn BufRead + Send>) -> FtpResult<()>;
+
+    fn retr(&mut self, path: &str, reader: &mut dyn FnMut(&mut dyn Read) -> FtpResult<()>) -> FtpResult<()>;
+    // REST and RETR together, so a reconnect can't separate them
+    fn retr_from(&mut self, path: &str, offset: usize, reader: &mut dyn FnMut(&mut dyn Read) -> FtpResult<()>) -> FtpResult<()>;
+    fn put_file(&mut self, path: &str, reader: &mut dyn Read) -> FtpResult<u64>;
+    fn append_file(&mut self, path: &str, reader: &mut dyn Read) -> FtpResult<u64>;
+    fn retr_as_stream(&mut self, path: &str) -> FtpResult<Box<dyn Read + Send>>;
+    fn finalize_retr_stream(&mut self, stream: Box<dyn Read + Send>) -> FtpResult<()>;
+    fn put_with_stream(&mut self, path: &str) -> FtpResult<Box<dyn Write + Send>>;
+    fn finalize_put_stream(&mut self, stream: Box<dyn Write + Send>) -> FtpResult<()>;
+}
+
+pub(crate) trait FtpConnector: std::fmt::Debug + Send {
+    fn connect(&mut self, addr: &str) -> FtpResult<Box<dyn FtpTransport>>;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub(crate) struct FtpStreamConnector {
+    pub preference: FtpAddressPreference,
+    pub timeout: Option<Duration>,
+    pub socket_options: FtpSocketOptions,
+}
+
+pub(crate) fn apply_socket_options(stream: &TcpStream, options: &FtpSocketOptions) -> io::Result<()> {
+    if let Some(nodelay) = options.nodelay {
+        stream.set_nodelay(nodelay)?;
+    }
+
+    let socket = SockRef::from(stream);
+
+    if options.keepalive_time.is_some() || options.keepalive_interval.is_some() {
+        let mut keepalive = TcpKeepalive::new();
+        if let Some(time) = options.keepalive_time {
+            keepalive = keepalive.with_time(time);
+        }
+        #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+        if let Some(interval) = options.keepalive_interval {
+            keepalive = keepalive.with_interval(interval);
+        }
+        socket.set_tcp_keepalive(&keepalive)?;
+    }
+
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+    if let Some(size) = options.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn resolve_addrs(addr: &str, preference: FtpAddressPreference) -> io::Result<Vec<SocketAddr>> {
+    Ok(sort_addrs(addr.to_socket_addrs()?.collect(), preference))
+}
+
+fn sort_addrs(mut addrs: Vec<SocketAddr>, preference: FtpAddressPreference) -> Vec<SocketAddr> {
+    // stable sort: resolver order is kept within each address family
+    match preference {
+        FtpAddressPreference::Any => {},
+        FtpAddressPreference::PreferIpv4 => addrs.sort_by_key(SocketAddr::is_ipv6),
+        FtpAddressPreference::PreferIpv6 => addrs.sort_by_key(SocketAddr::is_ipv4),
+    }
+    addrs
+}
+
+impl FtpConnector for FtpStreamConnector {
+    fn connect(&mut self, addr: &str) -> FtpResult<Box<dyn FtpTransport>> {
+        let addrs = resolve_addrs(addr, self.preference).map_err(FtpError::ConnectionError)?;
+        self.connect_first(addr, addrs)
+    }
+}
+
+impl FtpStreamConnector {
+    // The first of `addrs` that accepts, in order; `addr` names the server in the
+    // error when there are none.
+    fn connect_first(&self, addr: &str, addrs: Vec<SocketAddr>) -> FtpResult<Box<dyn FtpTransport>> {
+        let mut last_error = None;
+        for addr in addrs {
+            let result = match self.timeout {
+                Some(timeout) => FtpStream::connect_timeout(addr, timeout),
+                None => FtpStream::connect(addr),
+            };
+
+            match result {
+                Ok(stream) => {
+                    apply_socket_options(stream.get_ref(), &self.socket_options).map_err(FtpError::ConnectionError)?;
+                    return Ok(Box::new(FtpStreamTransport::new(stream, self.socket_options)));
+                },
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| FtpError::ConnectionError(io::Error::new(io::ErrorKind::AddrNotAvailable, addr.to_string()))))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// On a plain passive session the data connections are opened here, so they get
+// the socket options too. suppaftp has to wrap them in TLS on a secure session,
+// and opens them itself, with the system defaults.
+#[derive(Debug)]
+pub(crate) struct FtpStreamTransport {
+    stream: FtpStream,
+    socket_options: FtpSocketOptions,
+    mode: Mode,
+    secure: bool,
+}
+
+impl FtpStreamTransport {
+    pub(crate) fn new(stream: FtpStream, socket_options: FtpSocketOptions) -> Self {
+        Self { stream, socket_options, mode: Mode::Passive, secure: false }
+    }
+
+    fn opens_data(&self) -> bool {
+        !self.secure && !matches!(self.mode, Mode::Active)
+    }
+
+    // Passive, and to the control connection's peer whatever address PASV names,
+    // which also gets past servers that report their address behind NAT.
+    fn open_passive_data(&mut self) -> FtpResult<TcpStream> {
+        let peer = self.stream.get_ref().peer_addr().map_err(FtpError::ConnectionError)?;
+
+        let reply = FtpTransport::command(self, "EPSV")?;
+        let port = if reply.code == 229 {
+            parse_epsv_port(&reply.body)
+        } else {
+            let reply = FtpTransport::command(self, "PASV")?;
+            if reply.code != 227 {
+                return Err(FtpError::UnexpectedResponse(reply));
+            }
+            parse_pasv_port(&reply.body)
+        }.ok_or(FtpError::BadResponse)?;
+
+        let stream = TcpStream::connect(SocketAddr::new(peer.ip(), port)).map_err(FtpError::ConnectionError)?;
+        apply_socket_options(&stream, &self.socket_options).map_err(FtpError::ConnectionError)?;
+        let control = self.stream.get_ref();
+        stream.set_read_timeout(control.read_timeout().map_err(FtpError::ConnectionError)?).map_err(FtpError::ConnectionError)?;
+        stream.set_write_timeout(control.write_timeout().map_err(FtpError::ConnectionError)?).map_err(FtpError::ConnectionError)?;
+        Ok(stream)
+    }
+
+    // The data connection `command` is answered on, after its preliminary reply.
+    fn data_command(&mut self, command: &str) -> FtpResult<TcpStream> {
+        let data = self.open_passive_data()?;
+        let reply = FtpTransport::command(self, command)?;
+        if reply.code != 125 && reply.code != 150 {
+            return Err(FtpError::UnexpectedResponse(reply));
+        }
+        Ok(data)
+    }
+
+    // The reply that follows a closed data connection.
+    fn closing_reply(&mut self) -> FtpResult<()> {
+        let reply = match FtpStream::read_response_in(&mut self.stream, &[]) {
+            Err(FtpError::UnexpectedResponse(response)) => response,
+            result => result?,
+        };
+        match reply.code {
+            226 | 250 => Ok(()),
+            _ => Err(FtpError::UnexpectedResponse(reply)),
+        }
+    }
+
+    fn data_lines(&mut self, command: &str) -> FtpResult<Vec<String>> {
+        let lines = BufReader::new(self.data_command(command)?).lines()
+            .map(|line| line.map(|line| line.trim_end_matches('\r').to_string()))
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(FtpError::ConnectionError);
+        let closing = self.closing_reply();
+        let lines = lines?;
+        closing.map(|_| lines)
+    }
+
+    fn data_retr(&mut self, command: &str, reader: &mut dyn FnMut(&mut dyn Read) -> FtpResult<()>) -> FtpResult<()> {
+        let mut data = self.data_command(command)?;
+        let result = reader(&mut data);
+        drop(data);
+        let closing = self.closing_reply();
+        result.and(closing)
+    }
+
+    fn data_stor(&mut self, command: &str, reader: &mut dyn Read) -> FtpResult<u64> {
+        let mut data = self.data_command(command)?;
+        let written = io::copy(reader, &mut data).map_err(FtpError::ConnectionError);
+        drop(data);
+        let closing = self.closing_reply();
+        let written = written?;
+        closing.map(|_| written)
+    }
+}
+
+fn optional_argument(verb: &str, path: Option<&str>) -> String {
+    match path {
+        Some(path) => format!("{} {}", verb, path),
+        None => verb.to_string(),
+    }
+}
+
+impl FtpTransport for FtpStreamTransport {
+    #[cfg(feature = "sync-secure")]
+    fn into_secure(self: Box<Self>, tls_connector: TlsConnector, domain: &str) -> FtpResult<Box<dyn FtpTransport>> {
+        let this = *self;
+        Ok(Box::new(Self {
+            stream: FtpStream::into_secure(this.stream, tls_connector, domain)?,
+            secure: true,
+            ..this
+        }))
+    }
+
+    fn welcome_message(&self) -> Option<String> {
+        self.stream.get_welcome_msg().map(str::to_string)
+    }
+
+    fn command(&mut self, command: &str) -> FtpResult<Response> {
+        // any reply is handed back to the caller, whatever its code
+        match self.stream.custom_command(command, &[]) {
+            Err(FtpError::UnexpectedResponse(response)) => Ok(response),
+            result => result,
+        }
+    }
+
+    fn pipeline(&mut self, commands: &[String]) -> FtpResult<Vec<Response>> {
+        let batch: String = commands.iter().map(|command| format!("{}\r\n", command)).collect();
+        // straight to the socket, which is only valid on a plain control connection
+        (&*self.stream.get_ref()).write_all(batch.as_bytes()).map_err(FtpError::ConnectionError)?;
+
+        commands.iter()
+            .map(|_| match self.stream.read_response_in(&[]) {
+                Err(FtpError::UnexpectedResponse(response)) => Ok(response),
+                result => result,
+            })
+            .collect()
+    }
+
+    fn noop(&mut self) -> FtpResult<()> {
+        self.stream.noop()
+    }
+
+    fn quit(&mut self) -> FtpResult<()> {
+        self.stream.quit()
+    }
+
+    fn set_timeout(&mut self, timeout: Option<Duration>) -> FtpResult<()> {
+        let stream = self.stream.get_ref();
+        stream.set_read_timeout(timeout).map_err(FtpError::ConnectionError)?;
+        stream.set_write_timeout(timeout).map_err(FtpError::ConnectionError)
+    }
+
+    fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.stream.set_mode(mode)
+    }
+
+    fn feat(&mut self) -> FtpResult<Vec<String>> {
+        self.stream.feat()
+    }
+
+    fn list(&mut self, path: Option<&str>) -> FtpResult<Vec<String>> {
+        if self.opens_data() {
+            return self.data_lines(&optional_argument("LIST", path));
+        }
+        self.stream.list(path)
+    }
+
+    fn nlst(&mut self, path: Option<&str>) -> FtpResult<Vec<String>> {
+        if self.opens_data() {
+            return self.data_lines(&optional_argument("NLST", path));
+        }
+        self.stream.nlst(path)
+    }
+
+    fn mlsd(&mut self, path: Option<&str>) -> FtpResult<Vec<String>> {
+        if self.opens_data() {
+            return self.data_lines(&optional_argument("MLSD", path));
+        }
+        self.stream.mlsd(path)
+    }
+
+    // FtpStream only streams RETR, so this always opens its own data connection,
+    // which is never encrypted: plain control connections only.
+    fn mlsd_as_stream(&mut self, path: Option<&str>) -> FtpResult<Box<dyn BufRead + Send>> {
+        let data = self.data_command(&optional_argument("MLSD", path))?;
+        Ok(Box::new(BufReader::new(data)))
+    }
+
+    fn finalize_mlsd_stream(&mut self, stream: Box<dyn BufRead + Send>) -> FtpResult<()> {
+        drop(stream);
+        self.closing_reply()
+    }
+
+    fn retr(&mut self, path: &str, reader: &mut dyn FnMut(&mut dyn Read) -> FtpResult<()>) -> FtpResult<()> {
+        if self.opens_data() {
+            return self.data_retr(&format!("RETR {}", path), reader);
+        }
+        self.stream.retr(path, |stream| reader(stream))
+    }
+
+    fn retr_from(&mut self, path: &str, offset: usize, reader: &mut dyn FnMut(&mut dyn Read) -> FtpResult<()>) -> FtpResult<()> {
+        self.stream.resume_transfer(offset)?;
+        self.retr(path, reader)
+    }
+
+    fn put_file(&mut self, path: &str, mut reader: &mut dyn Read) -> FtpResult<u64> {
+        if self.opens_data() {
+            return self.data_stor(&format!("STOR {}", path), reader);
+        }
+        self.stream.put_file(path, &mut reader)
+    }
+
+    fn append_file(&mut self, path: &str, mut reader: &mut dyn Read) -> FtpResult<u64> {
+        if self.opens_data() {
+            return self.data_stor(&format!("APPE {}", path), reader);
+        }
+        self.stream.append_file(path, &mut reader)
+    }
+
+    fn retr_as_stream(&mut self, path: &str) -> FtpResult<Box<dyn Read + Send>> {
+        if self.opens_data() {
+            return Ok(Box::new(self.data_command(&format!("RETR {}", path))?));
+        }
+        Ok(Box::new(self.stream.retr_as_stream(path)?))
+    }
+
+    fn finalize_retr_stream(&mut self, stream: Box<dyn Read + Send>) -> FtpResult<()> {
+        if self.opens_data() {
+            drop(stream);
+            return self.closing_reply();
+        }
+        self.stream.finalize_retr_stream(stream)
+    }
+
+    fn put_with_stream(&mut self, path: &str) -> FtpResult<Box<dyn Write + Send>> {
+        if self.opens_data() {
+            return Ok(Box::new(self.data_command(&format!("STOR {}", path))?));
+        }
+        Ok(Box::new(self.stream.put_with_stream(path)?))
+    }
+
+    fn finalize_put_stream(&mut self, stream: Box<dyn Write + Send>) -> FtpResult<()> {
+        if self.opens_data() {
+            drop(stream);
+            return self.closing_reply();
+        }
+        self.stream.finalize_put_stream(stream)
+    }
+}
+
+// "229 Entering Extended Passive Mode (|||6446|)"
+fn parse_epsv_port(body: &str) -> Option<u16> {
+    let start = body.find("|||")? + 3;
+    body[start..].split('|').next()?.parse().ok()
+}
+
+// "227 Entering Passive Mode (h1,h2,h3,h4,p1,p2)"
+fn parse_pasv_port(body: &str) -> Option<u16> {
+    let start = body.find('(')? + 1;
+    let end = start + body[start..].find(')')?;
+    let numbers = body[start..end].split(',')
+        .map(|number| number.trim().parse::<u8>().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    match numbers[..] {
+        [_, _, _, _, high, low] => Some(u16::from(high) << 8 | u16::from(low)),
+        _ => None,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+pub(crate) mod fake {
+
+    use chrono::NaiveDateTime;
+    use std::io::{self, BufRead, Cursor, Read, Write};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use suppaftp::types::{FtpError, FtpResult, Mode, Response};
+    use super::{FtpConnector, FtpTransport};
+
+    #[derive(Debug, Default)]
+    pub struct FtpFakeState {
+        pub connections: usize,
+        pub commands: Vec<String>,
+        pub features: Vec<String>,
+        pub mlsd: Vec<String>,
+        pub list: Vec<String>,
+        pub fail_next: usize,
+        pub cwd: String,
+        pub replies: Vec<Response>,
+        pub account_required: bool,
+        pub welcome: Option<String>,
+        pub mdtm: Option<NaiveDateTime>,
+        // connection attempts still to be refused
+        pub refuse_connections: usize,
+        // commands answered with 550, e.g. "MKD /a"
+        pub rejected: Vec<String>,
+        // as last set by the client
+        pub mode: Option<Mode>,
+    }
+
+    // Scripted transport: every command is recorded in the shared state, and the
+    // next `fail_next` commands fail with a recoverable connection error.
+    #[derive(Debug)]
+    pub struct FtpFakeTransport {
+        state: Arc<Mutex<FtpFakeState>>,
+    }
+
+    impl FtpFakeTransport {
+        fn record(&mut self, command: String) -> FtpResult<()> {
+            let mut state = self.state.lock().unwrap();
+            let rejected = state.rejected.contains(&command);
+            state.commands.push(command);
+            if state.fail_next > 0 {
+                state.fail_next -= 1;
+                return Err(FtpError::ConnectionError(io::Error::from(io::ErrorKind::ConnectionReset)));
+            }
+            if rejected {
+                return Err(FtpError::UnexpectedResponse(Response { code: 550, body: "550 Rejected".to_string() }));
+            }
+            Ok(())
+        }
+    }
+
+    impl FtpTransport for FtpFakeTransport {
+        #[cfg(feature = "sync-secure")]
+        fn into_secure(self: Box<Self>, _tls_connector: native_tls::TlsConnector, _domain: &str) -> FtpResult<Box<dyn FtpTransport>> {
+            Ok(self)
+        }
+
+        fn welcome_message(&self) -> Option<String> {
+            self.state.lock().unwrap().welcome.clone()
+        }
+
+        // The login is always answered like a server would; other commands get the
+        // queued `replies` first, then a server-like reply, then 200.
+        fn command(&mut self, command: &str) -> FtpResult<Response> {
+            match self.record(command.to_string()) {
+                Err(FtpError::UnexpectedResponse(reply)) => return Ok(reply),
+                result => result?,
+            }
+
+            let mut state = self.state.lock().unwrap();
+            let (verb, argument) = command.split_once(' ').unwrap_or((command, ""));
+            let (code, text) = match verb {
+                "USER" => (331, "Password required".to_string()),
+                "PASS" if state.account_required => (332, "Need account".to_string()),
+                "PASS" | "ACCT" => (230, "Logged in".to_string()),
+                _ if !state.replies.is_empty() => return Ok(state.replies.remove(0)),
+                "CWD" => {
+                    state.cwd = argument.to_string();
+                    (250, "Directory changed".to_string())
+                },
+                "PWD" => (257, format!("\"{}\" is the current directory", state.cwd)),
+                "MKD" => (257, format!("\"{}\" created", argument)),
+                "CDUP" | "RMD" | "DELE" | "RNTO" => (250, "Done".to_string()),
+                "RNFR" => (350, "Ready for RNTO".to_string()),
+                "MDTM" => match state.mdtm {
+                    Some(modified) => (213, modified.format("%Y%m%d%H%M%S").to_string()),
+                    None => (550, "No such file".to_string()),
+                },
+                "SIZE" => (213, "0".to_string()),
+                _ => (200, "OK".to_string()),
+            };
+            Ok(Response { code, body: format!("{} {}", code, text) })
+        }
+
+        fn pipeline(&mut self, commands: &[String]) -> FtpResult<Vec<Response>> {
+            commands.iter().map(|command| self.command(command)).collect()
+        }
+
+        fn noop(&mut self) -> FtpResult<()> {
+            self.record("NOOP".to_string())
+        }
+
+        fn quit(&mut self) -> FtpResult<()> {
+            self.record("QUIT".to_string())
+        }
+
+        fn set_timeout(&mut self, _timeout: Option<Duration>) -> FtpResult<()> {
+            Ok(())
+        }
+
+        fn set_mode(&mut self, mode: Mode) {
+            self.state.lock().unwrap().mode = Some(mode);
+        }
+
+        fn feat(&mut self) -> FtpResult<Vec<String>> {
+            self.record("FEAT".to_string())?;
+            Ok(self.state.lock().unwrap().features.clone())
+        }
+
+        fn list(&mut self, _path: Option<&str>) -> FtpResult<Vec<String>> {
+            self.record("LIST".to_string())?;
+            Ok(self.state.lock().unwrap().list.clone())
+        }
+
+        fn nlst(&mut self, _path: Option<&str>) -> FtpResult<Vec<String>> {
+            self.record("NLST".to_string())?;
+            Ok(vec![])
+        }
+
+        fn mlsd(&mut self, _path: Option<&str>) -> FtpResult<Vec<String>> {
+            self.record("MLSD".to_string())?;
+            Ok(self.state.lock().unwrap().mlsd.clone())
+        }
+
+        fn mlsd_as_stream(&mut self, path: Option<&str>) -> FtpResult<Box<dyn BufRead + Send>> {
+            let lines: String = self.mlsd(path)?.iter().map(|line| format!("{}\r\n", line)).collect();
+            Ok(Box::new(Cursor::new(lines.into_bytes())))
+        }
+
+        fn finalize_mlsd_stream(&mut self, _stream: Box<dyn BufRead + Send>) -> FtpResult<()> {
+            Ok(())
+        }
+
+        fn retr(&mut self, path: &str, reader: &mut dyn FnMut(&mut dyn Read) -> FtpResult<()>) -> FtpResult<()> {
+            self.record(format!("RETR {}", path))?;
+            reader(&mut Cursor::new(vec![]))
+        }
+
+        fn retr_from(&mut self, path: &str, offset: usize, reader: &mut dyn FnMut(&mut dyn Read) -> FtpResult<()>) -> FtpResult<()> {
+            self.record(format!("REST {}", offset))?;
+            self.retr(path, reader)
+        }
+
+        fn put_file(&mut self, path: &str, reader: &mut dyn Read) -> FtpResult<u64> {
+            self.record(format!("STOR {}", path))?;
+            io::copy(reader, &mut io::sink()).map_err(FtpError::ConnectionError)
+        }
+
+        fn append_file(&mut self, path: &str, reader: &mut dyn Read) -> FtpResult<u64> {
+            self.record(format!("APPE {}", path))?;
+            io::copy(reader, &mut io::sink()).map_err(FtpError::ConnectionError)
+        }
+
+        fn retr_as_stream(&mut self, path: &str) -> FtpResult<Box<dyn Read + Send>> {
+            self.record(format!("RETR {}", path))?;
+            Ok(Box::new(Cursor::new(vec![])))
+        }
+
+        fn finalize_retr_stream(&mut self, _stream: Box<dyn Read + Send>) -> FtpResult<()> {
+            Ok(())
+        }
+
+        fn put_with_stream(&mut self, path: &str) -> FtpResult<Box<dyn Write + Send>> {
+            self.record(format!("STOR {}", path))?;
+            Ok(Box::new(io::sink()))
+        }
+
+        fn finalize_put_stream(&mut self, _stream: Box<dyn Write + Send>) -> FtpResult<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Default)]
+    pub struct FtpFakeConnector {
+        pub state: Arc<Mutex<FtpFakeState>>,
+    }
+
+    impl FtpConnector for FtpFakeConnector {
+        fn connect(&mut self, _addr: &str) -> FtpResult<Box<dyn FtpTransport>> {
+            let mut state = self.state.lock().unwrap();
+            if state.refuse_connections > 0 {
+                state.refuse_connections -= 1;
+                return Err(FtpError::ConnectionError(io::Error::from(io::ErrorKind::ConnectionRefused)));
+            }
+            state.connections += 1;
+            drop(state);
+            Ok(Box::new(FtpFakeTransport {
+                state: self.state.clone(),
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::net::TcpListener;
+    use crate::mock::{MOCK_SERVER_LOGIN, MOCK_SERVER_PASSWORD, support::server};
+
+    #[test]
+    fn test_address_preference() {
+        let v6: SocketAddr = "[::1]:21".parse().unwrap();
+        let v4: SocketAddr = "127.0.0.1:21".parse().unwrap();
+        let other_v4: SocketAddr = "127.0.0.2:21".parse().unwrap();
+
+        let addrs = vec![v6, v4, other_v4];
+        assert_eq!(sort_addrs(addrs.clone(), FtpAddressPreference::Any), addrs);
+        assert_eq!(sort_addrs(addrs.clone(), FtpAddressPreference::PreferIpv4), vec![v4, other_v4, v6]);
+        assert_eq!(sort_addrs(vec![v4, v6, other_v4], FtpAddressPreference::PreferIpv6), vec![v6, v4, other_v4]);
+
+        assert_eq!(resolve_addrs("[::1]:21", FtpAddressPreference::PreferIpv4).unwrap(), vec![v6]);
+        assert_eq!(resolve_addrs("127.0.0.1:21", FtpAddressPreference::PreferIpv6).unwrap(), vec![v4]);
+    }
+
+    #[test]
+    fn test_connect_next_address() {
+        let server = server();
+        // nothing listens there once the listener is dropped
+        let refused = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let connector = FtpStreamConnector {
+            preference: FtpAddressPreference::Any,
+            timeout: Some(Duration::from_secs(5)),
+            socket_options: FtpSocketOptions::default(),
+        };
+
+        let mut transport = connector.connect_first(&server.addr(), vec![refused, server.addr().parse().unwrap()]).unwrap();
+        assert_eq!(transport.command("NOOP").unwrap().code, 200);
+
+        assert!(connector.connect_first(&server.addr(), vec![refused]).is_err());
+        assert!(matches!(connector.connect_first("nowhere:21", vec![]), Err(FtpError::ConnectionError(e)) if e.kind() == io::ErrorKind::AddrNotAvailable));
+    }
+
+    #[test]
+    fn test_passive_ports() {
+        assert_eq!(parse_epsv_port("229 Entering Extended Passive Mode (|||6446|)"), Some(6446));
+        assert_eq!(parse_epsv_port("229 Entering Extended Passive Mode"), None);
+        assert_eq!(parse_pasv_port("227 Entering Passive Mode (127,0,0,1,25,46)"), Some(25 << 8 | 46));
+        assert_eq!(parse_pasv_port("227 Entering Passive Mode (127,0,0,1,300,46)"), None);
+    }
+
+    #[test]
+    fn test_data_socket_options() {
+        let server = server();
+        let options = FtpSocketOptions { nodelay: Some(true), ..FtpSocketOptions::default() };
+        let mut transport = FtpStreamTransport::new(FtpStream::connect(server.addr()).unwrap(), options);
+        transport.command(&format!("USER {}", MOCK_SERVER_LOGIN)).unwrap();
+        assert_eq!(transport.command(&format!("PASS {}", MOCK_SERVER_PASSWORD)).unwrap().code, 230);
+
+        let mut data = transport.data_command("RETR /data/a.txt").unwrap();
+        assert!(data.nodelay().unwrap());
+        let mut content = String::new();
+        data.read_to_string(&mut content).unwrap();
+        drop(data);
+        transport.closing_reply().unwrap();
+        assert_eq!(content, "hello");
+
+        assert_eq!(transport.put_file("/data/b.txt", &mut "world".as_bytes()).unwrap(), 5);
+        assert_eq!(transport.nlst(Some("/data")).unwrap(), vec!["a.txt", "b.txt"]);
+    }
+}
@@ -1,6 +1,8 @@
 use chrono::NaiveDateTime;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::{From, TryFrom, TryInto};
+use std::iter::FromIterator;
+use std::sync::Arc;
 use suppaftp::list;
 use crate::mlst::MlstFilePermissions;
 
@@ -11,12 +13,18 @@ pub enum FtpItemType {
     File,
     Dir,
     CurrentDir,
-    ParentDir    
+    ParentDir,
+    // whether the target is a file or a directory isn't known from the listing
+    Symlink,
 }
 
 impl FtpItemType {
     pub fn is_dir(&self) -> bool {
-        *self != Self::File
+        matches!(self, Self::Dir | Self::CurrentDir | Self::ParentDir)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        *self == Self::Symlink
     }
 }
 
@@ -29,6 +37,8 @@ impl TryFrom<&str> for FtpItemType {
             "cdir"  => Ok(FtpItemType::CurrentDir),
             "pdir"  => Ok(FtpItemType::ParentDir),
             "dir"   => Ok(FtpItemType::Dir),
+            "os.unix=symlink" => Ok(FtpItemType::Symlink),
+            ty if ty.starts_with("os.unix=slink") => Ok(FtpItemType::Symlink),
             _ => Err(list::ParseError::SyntaxError),
         }
     }
@@ -39,28 +49,147 @@ impl TryFrom<&str> for FtpItemType {
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct FtpItem {
     pub name:               String,
+    // absolute remote path, known for items that come from a directory listing
+    pub path:               Option<String>,
     pub ty:                 FtpItemType,
     pub size:               Option<u64>,
     pub modified:           Option<NaiveDateTime>,
     pub created:            Option<NaiveDateTime>,
     pub unique:             Option<String>,
     pub perm:               Option<MlstFilePermissions>,
-    pub lang:               Option<String>,
-    pub media_type:         Option<String>,
-    pub charset:            Option<String>,
+    // the `Arc<str>` facts are shared within a listing, see `FtpStringInterner`
+    pub lang:               Option<Arc<str>>,
+    pub media_type:         Option<Arc<str>>,
+    pub charset:            Option<Arc<str>>,
     pub unix_owner:         Option<u32>,
-    pub unix_ownername:     Option<String>,
+    pub unix_ownername:     Option<Arc<str>>,
     pub unix_group:         Option<u32>,
-    pub unix_groupname:     Option<String>,
+    pub unix_groupname:     Option<Arc<str>>,
     pub unix_mode:          Option<u16>,
-    pub others:             Option<HashMap<String, String>>,
+    // target of a symbolic link, when the server reports it
+    pub link_target:        Option<String>,
+    pub others:             Option<FtpOtherFacts>,
+}
+
+impl FtpItem {
+    pub fn new<S: Into<String>>(name: S, ty: FtpItemType) -> Self {
+        Self {
+            name:   name.into(),
+            path:   None,
+            ty,
+            size:   None,
+            modified: None,
+            created: None,
+            unique: None,
+            perm:   None,
+            lang:   None,
+            media_type: None,
+            charset:    None,
+            unix_owner: None,
+            unix_ownername: None,
+            unix_group:     None,
+            unix_groupname: None,
+            unix_mode:  None,
+            link_target:    None,
+            others:     None,
+        }
+    }
+}
+
+// Facts without a field of their own, by lowercase name. Kept as a sorted `Vec`:
+// an item rarely has more than one or two, which a map would allocate a table for.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct FtpOtherFacts {
+    facts: Vec<(String, String)>,
+}
+
+impl FtpOtherFacts {
+    fn position(&self, name: &str) -> Result<usize, usize> {
+        self.facts.binary_search_by(|(other, _)| other.as_str().cmp(name))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.position(name).ok().map(|index| self.facts[index].1.as_str())
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.position(name).is_ok()
+    }
+
+    // The previous value of the fact, if it was already set.
+    pub fn insert(&mut self, name: String, value: String) -> Option<String> {
+        match self.position(&name) {
+            Ok(index) => Some(std::mem::replace(&mut self.facts[index].1, value)),
+            Err(index) => {
+                self.facts.insert(index, (name, value));
+                None
+            },
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.position(name).ok().map(|index| self.facts.remove(index).1)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.facts.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.facts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.facts.is_empty()
+    }
+}
+
+impl FromIterator<(String, String)> for FtpOtherFacts {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        let mut facts = Self::default();
+        for (name, value) in iter {
+            facts.insert(name, value);
+        }
+        facts
+    }
+}
+
+// Hands out one allocation per distinct fact value, so that the owner, group and
+// charset repeated by most entries of a huge directory are stored once.
+#[derive(Debug, Clone, Default)]
+pub struct FtpStringInterner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl FtpStringInterner {
+    pub fn intern(&mut self, value: &str) -> Arc<str> {
+        if let Some(interned) = self.strings.get(value) {
+            return interned.clone();
+        }
+        let interned: Arc<str> = Arc::from(value);
+        self.strings.insert(interned.clone());
+        interned
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
 }
 
 ///////////////////////////////////////////////////////////////////////////////
 
+// Entries of one directory. The first `cdir` entry is `current` and any further
+// ones, which some servers send for each name the directory is reachable under,
+// are kept in `current_aliases`. A client listing fills in `parent` from the path
+// when the server sent no `pdir` entry, so it is only missing for `/`.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct FtpList {
     pub current: Option<FtpItem>,
+    pub current_aliases: Vec<FtpItem>,
     pub parent: Option<FtpItem>,
     pub items: Vec<FtpItem>,
 }
@@ -69,8 +198,224 @@ impl Default for FtpList {
     fn default() -> Self {
         Self {
             current: None,
+            current_aliases: vec![],
             parent: None,
             items: vec![],        
         }
     }    
 }
+
+impl FtpList {
+    pub fn push(&mut self, item: FtpItem) {
+        match item.ty {
+            FtpItemType::CurrentDir if self.current.is_some() => {
+                self.current_aliases.push(item);
+            },
+            FtpItemType::CurrentDir => {
+                self.current = Some(item);
+            },
+            FtpItemType::ParentDir => {
+                self.parent = Some(item);
+            },
+            _ => {
+                self.items.push(item);
+            },
+        }
+    }
+
+    pub fn diff(&self, other: &FtpList) -> FtpListDiff {
+        let old: HashMap<&str, &FtpItem> = self.items.iter().map(|item| (item.name.as_str(), item)).collect();
+        let new: HashMap<&str, &FtpItem> = other.items.iter().map(|item| (item.name.as_str(), item)).collect();
+
+        let mut diff = FtpListDiff::default();
+
+        for item in &other.items {
+            match old.get(item.name.as_str()) {
+                None => {
+                    diff.added.push(item.clone());
+                },
+                Some(old_item) if old_item.is_changed(item) => {
+                    diff.changed.push(((*old_item).clone(), item.clone()));
+                },
+                _ => {},
+            }
+        }
+
+        for item in &self.items {
+            if !new.contains_key(item.name.as_str()) {
+                diff.removed.push(item.clone());
+            }
+        }
+
+        // a rename shows up as a removal plus an addition of the same file
+        for old_item in std::mem::take(&mut diff.removed) {
+            let position = old_item.unique.as_ref()
+                .and_then(|unique| diff.added.iter().position(|item| item.unique.as_ref() == Some(unique)));
+            match position {
+                Some(position) => {
+                    let new_item = diff.added.remove(position);
+                    diff.renamed.push((old_item, new_item));
+                },
+                None => diff.removed.push(old_item),
+            }
+        }
+
+        diff
+    }
+}
+
+impl FtpItem {
+    fn is_changed(&self, other: &FtpItem) -> bool {
+        self.ty != other.ty 
+            || self.size != other.size 
+            || self.modified != other.modified 
+            || self.unique != other.unique
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct FtpListDiff {
+    pub added:      Vec<FtpItem>,
+    pub removed:    Vec<FtpItem>,
+    pub changed:    Vec<(FtpItem, FtpItem)>,
+    // removed and added items with the same `unique` fact
+    pub renamed:    Vec<(FtpItem, FtpItem)>,
+}
+
+impl FtpListDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty() && self.renamed.is_empty()
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+// Reply to a bare STAT. The text is free-form, so only the lines most servers
+// (vsftpd, ProFTPD, Pure-FTPd) phrase alike are picked out; `lines` keeps all of it.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct FtpServerStatus {
+    pub connection:         Option<String>,
+    pub user:               Option<String>,
+    pub transfer_type:      Option<String>,
+    pub structure:          Option<String>,
+    pub mode:               Option<String>,
+    pub data_connection:    Option<String>,
+    pub lines:              Vec<String>,
+}
+
+fn strip_prefix_ignore_case<'a>(line: &'a str, prefix: &str) -> Option<&'a str> {
+    match line.get(..prefix.len()) {
+        Some(head) if head.eq_ignore_ascii_case(prefix) => Some(line[prefix.len()..].trim()),
+        _ => None,
+    }
+}
+
+impl FtpServerStatus {
+    pub fn parse(body: &str) -> Self {
+        let mut status = Self::default();
+
+        for line in body.lines() {
+            // drop the "211-" / "211 " framing
+            let bytes = line.as_bytes();
+            let line = if bytes.len() >= 4 && bytes[..3].iter().all(u8::is_ascii_digit) && (bytes[3] == b'-' || bytes[3] == b' ') {
+                &line[4..]
+            } else {
+                line
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(peer) = strip_prefix_ignore_case(line, "connected to").or_else(|| strip_prefix_ignore_case(line, "connected from")) {
+                status.connection = Some(peer.to_string());
+            } else if let Some(user) = strip_prefix_ignore_case(line, "logged in as") {
+                status.user = Some(user.to_string());
+            } else if strip_prefix_ignore_case(line, "type:").is_some() {
+                // "TYPE: BINARY, STRUcture: File, Mode: Stream"
+                for part in line.split(',') {
+                    let (key, value) = match part.split_once(':') {
+                        Some((key, value)) => (key.trim().to_ascii_lowercase(), value.trim().to_string()),
+                        None => continue,
+                    };
+                    match key.as_str() {
+                        "type" => status.transfer_type = Some(value),
+                        "structure" => status.structure = Some(value),
+                        "mode" => status.mode = Some(value),
+                        _ => {},
+                    }
+                }
+            } else if line.to_ascii_lowercase().contains("data connection") && status.data_connection.is_none() {
+                status.data_connection = Some(line.to_string());
+            }
+
+            status.lines.push(line.to_string());
+        }
+
+        status
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn item(name: &str, size: u64) -> FtpItem {
+        FtpItem {
+            size: Some(size),
+            ..FtpItem::new(name, FtpItemType::File)
+        }
+    }
+
+    fn list(items: Vec<FtpItem>) -> FtpList {
+        FtpList { items, ..Default::default() }
+    }
+
+    #[test]
+    fn test_push_aliases() {
+        let mut list = FtpList::default();
+        list.push(FtpItem::new("data", FtpItemType::CurrentDir));
+        list.push(FtpItem::new("/srv/data", FtpItemType::CurrentDir));
+        list.push(item("a", 1));
+        assert_eq!(list.current.map(|current| current.name), Some("data".to_string()));
+        assert_eq!(list.current_aliases.len(), 1);
+        assert_eq!(list.items.len(), 1);
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = list(vec![item("a", 1), item("b", 2), item("c", 3)]);
+        let new = list(vec![item("b", 2), item("c", 4), item("d", 5)]);
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, vec![item("d", 5)]);
+        assert_eq!(diff.removed, vec![item("a", 1)]);
+        assert_eq!(diff.changed, vec![(item("c", 3), item("c", 4))]);
+
+        assert!(new.diff(&new).is_empty());
+
+        let unique = |item: FtpItem, unique: &str| FtpItem { unique: Some(unique.to_string()), ..item };
+        let old = list(vec![unique(item("a", 1), "1"), unique(item("b", 2), "2")]);
+        let new = list(vec![unique(item("c", 1), "1"), unique(item("d", 2), "3")]);
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.renamed, vec![(unique(item("a", 1), "1"), unique(item("c", 1), "1"))]);
+        assert_eq!(diff.added, vec![unique(item("d", 2), "3")]);
+        assert_eq!(diff.removed, vec![unique(item("b", 2), "2")]);
+    }
+
+    #[test]
+    fn test_server_status() {
+        let status = FtpServerStatus::parse("211-Status of 'ProFTPD'\r\n Connected from 10.0.0.2 (10.0.0.2)\r\n Logged in as alice\r\n TYPE: BINARY, STRUcture: File, Mode: Stream\r\n No data connection\r\n211 End of status");
+        assert_eq!(status.connection.as_deref(), Some("10.0.0.2 (10.0.0.2)"));
+        assert_eq!(status.user.as_deref(), Some("alice"));
+        assert_eq!(status.transfer_type.as_deref(), Some("BINARY"));
+        assert_eq!(status.structure.as_deref(), Some("File"));
+        assert_eq!(status.mode.as_deref(), Some("Stream"));
+        assert_eq!(status.data_connection.as_deref(), Some("No data connection"));
+        assert_eq!(status.lines.len(), 6);
+    }
+}
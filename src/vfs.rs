@@ -0,0 +1,320 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Cursor, Read, Write};
+use suppaftp::types::{FtpError, FtpResult};
+use crate::{
+    client::FtpClient,
+    path::{join_path, parent_path},
+    transfer::{FtpReader, FtpWriter},
+    types::{FtpItem, FtpItemType, FtpList},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub trait FtpFsReader: Read {
+    fn finish(self: Box<Self>) -> FtpResult<()>;
+}
+
+pub trait FtpFsWriter: Write {
+    fn finish(self: Box<Self>) -> FtpResult<()>;
+}
+
+pub trait FtpFs {
+    fn open_read(&mut self, path: &str) -> FtpResult<Box<dyn FtpFsReader + '_>>;
+    fn open_write(&mut self, path: &str) -> FtpResult<Box<dyn FtpFsWriter + '_>>;
+    fn metadata(&mut self, path: &str) -> FtpResult<Option<FtpItem>>;
+    fn read_dir(&mut self, path: &str) -> FtpResult<FtpList>;
+    fn create_dir(&mut self, path: &str) -> FtpResult<()>;
+    fn remove(&mut self, path: &str) -> FtpResult<()>;
+    fn rename(&mut self, from: &str, to: &str) -> FtpResult<()>;
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl FtpFsReader for FtpReader<'_> {
+    fn finish(self: Box<Self>) -> FtpResult<()> {
+        FtpReader::finish(*self)
+    }
+}
+
+impl FtpFsWriter for FtpWriter<'_> {
+    fn finish(self: Box<Self>) -> FtpResult<()> {
+        FtpWriter::finish(*self)
+    }
+}
+
+impl FtpFs for FtpClient {
+    fn open_read(&mut self, path: &str) -> FtpResult<Box<dyn FtpFsReader + '_>> {
+        Ok(Box::new(FtpClient::open_read(self, path)?))
+    }
+
+    fn open_write(&mut self, path: &str) -> FtpResult<Box<dyn FtpFsWriter + '_>> {
+        Ok(Box::new(FtpClient::open_write(self, path)?))
+    }
+
+    fn metadata(&mut self, path: &str) -> FtpResult<Option<FtpItem>> {
+        self.lookup(path)
+    }
+
+    fn read_dir(&mut self, path: &str) -> FtpResult<FtpList> {
+        self.list_path(path)
+    }
+
+    fn create_dir(&mut self, path: &str) -> FtpResult<()> {
+        self.mkdir(path)
+    }
+
+    fn remove(&mut self, path: &str) -> FtpResult<()> {
+        match self.lookup(path)? {
+            Some(item) if item.ty.is_dir() => self.rmdir(path),
+            _ => self.rm(path),
+        }
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> FtpResult<()> {
+        FtpClient::rename(self, from, to)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn not_found(path: &str) -> FtpError {
+    FtpError::ConnectionError(io::Error::new(io::ErrorKind::NotFound, path.to_string()))
+}
+
+fn normalize(path: &str) -> String {
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.is_empty() && path.starts_with('/') {
+        "/".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FtpMemoryFs {
+    files: BTreeMap<String, Vec<u8>>,
+    dirs: BTreeSet<String>,
+}
+
+impl FtpMemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert_file<C: Into<Vec<u8>>>(&mut self, path: &str, content: C) {
+        self.files.insert(normalize(path), content.into());
+    }
+
+    pub fn file(&self, path: &str) -> Option<&[u8]> {
+        self.files.get(&normalize(path)).map(Vec::as_slice)
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        path.is_empty() || path == "/" || self.dirs.contains(path)
+    }
+}
+
+struct FtpMemoryReader {
+    cursor: Cursor<Vec<u8>>,
+}
+
+impl Read for FtpMemoryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.cursor.read(buf)
+    }
+}
+
+impl FtpFsReader for FtpMemoryReader {
+    fn finish(self: Box<Self>) -> FtpResult<()> {
+        Ok(())
+    }
+}
+
+struct FtpMemoryWriter<'a> {
+    fs: &'a mut FtpMemoryFs,
+    path: String,
+    buffer: Option<Vec<u8>>,
+}
+
+impl FtpMemoryWriter<'_> {
+    fn commit(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.fs.files.insert(self.path.clone(), buffer);
+        }
+    }
+}
+
+impl Write for FtpMemoryWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.get_or_insert_with(Vec::new).extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FtpFsWriter for FtpMemoryWriter<'_> {
+    fn finish(mut self: Box<Self>) -> FtpResult<()> {
+        self.commit();
+        Ok(())
+    }
+}
+
+impl Drop for FtpMemoryWriter<'_> {
+    fn drop(&mut self) {
+        self.commit();
+    }
+}
+
+impl FtpFs for FtpMemoryFs {
+    fn open_read(&mut self, path: &str) -> FtpResult<Box<dyn FtpFsReader + '_>> {
+        let content = self.files.get(&normalize(path)).ok_or_else(|| not_found(path))?;
+        Ok(Box::new(FtpMemoryReader {
+            cursor: Cursor::new(content.clone()),
+        }))
+    }
+
+    fn open_write(&mut self, path: &str) -> FtpResult<Box<dyn FtpFsWriter + '_>> {
+        let path = normalize(path);
+        if !self.is_dir(parent_path(&path)) {
+            return Err(not_found(parent_path(&path)));
+        }
+
+        Ok(Box::new(FtpMemoryWriter {
+            fs: self,
+            path,
+            buffer: Some(vec![]),
+        }))
+    }
+
+    fn metadata(&mut self, path: &str) -> FtpResult<Option<FtpItem>> {
+        let path = normalize(path);
+        let name = path.rsplit('/').next().unwrap_or_default();
+
+        if let Some(content) = self.files.get(&path) {
+            return Ok(Some(FtpItem {
+                size: Some(content.len() as u64),
+                ..FtpItem::new(name, FtpItemType::File)
+            }));
+        }
+
+        if self.dirs.contains(&path) {
+            return Ok(Some(FtpItem::new(name, FtpItemType::Dir)));
+        }
+
+        Ok(None)
+    }
+
+    fn read_dir(&mut self, path: &str) -> FtpResult<FtpList> {
+        let path = normalize(path);
+        if !self.is_dir(&path) {
+            return Err(not_found(&path));
+        }
+
+        let children = self.dirs.iter()
+            .chain(self.files.keys())
+            .filter(|child| parent_path(child) == path)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut list = FtpList::default();
+        for child in children {
+            if let Some(item) = self.metadata(&child)? {
+                list.items.push(item);
+            }
+        }
+
+        Ok(list)
+    }
+
+    fn create_dir(&mut self, path: &str) -> FtpResult<()> {
+        let path = normalize(path);
+        if !self.is_dir(parent_path(&path)) {
+            return Err(not_found(parent_path(&path)));
+        }
+
+        self.dirs.insert(path);
+        Ok(())
+    }
+
+    fn remove(&mut self, path: &str) -> FtpResult<()> {
+        let path = normalize(path);
+
+        if self.files.remove(&path).is_some() {
+            return Ok(());
+        }
+
+        let has_children = self.dirs.iter()
+            .chain(self.files.keys())
+            .any(|child| parent_path(child) == path);
+
+        if has_children || !self.dirs.remove(&path) {
+            return Err(not_found(&path));
+        }
+
+        Ok(())
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> FtpResult<()> {
+        let from = normalize(from);
+        let to = normalize(to);
+
+        if let Some(content) = self.files.remove(&from) {
+            self.files.insert(to, content);
+            return Ok(());
+        }
+
+        if !self.dirs.contains(&from) {
+            return Err(not_found(&from));
+        }
+
+        let prefix = join_path(&from, "");
+        let moved_dirs: Vec<_> = self.dirs.iter().filter(|dir| **dir == from || dir.starts_with(&prefix)).cloned().collect();
+        for dir in moved_dirs {
+            self.dirs.remove(&dir);
+            self.dirs.insert(format!("{}{}", to, &dir[from.len()..]));
+        }
+
+        let moved_files: Vec<_> = self.files.keys().filter(|file| file.starts_with(&prefix)).cloned().collect();
+        for file in moved_files {
+            if let Some(content) = self.files.remove(&file) {
+                self.files.insert(format!("{}{}", to, &file[from.len()..]), content);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_memory_fs() {
+        let mut fs = FtpMemoryFs::new();
+        fs.create_dir("/data").unwrap();
+
+        let mut writer = fs.open_write("/data/a.txt").unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.finish().unwrap();
+
+        let mut content = String::new();
+        fs.open_read("/data/a.txt").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "hello");
+
+        fs.rename("/data", "/archive").unwrap();
+        let list = fs.read_dir("/archive").unwrap();
+        assert_eq!(list.items.len(), 1);
+        assert_eq!(list.items[0].name, "a.txt");
+        assert_eq!(list.items[0].size, Some(5));
+
+        assert!(fs.remove("/archive").is_err());
+        fs.remove("/archive/a.txt").unwrap();
+        fs.remove("/archive").unwrap();
+        assert_eq!(fs.metadata("/archive").unwrap(), None);
+    }
+}
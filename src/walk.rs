@@ -0,0 +1,396 @@
+use chrono::NaiveDateTime;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::Arc;
+use suppaftp::types::{FtpError, FtpResult};
+use crate::{
+    client::FtpClient,
+    mlst::MlstFact,
+    path::{join_path, resolve_dot_segments},
+    pattern::wildcard_match_any,
+    types::{FtpItem, FtpItemType},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FtpWalkControl {
+    Continue,
+    SkipDir,
+    Stop,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FtpWalkEntry {
+    pub path:   String,
+    pub depth:  usize,
+    pub item:   FtpItem,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum FtpWalkOrder {
+    // a directory's subdirectories are walked before its siblings'
+    #[default]
+    DepthFirst,
+    // level by level
+    BreadthFirst,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum FollowSymlinks {
+    #[default]
+    Never,
+    // a link back up the tree makes the walk endless unless it is bounded by `max_depth`
+    Always,
+    // never into a directory already walked, as told by the `unique` facts of the
+    // link and of the listings, or by the link's target; a link that reports neither
+    // a `unique` fact nor a target isn't followed
+    Safe,
+}
+
+pub type FtpWalkPrune = Arc<dyn Fn(&FtpWalkEntry) -> bool + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct FtpWalkOptions {
+    // entries deeper than this aren't listed; the root's children are at depth 1
+    pub max_depth:  Option<usize>,
+    // directories it returns `true` for are visited but not descended into
+    pub prune:      Option<FtpWalkPrune>,
+    pub order:      FtpWalkOrder,
+    // followed links are reported as the file or directory they point to
+    pub follow_symlinks:    FollowSymlinks,
+    // MLSD facts to list the directories with, see `FtpClient::list_path_with_facts`;
+    // `None` lists them like `list_path`, through the cache
+    pub facts:      Option<Vec<MlstFact>>,
+}
+
+impl std::fmt::Debug for FtpWalkOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FtpWalkOptions")
+            .field("max_depth", &self.max_depth)
+            .field("prune", &self.prune.is_some())
+            .field("order", &self.order)
+            .field("follow_symlinks", &self.follow_symlinks)
+            .field("facts", &self.facts)
+            .finish()
+    }
+}
+
+impl FtpClient {
+    pub fn walk<F>(&mut self, root: &str, visitor: F) -> FtpResult<()> 
+    where
+        F: FnMut(&FtpWalkEntry) -> FtpResult<FtpWalkControl>
+    {
+        self.walk_with(root, &FtpWalkOptions::default(), visitor)
+    }
+
+    pub fn walk_with<F>(&mut self, root: &str, options: &FtpWalkOptions, mut visitor: F) -> FtpResult<()> 
+    where
+        F: FnMut(&FtpWalkEntry) -> FtpResult<FtpWalkControl>
+    {
+        let mut pending = VecDeque::from(vec![(root.to_string(), 0)]);
+        // `unique` facts and paths of the directories walked so far, and the
+        // targets of the links followed
+        let mut visited = HashSet::new();
+        let mut visited_paths = HashSet::new();
+
+        while let Some((dir, depth)) = pending.pop_front() {
+            let list = match options.facts.as_deref() {
+                Some(facts) => self.list_path_with_facts(&dir, facts)?,
+                None => self.list_path(&dir)?,
+            };
+            if let Some(unique) = list.current.as_ref().and_then(|current| current.unique.clone()) {
+                // reached again through a link
+                if !visited.insert(unique) && options.follow_symlinks == FollowSymlinks::Safe {
+                    continue;
+                }
+            }
+            if dir.starts_with('/') {
+                visited_paths.insert(resolve_dot_segments(&dir));
+            }
+
+            let mut subdirs = vec![];
+
+            for item in list.items {
+                let mut entry = FtpWalkEntry {
+                    path: join_path(&dir, &item.name),
+                    depth: depth + 1,
+                    item,
+                };
+
+                if entry.item.ty.is_symlink() {
+                    let follow = match options.follow_symlinks {
+                        FollowSymlinks::Never => false,
+                        FollowSymlinks::Always => true,
+                        FollowSymlinks::Safe => {
+                            let unique = entry.item.unique.as_ref();
+                            let target = link_target_path(&dir, &entry.item);
+                            (unique.is_some() || target.is_some())
+                                && !unique.is_some_and(|unique| visited.contains(unique))
+                                && target.is_none_or(|target| visited_paths.insert(target))
+                        },
+                    };
+                    if follow {
+                        entry.item.ty = if self.is_dir_path(&entry.path)? { FtpItemType::Dir } else { FtpItemType::File };
+                    }
+                }
+
+                match visitor(&entry)? {
+                    FtpWalkControl::Continue => {
+                        let descend = entry.item.ty == FtpItemType::Dir
+                            && options.max_depth.is_none_or(|max_depth| entry.depth < max_depth)
+                            && !options.prune.as_ref().is_some_and(|prune| prune(&entry));
+                        if descend {
+                            subdirs.push((entry.path, entry.depth));
+                        }
+                    },
+                    FtpWalkControl::SkipDir => {},
+                    FtpWalkControl::Stop => {
+                        return Ok(());
+                    },
+                }
+            }
+
+            match options.order {
+                FtpWalkOrder::DepthFirst => {
+                    for subdir in subdirs.into_iter().rev() {
+                        pending.push_front(subdir);
+                    }
+                },
+                FtpWalkOrder::BreadthFirst => pending.extend(subdirs),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Probes with CWD, which is the only portable way to tell where a link points.
+    fn is_dir_path(&mut self, path: &str) -> FtpResult<bool> {
+        let cwd = self.pwd()?;
+        match self.chdir(path) {
+            Ok(()) => self.chdir(&cwd).map(|_| true),
+            Err(FtpError::UnexpectedResponse(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// Where a link listed in `dir` points, when the server reports its target.
+fn link_target_path(dir: &str, item: &FtpItem) -> Option<String> {
+    let target = item.link_target.as_deref()?;
+    if target.starts_with('/') {
+        Some(resolve_dot_segments(target))
+    } else if dir.starts_with('/') {
+        Some(resolve_dot_segments(&join_path(dir, target)))
+    } else {
+        None
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct FtpDirSizeOptions {
+    pub max_depth:  Option<usize>,
+    pub exclude:    Vec<String>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct FtpDirSize {
+    pub total:      u64,
+    pub subdirs:    BTreeMap<String, u64>,
+}
+
+impl FtpClient {
+    pub fn dir_size(&mut self, path: &str) -> FtpResult<FtpDirSize> {
+        self.dir_size_with(path, &FtpDirSizeOptions::default())
+    }
+
+    pub fn dir_size_with(&mut self, path: &str, options: &FtpDirSizeOptions) -> FtpResult<FtpDirSize> {
+        let mut result = FtpDirSize::default();
+
+        self.walk(path, |entry| {
+            if wildcard_match_any(&options.exclude, &entry.item.name) {
+                return Ok(FtpWalkControl::SkipDir);
+            }
+
+            if entry.item.ty.is_dir() {
+                if entry.depth == 1 {
+                    result.subdirs.insert(entry.item.name.clone(), 0);
+                }
+
+                return match options.max_depth {
+                    Some(max_depth) if entry.depth >= max_depth => Ok(FtpWalkControl::SkipDir),
+                    _ => Ok(FtpWalkControl::Continue),
+                };
+            }
+
+            let size = entry.item.size.unwrap_or(0);
+            result.total += size;
+
+            if entry.depth > 1 {
+                let top_level = entry.path[path.len()..]
+                    .trim_start_matches('/')
+                    .split('/')
+                    .next()
+                    .unwrap_or_default();
+
+                if let Some(subdir_size) = result.subdirs.get_mut(top_level) {
+                    *subdir_size += size;
+                }
+            }
+
+            Ok(FtpWalkControl::Continue)
+        })?;
+
+        Ok(result)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct FtpFindOptions {
+    pub names:          Vec<String>,
+    pub ty:             Option<FtpItemType>,
+    pub min_size:       Option<u64>,
+    pub max_size:       Option<u64>,
+    pub modified_since: Option<NaiveDateTime>,
+    pub modified_before:    Option<NaiveDateTime>,
+    pub max_depth:      Option<usize>,
+    pub limit:          Option<usize>,
+}
+
+impl FtpFindOptions {
+    pub fn is_match(&self, item: &FtpItem) -> bool {
+        if !self.names.is_empty() && !wildcard_match_any(&self.names, &item.name) {
+            return false;
+        }
+
+        if let Some(ty) = self.ty {
+            if item.ty != ty {
+                return false;
+            }
+        }
+
+        if self.min_size.is_some() || self.max_size.is_some() {
+            let size = match item.size {
+                Some(size) => size,
+                None => return false,
+            };
+            if self.min_size.map_or(false, |min_size| size < min_size) 
+                || self.max_size.map_or(false, |max_size| size > max_size) {
+                return false;
+            }
+        }
+
+        if self.modified_since.is_some() || self.modified_before.is_some() {
+            let modified = match item.modified {
+                Some(modified) => modified,
+                None => return false,
+            };
+            if self.modified_since.map_or(false, |since| modified < since) 
+                || self.modified_before.map_or(false, |before| modified >= before) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl FtpClient {
+    pub fn find(&mut self, root: &str, options: &FtpFindOptions) -> FtpResult<Vec<FtpWalkEntry>> {
+        let mut result = vec![];
+
+        if options.limit == Some(0) {
+            return Ok(result);
+        }
+
+        self.walk(root, |entry| {
+            if options.is_match(&entry.item) {
+                result.push(entry.clone());
+                if options.limit.map_or(false, |limit| result.len() >= limit) {
+                    return Ok(FtpWalkControl::Stop);
+                }
+            }
+
+            match options.max_depth {
+                Some(max_depth) if entry.depth >= max_depth => Ok(FtpWalkControl::SkipDir),
+                _ => Ok(FtpWalkControl::Continue),
+            }
+        })?;
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::client::FtpClientListMode;
+    use crate::mock::support::*;
+
+    #[test]
+    fn test_walk_with() {
+        let server = server();
+        server.add_dir("/data/sub");
+        server.add_file("/data/sub/b.txt", "b");
+        let mut client = FtpClient::new(settings(&server));
+
+        let mut walk = |options: &FtpWalkOptions| {
+            let mut paths = vec![];
+            client.walk_with("/", options, |entry| {
+                paths.push(entry.path.clone());
+                Ok(FtpWalkControl::Continue)
+            }).unwrap();
+            paths
+        };
+
+        let options = FtpWalkOptions { order: FtpWalkOrder::BreadthFirst, ..FtpWalkOptions::default() };
+        assert_eq!(walk(&options), vec!["/data", "/readme", "/data/sub", "/data/a.txt", "/data/sub/b.txt"]);
+
+        let options = FtpWalkOptions { max_depth: Some(1), ..FtpWalkOptions::default() };
+        assert_eq!(walk(&options), vec!["/data", "/readme"]);
+
+        let options = FtpWalkOptions { prune: Some(Arc::new(|entry| entry.item.name == "sub")), ..FtpWalkOptions::default() };
+        assert_eq!(walk(&options), vec!["/data", "/readme", "/data/sub", "/data/a.txt"]);
+    }
+
+    #[test]
+    fn test_walk_symlinks() {
+        let (mut client, state) = fake_client(Some(FtpClientListMode::Mlsd));
+        // every directory lists the same, so `loop` leads back into the root
+        state.lock().unwrap().cwd = "/".to_string();
+        state.lock().unwrap().mlsd = vec![
+            "type=cdir;unique=1; /".to_string(),
+            "type=file;size=1; a.txt".to_string(),
+            "type=OS.unix=symlink;unique=2; loop".to_string(),
+            "type=OS.unix=slink:/; up".to_string(),
+        ];
+
+        let mut walk = |options: &FtpWalkOptions| {
+            let mut entries = vec![];
+            client.walk_with("/", options, |entry| {
+                entries.push((entry.path.clone(), entry.item.ty));
+                Ok(FtpWalkControl::Continue)
+            }).unwrap();
+            entries
+        };
+
+        let options = FtpWalkOptions { follow_symlinks: FollowSymlinks::Safe, ..FtpWalkOptions::default() };
+        assert_eq!(walk(&options), vec![
+            ("/a.txt".to_string(), FtpItemType::File),
+            ("/loop".to_string(), FtpItemType::Dir),
+            ("/up".to_string(), FtpItemType::Symlink),
+        ]);
+
+        let options = FtpWalkOptions { follow_symlinks: FollowSymlinks::Always, max_depth: Some(2), ..FtpWalkOptions::default() };
+        let paths: Vec<_> = walk(&options).into_iter().map(|(path, _)| path).collect();
+        assert_eq!(paths, vec![
+            "/a.txt", "/loop", "/up",
+            "/loop/a.txt", "/loop/loop", "/loop/up",
+            "/up/a.txt", "/up/loop", "/up/up",
+        ]);
+    }
+}
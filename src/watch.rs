@@ -0,0 +1,101 @@
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::{
+    path::join_path,
+    queue::{FtpTransferJob, FtpTransferQueue},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn remote_path_for(local_dir: &Path, remote_dir: &str, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(local_dir).ok()?;
+    relative.components().try_fold(remote_dir.to_string(), |remote, component| {
+        Some(join_path(&remote, component.as_os_str().to_str()?))
+    })
+}
+
+// "Hot folder": files created or modified under `local_dir` are queued for upload
+// to the same relative path under `remote_dir` once they have been quiet for
+// `debounce`.
+pub struct FtpHotFolder {
+    _watcher: RecommendedWatcher,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for FtpHotFolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FtpHotFolder").finish()
+    }
+}
+
+impl FtpHotFolder {
+    pub fn start<P: AsRef<Path>>(queue: Arc<FtpTransferQueue>, local_dir: P, remote_dir: &str, debounce: Duration) -> notify::Result<Self> {
+        let local_dir = local_dir.as_ref().canonicalize()?;
+        let remote_dir = remote_dir.to_string();
+
+        let (sender, receiver) = mpsc::channel::<PathBuf>();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            if let Ok(event) = event {
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    for path in event.paths {
+                        let _ = sender.send(path);
+                    }
+                }
+            }
+        })?;
+        watcher.watch(&local_dir, RecursiveMode::Recursive)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let handle = thread::spawn(move || {
+            let mut changed: HashMap<PathBuf, Instant> = HashMap::new();
+
+            while !thread_stop.load(Ordering::SeqCst) {
+                match receiver.recv_timeout(debounce.min(Duration::from_millis(100))) {
+                    Ok(path) => {
+                        changed.insert(path, Instant::now());
+                    },
+                    Err(RecvTimeoutError::Timeout) => {},
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+
+                let ready: Vec<PathBuf> = changed.iter()
+                    .filter(|(_, at)| at.elapsed() >= debounce)
+                    .map(|(path, _)| path.clone())
+                    .collect();
+
+                for path in ready {
+                    changed.remove(&path);
+                    if !path.is_file() {
+                        continue;
+                    }
+                    if let Some(remote) = remote_path_for(&local_dir, &remote_dir, &path) {
+                        queue.push(FtpTransferJob::Upload { local: path, remote });
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            _watcher: watcher,
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for FtpHotFolder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}